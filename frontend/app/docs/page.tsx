@@ -129,162 +129,162 @@ event: content
 data: {"type":"content","content":[{"type":"text","text":"<thinking>\n"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"Okay"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"Okay"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":","}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":","}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" let"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" let"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"'s"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"'s"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" see"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" see"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"."}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"."}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" The"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" The"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" user"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" user"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" is"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" is"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" asking"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" asking"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" how"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" how"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" many"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" many"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" times"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" times"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" the"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" the"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" letter"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" letter"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" \""}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" \""}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"r"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"r"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"\""}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"\""}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" appears"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" appears"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" in"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" in"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" the"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" the"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" word"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" word"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" \""}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" \""}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"st"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"st"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"raw"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"raw"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"berry"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"berry"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"\"."}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"\"."}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" Al"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" Al"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"right"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"right"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":","}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":","}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" first"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" first"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":","}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":","}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" I"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" I"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" need"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" need"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" to"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" to"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" make"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" make"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" sure"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" sure"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" I"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" I"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" spell"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" spell"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" the"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" the"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" word"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" word"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" correctly"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" correctly"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"."}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"."}]}
 
 [... SSE events omitted for brevity ...]
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" Therefore"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" Therefore"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":","}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":","}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" the"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" the"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" correct"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" correct"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" answer"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" answer"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" is"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" is"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":" "}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":" "}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"3"}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"3"}]}
 
 event: content
-data: {"type":"content","content":[{"type":"text_delta","text":"."}]}
+data: {"type":"content","content":[{"type":"thinking_delta","text":"."}]}
 
 event: content
 data: {"type":"content","content":[{"type":"text","text":"\n</thinking>"}]}
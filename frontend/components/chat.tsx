@@ -512,8 +512,8 @@ export function Chat({ selectedModel, onModelChange, apiTokens }: ChatProps) {
                   return [...prev, { ...currentMessageRef.current! }]
                 })
               }
-            } else if (content.type === "text_delta") {
-              if (isThinking) {
+            } else if (content.type === "text_delta" || content.type === "thinking_delta") {
+              if (content.type === "thinking_delta") {
                 currentMessageRef.current.thinking += content.text
               } else {
                 currentMessageRef.current.content += content.text
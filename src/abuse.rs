@@ -0,0 +1,190 @@
+//! Per-client request accounting for spotting abusive clients.
+//!
+//! Every request is attributed to a client, identified by a hash of the
+//! credential it sent or else by its IP address, and logged with its
+//! request and response sizes and token counts. A sliding window counts
+//! each client's recent requests; a client over `abuse.abuse_threshold` is
+//! logged at warning level and, if configured, rejected with 429.
+//! Credentials are only ever logged as hashes.
+
+use crate::models::CombinedUsage;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Headers whose value identifies a client, in order of preference.
+const CREDENTIAL_HEADERS: &[&str] = &[
+    "authorization",
+    "x-deepseek-api-token",
+    "x-gemini-api-token",
+    "x-anthropic-api-token",
+];
+
+/// Hex characters of the credential hash kept in client identifiers.
+const CLIENT_HASH_CHARS: usize = 16;
+
+/// Requests between sweeps that forget clients idle for a whole window.
+const SWEEP_INTERVAL: usize = 1024;
+
+/// Sliding-window request counter per client.
+#[derive(Debug)]
+pub struct AbuseMonitor {
+    window: Duration,
+    clients: Mutex<HashMap<String, VecDeque<Instant>>>,
+    requests: AtomicUsize,
+}
+
+impl AbuseMonitor {
+    /// Creates a monitor counting requests over `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            clients: Mutex::new(HashMap::new()),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a request from `client`.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The client's requests within the window, including this one
+    pub fn hit(&self, client: &str) -> usize {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if self.requests.fetch_add(1, Ordering::Relaxed).is_multiple_of(SWEEP_INTERVAL) {
+            clients.retain(|_, hits| hits.back().is_some_and(|&last| now.duration_since(last) < self.window));
+        }
+
+        let hits = clients.entry(client.to_string()).or_default();
+        while hits.front().is_some_and(|&first| now.duration_since(first) >= self.window) {
+            hits.pop_front();
+        }
+        hits.push_back(now);
+        hits.len()
+    }
+}
+
+/// Hashes the credential a request carries, in order of preference.
+///
+/// # Arguments
+///
+/// * `headers` - The request headers
+///
+/// # Returns
+///
+/// * `Option<String>` - Hex SHA-256 of the credential, or None if the
+///   request carries none
+pub fn credential_hash(headers: &axum::http::HeaderMap) -> Option<String> {
+    CREDENTIAL_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .map(|value| value.as_bytes())
+        .filter(|value| !value.is_empty())
+        .map(|credential| format!("{:x}", Sha256::digest(credential)))
+}
+
+/// Identifies the client behind a request.
+///
+/// # Arguments
+///
+/// * `headers` - The request headers
+/// * `peer` - The address the connection came from, if known
+///
+/// # Returns
+///
+/// * `String` - `key:<hash>` for a request carrying a credential, otherwise
+///   `ip:<address>`, or `unknown`
+pub fn client_id(headers: &axum::http::HeaderMap, peer: Option<IpAddr>) -> String {
+    match (credential_hash(headers), peer) {
+        (Some(hash), _) => format!("key:{}", &hash[..CLIENT_HASH_CHARS]),
+        (None, Some(peer)) => format!("ip:{}", peer),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Token counts of one HTTP request, filled in as its usage is recorded.
+///
+/// Batches add every item's usage to the same tally.
+#[derive(Debug, Default)]
+pub struct RequestTally {
+    input_tokens: AtomicU64,
+    output_tokens: AtomicU64,
+}
+
+impl RequestTally {
+    /// Adds a chat request's input and output tokens to the tally.
+    pub fn add(&self, usage: &CombinedUsage) {
+        self.input_tokens
+            .fetch_add(u64::from(usage.total_input_tokens()), Ordering::Relaxed);
+        self.output_tokens
+            .fetch_add(u64::from(usage.total_output_tokens()), Ordering::Relaxed);
+    }
+
+    /// Returns the (input, output) tokens recorded so far.
+    pub fn tokens(&self) -> (u64, u64) {
+        (
+            self.input_tokens.load(Ordering::Relaxed),
+            self.output_tokens.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Extracts the request's tally, if `track_client` is accounting for it.
+///
+/// Accounting is off when `abuse.enabled` is false, so handlers get None.
+#[derive(Debug, Clone, Default)]
+pub struct ClientTally(pub Option<Arc<RequestTally>>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientTally {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientTally(parts.extensions.get::<Arc<RequestTally>>().cloned()))
+    }
+}
+
+/// Accounting of one HTTP request, logged when dropped.
+///
+/// The middleware moves it into the response body, so it is dropped, and
+/// logged, once the body is fully sent or the client goes away.
+#[derive(Debug)]
+pub struct RequestLog {
+    /// Client identifier from `client_id`.
+    pub client: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    /// Size of the request body, from its `Content-Length`.
+    pub request_bytes: u64,
+    /// Bytes of response body sent, including every streamed event.
+    pub response_bytes: u64,
+    pub tally: Arc<RequestTally>,
+}
+
+impl Drop for RequestLog {
+    fn drop(&mut self) {
+        let (input_tokens, output_tokens) = self.tally.tokens();
+        tracing::info!(
+            client = %self.client,
+            method = %self.method,
+            path = %self.path,
+            status = self.status,
+            request_bytes = self.request_bytes,
+            response_bytes = self.response_bytes,
+            input_tokens,
+            output_tokens,
+            "Request accounted"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! In-memory cache of DeepSeek reasoning results.
+//!
+//! Repeated identical prompts (common in evaluation harnesses) would
+//! otherwise re-run the reasoning phase every time. Entries are keyed on
+//! the reasoner's input and evicted least-recently-used once the cache is
+//! full, or once they outlive the configured TTL.
+
+use crate::{
+    clients::deepseek::{self, DeepSeekResponse},
+    models::{ApiConfig, Message},
+};
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::{
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A successful reasoning result that can be reused.
+#[derive(Debug, Clone)]
+pub struct CachedReasoning {
+    pub response: DeepSeekResponse,
+    pub reasoning: Option<String>,
+}
+
+/// Thread-safe LRU cache of reasoning results.
+#[derive(Debug)]
+pub struct ReasoningCache {
+    entries: Mutex<LruCache<String, (Instant, CachedReasoning)>>,
+    ttl: Option<Duration>,
+}
+
+impl ReasoningCache {
+    /// Creates a cache holding at most `capacity` entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Maximum number of entries before LRU eviction
+    /// * `ttl` - How long an entry stays valid, or None for no expiry
+    pub fn new(capacity: NonZeroUsize, ttl: Option<Duration>) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    /// Computes the cache key for a reasoning call.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The messages sent to the reasoner, including the system prompt
+    /// * `config` - The DeepSeek request configuration
+    ///
+    /// # Returns
+    ///
+    /// * `String` - Lowercase hex SHA-256 of the messages, config, and resolved model
+    pub fn key(messages: &[Message], config: &ApiConfig) -> String {
+        let model = config
+            .body
+            .get("model")
+            .and_then(|m| m.as_str())
+            .unwrap_or(deepseek::DEFAULT_MODEL);
+        let input = serde_json::json!({
+            "messages": messages,
+            "deepseek_config": config,
+            "model": model,
+        });
+
+        format!("{:x}", Sha256::digest(serde_json::to_vec(&input).unwrap_or_default()))
+    }
+
+    /// Returns the cached result for `key`, if present and not expired.
+    pub fn get(&self, key: &str) -> Option<CachedReasoning> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (inserted, cached) = entries.get(key)?;
+        if self.ttl.is_some_and(|ttl| inserted.elapsed() > ttl) {
+            entries.pop(key);
+            return None;
+        }
+        Some(cached.clone())
+    }
+
+    /// Stores a successful reasoning result under `key`.
+    pub fn insert(&self, key: String, cached: CachedReasoning) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.put(key, (Instant::now(), cached));
+    }
+}
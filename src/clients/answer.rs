@@ -0,0 +1,322 @@
+//! Provider-neutral access to the answer model.
+//!
+//! The answer phase can be served by Gemini or Anthropic. This module wraps
+//! both clients behind a single `AnswerClient` so handlers can run the
+//! post-reasoning call without caring which provider was selected. In mock
+//! mode the client serves canned output in place of either provider.
+
+use super::{anthropic, AnthropicClient, GeminiClient, MockProvider};
+use crate::{
+    config::{AnswerProvider, Config},
+    error::Result,
+    models::{ApiConfig, Citation, FinishReason, Message},
+};
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+
+/// Client for whichever provider is configured to produce the final answer.
+#[derive(Debug)]
+pub enum AnswerClient {
+    Gemini(GeminiClient),
+    Anthropic(AnthropicClient),
+    /// Canned output standing in for the given provider.
+    Mock(MockProvider, AnswerProvider),
+}
+
+/// Token usage reported by an answer provider.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnswerUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cached_input_tokens: u32,
+    /// Input tokens written to the provider's prompt cache, billed at the
+    /// cache write price.
+    pub cache_creation_input_tokens: u32,
+}
+
+impl std::ops::Add for AnswerUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            input_tokens: self.input_tokens + other.input_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+            cached_input_tokens: self.cached_input_tokens + other.cached_input_tokens,
+            cache_creation_input_tokens: self.cache_creation_input_tokens + other.cache_creation_input_tokens,
+        }
+    }
+}
+
+/// Complete, non-streaming answer from an answer provider.
+#[derive(Debug, Clone)]
+pub struct AnswerResponse {
+    /// Provider that served the answer.
+    pub provider: AnswerProvider,
+    pub text: String,
+    /// Text of each part of the answer, when the provider returned more
+    /// than one. Concatenated, they form `text`.
+    pub parts: Vec<String>,
+    /// Texts of additional candidates, when several were requested.
+    pub alternatives: Vec<String>,
+    /// Sources the provider cited for the answer.
+    pub citations: Vec<Citation>,
+    pub finish_reason: Option<FinishReason>,
+    pub usage: AnswerUsage,
+    /// Times the call was repeated after an empty answer.
+    pub retries: u32,
+    /// Raw provider response, used for verbose output
+    pub raw: serde_json::Value,
+}
+
+/// A single chunk of a streaming answer.
+#[derive(Debug, Clone, Default)]
+pub struct AnswerChunk {
+    pub text: Option<String>,
+    /// Sources cited in this chunk.
+    pub citations: Vec<Citation>,
+    pub finish_reason: Option<FinishReason>,
+    pub usage: Option<AnswerUsage>,
+}
+
+impl AnswerClient {
+    /// Creates a client for the given answer provider, using the base URL
+    /// configured for it, or a mock of it when `provider_mode` is `mock`.
+    ///
+    /// `http` is the provider's shared HTTP client.
+    pub fn new(
+        provider: AnswerProvider,
+        api_token: String,
+        config: &Config,
+        http: reqwest::Client,
+    ) -> Self {
+        if let Some(mock) = MockProvider::for_config(config) {
+            return AnswerClient::Mock(mock, provider);
+        }
+        match provider {
+            AnswerProvider::Gemini => AnswerClient::Gemini(
+                GeminiClient::with_http_client(api_token, http)
+                    .with_base_url(config.gemini.base_url.as_deref()),
+            ),
+            AnswerProvider::Anthropic => AnswerClient::Anthropic(
+                AnthropicClient::with_http_client(api_token, http)
+                    .with_base_url(config.anthropic.base_url.as_deref()),
+            ),
+        }
+    }
+
+    /// Returns the provider backing this client.
+    pub fn provider(&self) -> AnswerProvider {
+        match self {
+            AnswerClient::Gemini(_) => AnswerProvider::Gemini,
+            AnswerClient::Anthropic(_) => AnswerProvider::Anthropic,
+            AnswerClient::Mock(_, provider) => *provider,
+        }
+    }
+
+    /// Sends a non-streaming chat request to the answer provider.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages for the conversation
+    /// * `system` - Optional system prompt, for providers that take it separately
+    /// * `config` - Configuration options for the request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<AnswerResponse>` - The normalized answer on success
+    pub async fn chat(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &ApiConfig,
+    ) -> Result<AnswerResponse> {
+        match self {
+            AnswerClient::Gemini(client) => {
+                let response = client.chat(messages, config).await?;
+                let usage = response.usage.as_ref();
+                Ok(AnswerResponse {
+                    provider: AnswerProvider::Gemini,
+                    text: response.text(),
+                    parts: response.parts(),
+                    alternatives: response.alternatives(),
+                    citations: response.citations.clone(),
+                    finish_reason: response
+                        .choices
+                        .first()
+                        .and_then(|c| c.finish_reason.as_deref())
+                        .map(FinishReason::from_gemini),
+                    // Gemini's completion tokens already cover every candidate
+                    usage: AnswerUsage {
+                        input_tokens: usage.map(|u| u.prompt_tokens).unwrap_or(0),
+                        output_tokens: usage.map(|u| u.completion_tokens).unwrap_or(0),
+                        cached_input_tokens: 0,
+                        cache_creation_input_tokens: 0,
+                    },
+                    retries: 0,
+                    raw: serde_json::to_value(&response).unwrap_or_default(),
+                })
+            }
+            AnswerClient::Anthropic(client) => {
+                let response = client.chat(messages, system, config).await?;
+                Ok(AnswerResponse {
+                    provider: AnswerProvider::Anthropic,
+                    text: response.text(),
+                    parts: Vec::new(),
+                    alternatives: Vec::new(),
+                    citations: Vec::new(),
+                    finish_reason: response
+                        .stop_reason
+                        .as_deref()
+                        .map(FinishReason::from_anthropic),
+                    usage: AnswerUsage {
+                        input_tokens: response.usage.input_tokens,
+                        output_tokens: response.usage.output_tokens,
+                        cached_input_tokens: response.usage.cache_read_input_tokens,
+                        cache_creation_input_tokens: response.usage.cache_creation_input_tokens,
+                    },
+                    retries: 0,
+                    raw: serde_json::to_value(&response).unwrap_or_default(),
+                })
+            }
+            AnswerClient::Mock(mock, provider) => Ok(mock.answer_response(*provider, &messages)),
+        }
+    }
+
+    /// Sends a streaming chat request to the answer provider.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages for the conversation
+    /// * `system` - Optional system prompt, for providers that take it separately
+    /// * `config` - Configuration options for the request
+    ///
+    /// # Returns
+    ///
+    /// * `Pin<Box<dyn Stream<Item = Result<AnswerChunk>> + Send>>` - A stream of normalized chunks
+    pub fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<AnswerChunk>> + Send>> {
+        match self {
+            AnswerClient::Gemini(client) => {
+                let stream = client.chat_stream(messages, config);
+                Box::pin(stream.map(|result| {
+                    result.map(|response| {
+                        let choice = response.choices.first();
+                        AnswerChunk {
+                            text: choice.and_then(|c| c.delta.content.clone()),
+                            citations: response.citations,
+                            finish_reason: choice
+                                .and_then(|c| c.finish_reason.as_deref())
+                                .map(FinishReason::from_gemini),
+                            usage: response.usage.map(|u| AnswerUsage {
+                                input_tokens: u.prompt_tokens,
+                                output_tokens: u.completion_tokens,
+                                cached_input_tokens: 0,
+                                cache_creation_input_tokens: 0,
+                            }),
+                        }
+                    })
+                }))
+            }
+            AnswerClient::Anthropic(client) => {
+                let mut stream = client.chat_stream(messages, system, config);
+                Box::pin(async_stream::try_stream! {
+                    // Anthropic reports input tokens at the start and output tokens at the end
+                    let mut usage = AnswerUsage::default();
+                    while let Some(event) = stream.next().await {
+                        match event? {
+                            anthropic::StreamEvent::MessageStart { message } => {
+                                usage.input_tokens = message.usage.input_tokens;
+                                usage.cached_input_tokens = message.usage.cache_read_input_tokens;
+                                usage.cache_creation_input_tokens = message.usage.cache_creation_input_tokens;
+                            }
+                            anthropic::StreamEvent::ContentBlockDelta { delta, .. } => {
+                                yield AnswerChunk {
+                                    text: Some(delta.text),
+                                    ..Default::default()
+                                };
+                            }
+                            anthropic::StreamEvent::MessageDelta { delta, usage: delta_usage } => {
+                                if let Some(delta_usage) = delta_usage {
+                                    usage.output_tokens = delta_usage.output_tokens;
+                                }
+                                yield AnswerChunk {
+                                    text: None,
+                                    citations: Vec::new(),
+                                    finish_reason: delta.stop_reason.as_deref().map(FinishReason::from_anthropic),
+                                    usage: Some(usage),
+                                };
+                            }
+                            _ => {} // Other events carry no content
+                        }
+                    }
+                })
+            }
+            AnswerClient::Mock(mock, _) => mock.answer_stream(&messages),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, user_message};
+    use axum::{routing::post, Router};
+
+    const USAGE: &str = r#""usage":{"input_tokens":100,"output_tokens":20,"cache_creation_input_tokens":300,"cache_read_input_tokens":400}"#;
+
+    async fn anthropic_client(body: String) -> AnswerClient {
+        let url = serve(Router::new().route("/v1/messages", post(move || async move { body }))).await;
+        let mut config = Config::default();
+        config.anthropic.base_url = Some(url);
+        AnswerClient::new(AnswerProvider::Anthropic, "token".to_string(), &config, reqwest::Client::new())
+    }
+
+    #[tokio::test]
+    async fn anthropic_usage_keeps_cache_reads_and_writes() {
+        let body = format!(
+            r#"{{"id":"msg_1","type":"message","role":"assistant","content":[{{"type":"text","text":"hi"}}],"model":"claude-3-5-sonnet-20241022","stop_reason":"end_turn","stop_sequence":null,{}}}"#,
+            USAGE
+        );
+        let client = anthropic_client(body).await;
+
+        let response = client
+            .chat(user_message("hi"), None, &ApiConfig::default())
+            .await
+            .unwrap();
+
+        assert_eq!(response.usage.input_tokens, 100);
+        assert_eq!(response.usage.output_tokens, 20);
+        assert_eq!(response.usage.cached_input_tokens, 400);
+        assert_eq!(response.usage.cache_creation_input_tokens, 300);
+    }
+
+    #[tokio::test]
+    async fn anthropic_stream_usage_keeps_cache_reads_and_writes() {
+        let body = format!(
+            concat!(
+                "event: message_start\n",
+                r#"data: {{"type":"message_start","message":{{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-3-5-sonnet-20241022","stop_reason":null,"stop_sequence":null,{}}}}}"#,
+                "\n\nevent: message_delta\n",
+                r#"data: {{"type":"message_delta","delta":{{"stop_reason":"end_turn","stop_sequence":null}},"usage":{{"output_tokens":20}}}}"#,
+                "\n\n"
+            ),
+            USAGE
+        );
+        let client = anthropic_client(body).await;
+
+        let usage = client
+            .chat_stream(user_message("hi"), None, &ApiConfig::default())
+            .filter_map(|chunk| async move { chunk.unwrap().usage })
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].cached_input_tokens, 400);
+        assert_eq!(usage[0].cache_creation_input_tokens, 300);
+        assert_eq!(usage[0].output_tokens, 20);
+    }
+}
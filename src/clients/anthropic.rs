@@ -0,0 +1,532 @@
+//! Anthropic API client implementation for interacting with Claude models.
+//!
+//! This module provides a client implementation for making requests to Anthropic's
+//! Messages API. It supports both streaming and non-streaming interactions and is
+//! used as an alternative answer provider to Gemini.
+
+use crate::{
+    error::{ApiError, Result},
+    models::{ApiConfig, ContentPart, Message, MessageContent, Role},
+};
+use futures::{Stream, StreamExt};
+use reqwest::{header::HeaderMap, Client};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, pin::Pin};
+
+/// Base URL of Anthropic's public API.
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+pub(crate) const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20241022";
+
+/// Output token limit used when the request doesn't set `max_tokens`.
+pub(crate) const DEFAULT_MAX_OUTPUT_TOKENS: u32 = 8192;
+
+/// Client for interacting with Anthropic's Claude models.
+///
+/// This client handles authentication, request construction, and response parsing
+/// for both streaming and non-streaming interactions with the Messages API.
+///
+/// # Examples
+///
+/// ```no_run
+/// use deepclaude::clients::AnthropicClient;
+///
+/// let client = AnthropicClient::with_http_client("api_token".to_string(), reqwest::Client::new());
+/// ```
+#[derive(Debug)]
+pub struct AnthropicClient {
+    pub(crate) client: Client,
+    api_token: String,
+    base_url: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnthropicResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub response_type: String,
+    pub role: String,
+    pub content: Vec<ContentBlock>,
+    pub model: String,
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+    pub usage: Usage,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ContentBlock {
+    #[serde(rename = "type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub input_tokens: u32,
+    #[serde(default)]
+    pub output_tokens: u32,
+    #[serde(default)]
+    pub cache_creation_input_tokens: u32,
+    #[serde(default)]
+    pub cache_read_input_tokens: u32,
+}
+
+// Streaming response types
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type")]
+pub enum StreamEvent {
+    #[serde(rename = "message_start")]
+    MessageStart { message: AnthropicResponse },
+    #[serde(rename = "content_block_start")]
+    ContentBlockStart { index: usize, content_block: ContentBlock },
+    #[serde(rename = "content_block_delta")]
+    ContentBlockDelta { index: usize, delta: ContentDelta },
+    #[serde(rename = "content_block_stop")]
+    ContentBlockStop { index: usize },
+    #[serde(rename = "message_delta")]
+    MessageDelta { delta: MessageDelta, usage: Option<Usage> },
+    #[serde(rename = "message_stop")]
+    MessageStop,
+    #[serde(rename = "ping")]
+    Ping,
+    /// A failure reported after the stream started, such as overload.
+    #[serde(rename = "error")]
+    Error { error: StreamError },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StreamError {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ContentDelta {
+    #[serde(rename = "type")]
+    pub delta_type: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageDelta {
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AnthropicRequest {
+    messages: Vec<serde_json::Value>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(flatten)]
+    additional_params: serde_json::Value,
+}
+
+impl AnthropicResponse {
+    /// Returns the concatenated text of all text content blocks.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter(|block| block.content_type == "text")
+            .map(|block| block.text.as_str())
+            .collect()
+    }
+}
+
+impl AnthropicClient {
+    /// Creates a client that sends requests through `client`, such as one
+    /// with a pinned certificate.
+    pub fn with_http_client(api_token: String, client: Client) -> Self {
+        Self {
+            client,
+            api_token,
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Sends requests to `base_url` instead of Anthropic's public API, such
+    /// as a proxy. None keeps the default.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> Self {
+        if let Some(base_url) = base_url {
+            self.base_url = base_url.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
+    /// Returns the messages endpoint under the base URL.
+    fn messages_url(&self) -> String {
+        format!("{}/v1/messages", self.base_url)
+    }
+
+    /// Builds the HTTP headers required for Anthropic API requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `custom_headers` - Optional additional headers to include in requests
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HeaderMap>` - The constructed headers on success, or an error if header construction fails
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::Internal` if:
+    /// - The API token is invalid
+    /// - Content-Type or version headers cannot be constructed
+    pub(crate) fn build_headers(&self, custom_headers: Option<&HashMap<String, String>>) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-api-key",
+            self.api_token
+                .parse()
+                .map_err(|e| ApiError::Internal {
+                    message: format!("Invalid API token: {}", e)
+                })?,
+        );
+        headers.insert(
+            "anthropic-version",
+            ANTHROPIC_VERSION
+                .parse()
+                .map_err(|e| ApiError::Internal {
+                    message: format!("Invalid anthropic version: {}", e)
+                })?,
+        );
+        headers.insert(
+            "Content-Type",
+            "application/json"
+                .parse()
+                .map_err(|e| ApiError::Internal {
+                    message: format!("Invalid content type: {}", e)
+                })?,
+        );
+
+        if let Some(custom) = custom_headers {
+            headers.extend(super::build_headers(custom)?);
+        }
+
+        Ok(headers)
+    }
+
+    /// Converts a message into the Anthropic Messages API format.
+    ///
+    /// Image parts are mapped to base64 or URL image sources.
+    fn convert_message(message: &Message) -> serde_json::Value {
+        let role = match message.role {
+            Role::Assistant => "assistant",
+            _ => "user",
+        };
+
+        let content = match &message.content {
+            MessageContent::Text(text) => serde_json::json!(text),
+            MessageContent::Parts(parts) => serde_json::Value::Array(
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        ContentPart::Text { text } => serde_json::json!({
+                            "type": "text",
+                            "text": text,
+                        }),
+                        ContentPart::Image { mime_type, data } => serde_json::json!({
+                            "type": "image",
+                            "source": { "type": "base64", "media_type": mime_type, "data": data },
+                        }),
+                        ContentPart::ImageUrl { url, .. } => serde_json::json!({
+                            "type": "image",
+                            "source": { "type": "url", "url": url },
+                        }),
+                    })
+                    .collect(),
+            ),
+        };
+
+        serde_json::json!({ "role": role, "content": content })
+    }
+
+    /// Constructs a request object for the Anthropic API.
+    ///
+    /// System messages are removed from the message list since Anthropic
+    /// takes the system prompt as a separate top-level field.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages to send to the model
+    /// * `system` - Optional system prompt
+    /// * `stream` - Whether to enable streaming mode
+    /// * `config` - Configuration options for the request
+    ///
+    /// # Returns
+    ///
+    /// An `AnthropicRequest` object configured with the provided parameters and defaults
+    pub(crate) fn build_request(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        stream: bool,
+        config: &ApiConfig,
+    ) -> AnthropicRequest {
+        let messages: Vec<serde_json::Value> = messages
+            .iter()
+            .filter(|msg| !matches!(msg.role, Role::System))
+            .map(Self::convert_message)
+            .collect();
+
+        let mut additional_params = serde_json::json!({
+            // Set defaults only if not provided in config
+            "model": config.body.get("model").unwrap_or(&serde_json::json!(DEFAULT_MODEL)),
+            "max_tokens": config.body.get("max_tokens").unwrap_or(&serde_json::json!(DEFAULT_MAX_OUTPUT_TOKENS)),
+        });
+
+        // Merge additional configuration from config.body while protecting critical fields
+        if let (serde_json::Value::Object(map), serde_json::Value::Object(body)) =
+            (&mut additional_params, &config.body)
+        {
+            for (key, value) in body {
+                if !matches!(key.as_str(), "stream" | "messages" | "system") {
+                    map.insert(key.clone(), value.clone());
+                }
+            }
+        }
+
+        AnthropicRequest {
+            messages,
+            stream,
+            system,
+            additional_params,
+        }
+    }
+
+    /// Sends a non-streaming chat request to the Anthropic API.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages for the conversation
+    /// * `system` - Optional system prompt
+    /// * `config` - Configuration options for the request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<AnthropicResponse>` - The model's response on success
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::AnthropicError` if:
+    /// - The API request fails
+    /// - The response status is not successful
+    /// - The response cannot be parsed
+    pub async fn chat(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &ApiConfig,
+    ) -> Result<AnthropicResponse> {
+        let headers = self.build_headers(Some(&config.headers))?;
+        let request = self.build_request(messages, system, false, config);
+
+        let response = self
+            .client
+            .post(self.messages_url())
+            .headers(headers)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| ApiError::AnthropicError {
+                message: format!("Request failed: {}", e),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })?;
+
+        if !response.status().is_success() {
+            let provider_request_id = super::provider_request_id(response.headers());
+            let error = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            let details = super::parse_error_body(&error);
+            return Err(ApiError::AnthropicError {
+                message: error,
+                type_: details.type_.unwrap_or_else(|| "api_error".to_string()),
+                param: details.param,
+                code: details.code,
+                provider_request_id
+            });
+        }
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ApiError::AnthropicError {
+                message: format!("Failed to read response: {}", e),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })?;
+
+        serde_json::from_str::<AnthropicResponse>(&super::decode_body("Anthropic", &body))
+            .map_err(|e| ApiError::AnthropicError {
+                message: format!("Failed to parse response: {}", e),
+                type_: "parse_error".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })
+    }
+
+    /// Sends a streaming chat request to the Anthropic API.
+    ///
+    /// Returns a stream that yields server-sent events as they arrive.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages for the conversation
+    /// * `system` - Optional system prompt
+    /// * `config` - Configuration options for the request
+    ///
+    /// # Returns
+    ///
+    /// * `Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>` - A stream of response events
+    ///
+    /// # Errors
+    ///
+    /// The stream may yield `ApiError::AnthropicError` if:
+    /// - The API request fails
+    /// - Stream processing encounters an error
+    /// - Anthropic sends an `error` event partway through
+    pub fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>> {
+        let headers = match self.build_headers(Some(&config.headers)) {
+            Ok(h) => h,
+            Err(e) => return Box::pin(futures::stream::once(async move { Err(e) })),
+        };
+
+        let request = self.build_request(messages, system, true, config);
+        let client = self.client.clone();
+        let url = self.messages_url();
+
+        Box::pin(async_stream::try_stream! {
+            let response = client
+                .post(url)
+                .headers(headers)
+                .json(&request)
+                .send()
+                .await
+                .map_err(|e| ApiError::AnthropicError {
+                    message: format!("Request failed: {}", e),
+                    type_: "request_failed".to_string(),
+                    param: None,
+                    code: None,
+                    provider_request_id: None
+                })?;
+
+            if !response.status().is_success() {
+                let provider_request_id = super::provider_request_id(response.headers());
+                let error = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "Unknown error".to_string());
+                let details = super::parse_error_body(&error);
+                Err(ApiError::AnthropicError {
+                    message: error,
+                    type_: details.type_.unwrap_or_else(|| "api_error".to_string()),
+                    param: details.param,
+                    code: details.code,
+                    provider_request_id
+                })?;
+                // The error above ends the stream
+                return;
+            }
+
+            let mut stream = response.bytes_stream();
+
+            // Buffer raw bytes so multi-byte characters split across chunks decode correctly
+            let mut data: Vec<u8> = Vec::new();
+
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(|e| ApiError::AnthropicError {
+                    message: format!("Stream error: {}", e),
+                    type_: "stream_error".to_string(),
+                    param: None,
+                    code: None,
+                    provider_request_id: None
+                })?;
+                data.extend_from_slice(&chunk);
+
+                let mut start = 0;
+                while let Some(end) = data[start..].windows(2).position(|w| w == b"\n\n") {
+                    let end = start + end;
+                    let event = super::decode_body("Anthropic", &data[start..end]);
+                    start = end + 2;
+
+                    // Each SSE event has an `event:` line followed by a `data:` line
+                    for line in event.lines() {
+                        if let Some(json_data) = line.trim().strip_prefix("data: ") {
+                            match serde_json::from_str::<StreamEvent>(json_data) {
+                                // A mid-stream error ends the answer; it must not pass for a finished one
+                                Ok(StreamEvent::Error { error }) => Err(ApiError::AnthropicError {
+                                    message: error.message,
+                                    type_: error.error_type,
+                                    param: None,
+                                    code: None,
+                                    provider_request_id: None
+                                })?,
+                                Ok(event) => yield event,
+                                Err(_) => {}
+                            }
+                        }
+                    }
+                }
+
+                if start > 0 {
+                    data.drain(..start);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, user_message};
+    use axum::{routing::post, Router};
+
+    const MESSAGE_START: &str = r#"{"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-3-5-sonnet-20241022","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":10,"output_tokens":1}}}"#;
+    const OVERLOADED: &str = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+
+    #[tokio::test]
+    async fn error_event_ends_the_stream_with_an_error() {
+        let body = format!(
+            "event: message_start\ndata: {}\n\nevent: error\ndata: {}\n\n",
+            MESSAGE_START, OVERLOADED
+        );
+        let url = serve(Router::new().route("/v1/messages", post(move || async move { body }))).await;
+        let client = AnthropicClient::with_http_client("token".to_string(), Client::new()).with_base_url(Some(&url));
+
+        let events: Vec<_> = client
+            .chat_stream(user_message("hi"), None, &ApiConfig::default())
+            .collect()
+            .await;
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], Ok(StreamEvent::MessageStart { .. })));
+        match &events[1] {
+            Err(ApiError::AnthropicError { type_, message, .. }) => {
+                assert_eq!(type_, "overloaded_error");
+                assert_eq!(message, "Overloaded");
+            }
+            other => panic!("expected an Anthropic error, got {:?}", other),
+        }
+    }
+}
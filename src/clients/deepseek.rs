@@ -22,7 +22,7 @@
 //!
 //! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
 //! // Initialize the client
-//! let client = DeepSeekClient::new("your-api-key".to_string());
+//! let client = DeepSeekClient::with_http_client("your-api-key".to_string(), reqwest::Client::new());
 //!
 //! // Prepare messages and configuration
 //! let messages = vec![Message {
@@ -65,8 +65,12 @@ use std::{collections::HashMap, pin::Pin};
 use futures::StreamExt;
 use serde_json;
 
-pub(crate) const DEEPSEEK_API_URL: &str = "https://api.deepseek.com/chat/completions";
-const DEFAULT_MODEL: &str = "deepseek-reasoner";
+/// Base URL of DeepSeek's public API.
+pub(crate) const DEFAULT_BASE_URL: &str = "https://api.deepseek.com";
+pub(crate) const DEFAULT_MODEL: &str = "deepseek-reasoner";
+
+/// Output token limit used when the request doesn't set `max_tokens`.
+pub(crate) const DEFAULT_MAX_OUTPUT_TOKENS: u32 = 8192;
 
 /// Client for interacting with DeepSeek's AI models.
 ///
@@ -78,12 +82,15 @@ const DEFAULT_MODEL: &str = "deepseek-reasoner";
 /// ```no_run
 /// use deepclaude::clients::DeepSeekClient;
 ///
-/// let client = DeepSeekClient::new("api_token".to_string());
+/// let client = DeepSeekClient::with_http_client("api_token".to_string(), reqwest::Client::new());
 /// ```
 #[derive(Debug)]
 pub struct DeepSeekClient {
     pub(crate) client: Client,
     api_token: String,
+    base_url: String,
+    /// Canned output returned instead of calling the API, in mock mode.
+    mock: Option<super::MockProvider>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -146,10 +153,49 @@ pub struct Usage {
     pub total_tokens: u32,
     pub prompt_tokens_details: PromptTokensDetails,
     pub completion_tokens_details: CompletionTokensDetails,
+    #[serde(default)]
     pub prompt_cache_hit_tokens: u32,
+    #[serde(default)]
     pub prompt_cache_miss_tokens: u32,
 }
 
+impl Usage {
+    /// Returns the prompt tokens served from DeepSeek's context cache.
+    ///
+    /// DeepSeek reports cache hits as `prompt_cache_hit_tokens`; the
+    /// OpenAI-style `prompt_tokens_details.cached_tokens` is used when that
+    /// is absent.
+    pub fn cached_tokens(&self) -> u32 {
+        match self.prompt_cache_hit_tokens {
+            0 => self.prompt_tokens_details.cached_tokens,
+            hits => hits,
+        }
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Self;
+
+    /// Combines the usage of two calls, such as a retried reasoning call
+    /// and the attempt it replaced.
+    fn add(self, other: Self) -> Self {
+        Self {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            completion_tokens: self.completion_tokens + other.completion_tokens,
+            total_tokens: self.total_tokens + other.total_tokens,
+            prompt_tokens_details: PromptTokensDetails {
+                cached_tokens: self.prompt_tokens_details.cached_tokens + other.prompt_tokens_details.cached_tokens,
+            },
+            completion_tokens_details: CompletionTokensDetails {
+                reasoning_tokens: self.completion_tokens_details.reasoning_tokens
+                    + other.completion_tokens_details.reasoning_tokens,
+            },
+            prompt_cache_hit_tokens: self.prompt_cache_hit_tokens + other.prompt_cache_hit_tokens,
+            prompt_cache_miss_tokens: self.prompt_cache_miss_tokens + other.prompt_cache_miss_tokens,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PromptTokensDetails {
     pub cached_tokens: u32,
@@ -169,11 +215,36 @@ pub(crate) struct DeepSeekRequest {
 }
 
 impl DeepSeekClient {
-    pub fn new(api_token: String) -> Self {
+    /// Creates a new DeepSeekClient that sends requests through `client`,
+    /// such as one built by `pinning::http_client`.
+    pub fn with_http_client(api_token: String, client: Client) -> Self {
         Self {
-            client: Client::new(),
+            client,
             api_token,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            mock: None,
+        }
+    }
+
+    /// Sends requests to `base_url` instead of DeepSeek's public API, such
+    /// as a proxy or an OpenAI-compatible mirror. None keeps the default.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> Self {
+        if let Some(base_url) = base_url {
+            self.base_url = base_url.trim_end_matches('/').to_string();
         }
+        self
+    }
+
+    /// Serves canned output from `mock` instead of calling the API.
+    /// None keeps calling the API.
+    pub fn with_mock(mut self, mock: Option<super::MockProvider>) -> Self {
+        self.mock = mock;
+        self
+    }
+
+    /// Returns the chat completions endpoint under the base URL.
+    fn completions_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url)
     }
 
     /// Builds the HTTP headers required for DeepSeek API requests.
@@ -237,19 +308,33 @@ impl DeepSeekClient {
     ///
     /// A `DeepSeekRequest` object configured with the provided parameters and defaults
     pub(crate) fn build_request(&self, messages: Vec<Message>, stream: bool, config: &ApiConfig) -> DeepSeekRequest {
+        // DeepSeek is text-only, so replace any images with placeholders
+        let messages: Vec<Message> = messages.iter().map(Message::to_text_only).collect();
+
         // Create a base request with required fields
         let mut request_value = serde_json::json!({
             "messages": messages,
             "stream": stream,
             // Set defaults only if not provided in config
             "model": config.body.get("model").unwrap_or(&serde_json::json!(DEFAULT_MODEL)),
-            "max_tokens": config.body.get("max_tokens").unwrap_or(&serde_json::json!(8192)),
-            "temperature": config.body.get("temperature").unwrap_or(&serde_json::json!(1.0)),
+            "max_tokens": config.body.get("max_tokens").unwrap_or(&serde_json::json!(DEFAULT_MAX_OUTPUT_TOKENS)),
+            // temperature, top_p, frequency_penalty, and presence_penalty
+            // are only sent when set in config.body, so DeepSeek's defaults
+            // apply otherwise
             "response_format": {
                 "type": "text"
             }
         });
 
+        // DeepSeek currently ignores the seed, but it is forwarded for when it doesn't
+        if let Some(seed) = config.seed {
+            request_value["seed"] = serde_json::json!(seed);
+        }
+
+        if let Some(effort) = config.reasoning_effort {
+            request_value["reasoning_effort"] = serde_json::json!(effort);
+        }
+
         // Merge additional configuration from config.body while protecting critical fields
         if let serde_json::Value::Object(mut map) = request_value {
             if let serde_json::Value::Object(mut body) = serde_json::to_value(&config.body).unwrap_or_default() {
@@ -262,6 +347,13 @@ impl DeepSeekClient {
                     map.insert(key, value);
                 }
             }
+
+            // Extra parameters are forwarded as-is, except the protected fields
+            for (key, value) in config.extra_params().into_iter().flatten() {
+                if key != "stream" && key != "messages" {
+                    map.insert(key.clone(), value.clone());
+                }
+            }
             request_value = serde_json::Value::Object(map);
         }
 
@@ -295,44 +387,62 @@ impl DeepSeekClient {
         messages: Vec<Message>,
         config: &ApiConfig,
     ) -> Result<DeepSeekResponse> {
+        if let Some(mock) = &self.mock {
+            return Ok(mock.deepseek_response(&messages, config));
+        }
+
         let headers = self.build_headers(Some(&config.headers))?;
         let request = self.build_request(messages, false, config);
 
         let response = self
             .client
-            .post(DEEPSEEK_API_URL)
+            .post(self.completions_url())
             .headers(headers)
             .json(&request)
             .send()
             .await
             .map_err(|e| ApiError::DeepSeekError { 
-                message: format!("Request failed: {}", e),
+                message: format!("Request failed: {}", super::error_chain(&e)),
                 type_: "request_failed".to_string(),
                 param: None,
-                code: None
+                code: None,
+                provider_request_id: None
             })?;
 
         if !response.status().is_success() {
+            let provider_request_id = super::provider_request_id(response.headers());
             let error = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ApiError::DeepSeekError { 
+            let details = super::parse_error_body(&error);
+            return Err(ApiError::DeepSeekError {
                 message: error,
-                type_: "api_error".to_string(),
-                param: None,
-                code: None
+                type_: details.type_.unwrap_or_else(|| "api_error".to_string()),
+                param: details.param,
+                code: details.code,
+                provider_request_id
             });
         }
 
-        response
-            .json::<DeepSeekResponse>()
+        let body = response
+            .bytes()
             .await
+            .map_err(|e| ApiError::DeepSeekError { 
+                message: format!("Failed to read response: {}", e),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })?;
+
+        serde_json::from_str::<DeepSeekResponse>(&super::decode_body("DeepSeek", &body))
             .map_err(|e| ApiError::DeepSeekError { 
                 message: format!("Failed to parse response: {}", e),
                 type_: "parse_error".to_string(),
                 param: None,
-                code: None
+                code: None,
+                provider_request_id: None
             })
     }
 
@@ -360,6 +470,10 @@ impl DeepSeekClient {
         messages: Vec<Message>,
         config: &ApiConfig,
     ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse>> + Send>> {
+        if let Some(mock) = &self.mock {
+            return mock.deepseek_stream(&messages, config);
+        }
+
         let headers = match self.build_headers(Some(&config.headers)) {
             Ok(h) => h,
             Err(e) => return Box::pin(futures::stream::once(async move { Err(e) })),
@@ -367,41 +481,64 @@ impl DeepSeekClient {
 
         let request = self.build_request(messages, true, config);
         let client = self.client.clone();
+        let url = self.completions_url();
 
         Box::pin(async_stream::try_stream! {
-            let mut stream = client
-                .post(DEEPSEEK_API_URL)
+            let response = client
+                .post(url)
                 .headers(headers)
                 .json(&request)
                 .send()
                 .await
                 .map_err(|e| ApiError::DeepSeekError { 
-                    message: format!("Request failed: {}", e),
+                    message: format!("Request failed: {}", super::error_chain(&e)),
                     type_: "request_failed".to_string(),
                     param: None,
-                    code: None
-                })?
-                .bytes_stream();
+                    code: None,
+                    provider_request_id: None
+                })?;
 
-            let mut data = String::new();
+            if !response.status().is_success() {
+                let provider_request_id = super::provider_request_id(response.headers());
+                let error = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "Unknown error".to_string());
+                let details = super::parse_error_body(&error);
+                Err(ApiError::DeepSeekError {
+                    message: error,
+                    type_: details.type_.unwrap_or_else(|| "api_error".to_string()),
+                    param: details.param,
+                    code: details.code,
+                    provider_request_id
+                })?;
+                // The error above ends the stream
+                return;
+            }
+
+            let mut stream = response.bytes_stream();
+
+            // Buffer raw bytes so multi-byte characters split across chunks decode correctly
+            let mut data: Vec<u8> = Vec::new();
             
             while let Some(chunk) = stream.next().await {
                 let chunk = chunk.map_err(|e| ApiError::DeepSeekError { 
                     message: format!("Stream error: {}", e),
                     type_: "stream_error".to_string(),
                     param: None,
-                    code: None
+                    code: None,
+                    provider_request_id: None
                 })?;
-                data.push_str(&String::from_utf8_lossy(&chunk));
+                data.extend_from_slice(&chunk);
 
                 let mut start = 0;
-                while let Some(end) = data[start..].find("\n\n") {
+                while let Some(end) = data[start..].windows(2).position(|w| w == b"\n\n") {
                     let end = start + end;
-                    let line = &data[start..end].trim();
+                    let event = super::decode_body("DeepSeek", &data[start..end]);
+                    let line = event.trim();
                     start = end + 2;
                     
-                    if line.starts_with("data: ") {
-                        let json_data = &line["data: ".len()..];
+                    if let Some(json_data) = line.strip_prefix("data: ") {
                         if let Ok(response) = serde_json::from_str::<StreamResponse>(json_data) {
                             yield response;
                         }
@@ -409,9 +546,80 @@ impl DeepSeekClient {
                 }
 
                 if start > 0 {
-                    data = data[start..].to_string();
+                    data.drain(..start);
                 }
             }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::ApiConfig, test_support};
+
+    #[tokio::test]
+    async fn a_response_starting_with_a_byte_order_mark_parses() {
+        let mut body = "\u{feff}".as_bytes().to_vec();
+        body.extend(test_support::deepseek_body("Answer.", Some("Reasoning.")).to_string().into_bytes());
+        let url = test_support::serve(axum::Router::new().fallback(move || {
+            let body = body.clone();
+            async move { ([(axum::http::header::CONTENT_TYPE, "application/json")], body) }
+        }))
+        .await;
+        let client = DeepSeekClient::with_http_client("token".to_string(), Client::new()).with_base_url(Some(&url));
+
+        let response = client.chat(test_support::user_message("hi"), &ApiConfig::default()).await.unwrap();
+
+        let message = &response.choices[0].message;
+        assert_eq!(message.content.as_deref(), Some("Answer."));
+        assert_eq!(message.reasoning_content.as_deref(), Some("Reasoning."));
+    }
+
+    #[test]
+    fn build_request_forwards_the_seed() {
+        let client = DeepSeekClient::with_http_client(String::new(), Client::new());
+        let config: ApiConfig = serde_json::from_value(serde_json::json!({"seed": 42})).unwrap();
+
+        let request = client.build_request(test_support::user_message("hi"), false, &config);
+
+        assert_eq!(serde_json::to_value(&request).unwrap()["seed"], serde_json::json!(42));
+    }
+
+    #[test]
+    fn penalties_are_sent_only_when_set() {
+        let client = DeepSeekClient::with_http_client(String::new(), Client::new());
+        let set: ApiConfig = serde_json::from_value(serde_json::json!({
+            "body": {"frequency_penalty": 0.5, "presence_penalty": -0.25}
+        }))
+        .unwrap();
+
+        let with = serde_json::to_value(client.build_request(test_support::user_message("hi"), false, &set)).unwrap();
+        let without = serde_json::to_value(
+            client.build_request(test_support::user_message("hi"), false, &ApiConfig::default()),
+        )
+        .unwrap();
+
+        assert_eq!(with["frequency_penalty"], serde_json::json!(0.5));
+        assert_eq!(with["presence_penalty"], serde_json::json!(-0.25));
+        assert!(without.get("frequency_penalty").is_none());
+        assert!(without.get("presence_penalty").is_none());
+        assert!(without.get("temperature").is_none());
+    }
+
+    #[test]
+    fn reasoning_effort_is_sent_when_set() {
+        let client = DeepSeekClient::with_http_client(String::new(), Client::new());
+        let config: ApiConfig = serde_json::from_value(serde_json::json!({"reasoning_effort": "high"})).unwrap();
+
+        let with = serde_json::to_value(client.build_request(test_support::user_message("hi"), false, &config)).unwrap();
+        let without = serde_json::to_value(
+            client.build_request(test_support::user_message("hi"), false, &ApiConfig::default()),
+        )
+        .unwrap();
+
+        assert_eq!(with["reasoning_effort"], "high");
+        assert!(without.get("reasoning_effort").is_none());
+        assert!(serde_json::from_value::<ApiConfig>(serde_json::json!({"reasoning_effort": "max"})).is_err());
+    }
+}
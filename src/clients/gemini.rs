@@ -1,18 +1,30 @@
+//! Gemini API client implementation for the answer phase.
+//!
+//! Requests go straight to the `generateContent` REST endpoints through a
+//! reqwest client, so the same pinned HTTP client setup used for the other
+//! providers applies to Gemini too.
+
 use std::{collections::HashMap, pin::Pin};
-use futures::Stream;
-use google_generative_ai_rs::{
-    client::Client,
-    types::{GenerateContentRequest, GenerateContentResponse, Part},
-};
-use reqwest::header::HeaderMap;
+use futures::{Stream, StreamExt};
+use reqwest::{header::HeaderMap, Client};
 use serde::{Deserialize, Serialize};
-use tokio_stream::StreamExt;
 
 use crate::{
-    models::{ApiConfig, Message},
-    error::Result,
+    models::{ApiConfig, Citation, ContentPart, FinishReason, Message, MessageContent, ResponseFormat, Role},
+    error::{ApiError, Result},
 };
 
+pub(crate) const DEFAULT_MODEL: &str = "gemini-2.0-pro-exp";
+
+/// Finish reason reported when Gemini returns no candidates at all.
+const NO_CANDIDATES_FINISH_REASON: &str = "NO_CANDIDATES";
+
+/// Base URL of Google's public Gemini API.
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+
+/// Output token limit used when the request doesn't set `max_tokens`.
+pub(crate) const DEFAULT_MAX_OUTPUT_TOKENS: u32 = 2048;
+
 /// Client for interacting with Google's Gemini AI models.
 ///
 /// This client handles authentication, request construction, and response parsing
@@ -23,11 +35,13 @@ use crate::{
 /// ```no_run
 /// use deepclaude::clients::GeminiClient;
 ///
-/// let client = GeminiClient::new("api_token".to_string());
+/// let client = GeminiClient::with_http_client("api_token".to_string(), reqwest::Client::new());
 /// ```
 #[derive(Debug, Clone)]
 pub struct GeminiClient {
     client: Client,
+    api_token: String,
+    base_url: String,
     model: String,
 }
 
@@ -35,6 +49,9 @@ pub struct GeminiClient {
 pub struct GeminiResponse {
     pub choices: Vec<Choice>,
     pub usage: Option<Usage>,
+    /// Sources cited by the first candidate.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -46,7 +63,11 @@ pub struct Choice {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AssistantMessage {
     pub role: String,
+    /// Text of all parts, concatenated.
     pub content: String,
+    /// Text of each part, in order, when the candidate has more than one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<String>,
 }
 
 // Streaming response types
@@ -69,6 +90,9 @@ pub struct StreamResponse {
     pub created: u64,
     pub model: String,
     pub usage: Option<Usage>,
+    /// Sources cited in this chunk.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -90,13 +114,247 @@ pub struct CompletionTokensDetails {
     pub total_tokens: u32,
 }
 
+// Wire types of the generateContent REST API
+
+/// Request body of `generateContent` and `streamGenerateContent`.
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    safety_settings: Vec<SafetySetting>,
+    generation_config: GenerationConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct Content {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inline_data: Option<InlineData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_data: Option<FileData>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct InlineData {
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct FileData {
+    mime_type: String,
+    file_uri: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct SafetySetting {
+    category: String,
+    threshold: String,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_schema: Option<serde_json::Value>,
+}
+
+/// Response body of `generateContent`, and of each streamed event.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct GenerateContentResponse {
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Candidate {
+    content: Content,
+    finish_reason: Option<String>,
+    safety_ratings: Vec<SafetyRating>,
+    citation_metadata: Option<CitationMetadata>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct PromptFeedback {
+    block_reason: Option<String>,
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+struct SafetyRating {
+    category: String,
+    probability: String,
+    blocked: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct CitationMetadata {
+    citation_sources: Vec<CitationSource>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct CitationSource {
+    start_index: Option<i64>,
+    end_index: Option<i64>,
+    uri: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct UsageMetadata {
+    prompt_token_count: u32,
+    candidates_token_count: u32,
+    total_token_count: u32,
+}
+
+impl GenerateContentResponse {
+    /// Returns the text of the first candidate's parts, concatenated.
+    fn text(&self) -> String {
+        self.candidates
+            .first()
+            .map(|candidate| {
+                candidate
+                    .content
+                    .parts
+                    .iter()
+                    .filter_map(|part| part.text.as_deref())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Converts the reported token counts to our usage format.
+    fn usage(&self) -> Option<Usage> {
+        self.usage_metadata.as_ref().map(|metadata| Usage {
+            prompt_tokens: metadata.prompt_token_count,
+            completion_tokens: metadata.candidates_token_count,
+            total_tokens: metadata.total_token_count,
+            prompt_tokens_details: None,
+            completion_tokens_details: None,
+        })
+    }
+}
+
+impl GeminiResponse {
+    /// Returns the text of the first choice.
+    pub fn text(&self) -> String {
+        self.choices
+            .first()
+            .map(|choice| choice.message.content.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the text of each part of the first choice, or an empty list
+    /// if it has a single part.
+    pub fn parts(&self) -> Vec<String> {
+        self.choices
+            .first()
+            .map(|choice| choice.message.parts.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the text of every choice after the first.
+    pub fn alternatives(&self) -> Vec<String> {
+        self.choices
+            .iter()
+            .skip(1)
+            .map(|choice| choice.message.content.clone())
+            .collect()
+    }
+}
+
 impl GeminiClient {
-    /// Creates a new GeminiClient with the specified API token.
-    pub fn new(api_token: String) -> Self {
+    /// Creates a new GeminiClient that sends requests through `client`,
+    /// such as one built by `pinning::http_client`.
+    pub fn with_http_client(api_token: String, client: Client) -> Self {
         Self {
-            client: Client::new(api_token),
-            model: "gemini-2.0-pro-exp".to_string(),
+            client,
+            api_token,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            model: DEFAULT_MODEL.to_string(),
+        }
+    }
+
+    /// Sends requests to `base_url` instead of Google's public API, such as
+    /// a proxy. None keeps the default.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> Self {
+        if let Some(base_url) = base_url {
+            self.base_url = base_url.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
+    /// Returns the endpoint for `method` on the model the config selects.
+    fn model_url(&self, config: &ApiConfig, method: &str) -> String {
+        let model = config.model().unwrap_or(&self.model);
+        format!("{}/v1beta/models/{}:{}", self.base_url, model, method)
+    }
+
+    /// Builds the HTTP headers required for Gemini API requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::Internal` if the API token or a custom header is invalid
+    fn build_headers(&self, custom_headers: Option<&HashMap<String, String>>) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-goog-api-key",
+            self.api_token
+                .parse()
+                .map_err(|e| ApiError::Internal {
+                    message: format!("Invalid API token: {}", e)
+                })?,
+        );
+        headers.insert(
+            "Content-Type",
+            "application/json"
+                .parse()
+                .map_err(|e| ApiError::Internal {
+                    message: format!("Invalid content type: {}", e)
+                })?,
+        );
+
+        if let Some(custom) = custom_headers {
+            headers.extend(super::build_headers(custom)?);
         }
+
+        Ok(headers)
     }
 
     /// Sends a non-streaming chat request to the Gemini API.
@@ -109,15 +367,61 @@ impl GeminiClient {
     /// # Returns
     ///
     /// * `Result<GeminiResponse>` - The model's response on success
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::GoogleError` if the request fails, the response
+    /// status is not successful, or the response cannot be parsed, and
+    /// `ApiError::PromptBlocked` or `ApiError::ContentFiltered` if Gemini
+    /// blocked the prompt or the answer.
     pub async fn chat(
         &self,
         messages: Vec<Message>,
         config: &ApiConfig,
     ) -> Result<GeminiResponse> {
-        let request = self.build_request(messages, config);
-        let response = self.client.generate_content(request).await?;
-        
-        Ok(self.convert_response(response))
+        let headers = self.build_headers(Some(&config.headers))?;
+        let request = self.build_request(messages, false, config);
+
+        let response = self
+            .client
+            .post(self.model_url(config, "generateContent"))
+            .headers(headers)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| ApiError::GoogleError {
+                message: format!("Request failed: {}", super::error_chain(&e)),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })?;
+
+        if !response.status().is_success() {
+            return Err(Self::error_response(response).await);
+        }
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ApiError::GoogleError {
+                message: format!("Failed to read response: {}", e),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })?;
+
+        let response = serde_json::from_str::<GenerateContentResponse>(&super::decode_body("Gemini", &body))
+            .map_err(|e| ApiError::GoogleError {
+                message: format!("Failed to parse response: {}", e),
+                type_: "parse_error".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            })?;
+
+        self.convert_response(response)
     }
 
     /// Sends a streaming chat request to the Gemini API.
@@ -132,60 +436,482 @@ impl GeminiClient {
     /// # Returns
     ///
     /// * `Pin<Box<dyn Stream<Item = Result<StreamResponse>> + Send>>` - A stream of response chunks
+    ///
+    /// # Errors
+    ///
+    /// The stream may yield `ApiError::GoogleError` if the request or the
+    /// stream fails, and the safety errors `chat` returns.
     pub fn chat_stream(
         &self,
         messages: Vec<Message>,
         config: &ApiConfig,
     ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse>> + Send>> {
-        let request = self.build_request(messages, config);
-        let stream = self.client.generate_content_stream(request);
-        
-        Box::pin(stream.map(|result| {
-            result.map_err(Into::into).map(|response| self.convert_stream_response(response))
-        }))
+        let headers = match self.build_headers(Some(&config.headers)) {
+            Ok(h) => h,
+            Err(e) => return Box::pin(futures::stream::once(async move { Err(e) })),
+        };
+
+        let request = self.build_request(messages, true, config);
+        let client = self.clone();
+        let url = format!("{}?alt=sse", self.model_url(config, "streamGenerateContent"));
+
+        Box::pin(async_stream::try_stream! {
+            let response = client
+                .client
+                .post(url)
+                .headers(headers)
+                .json(&request)
+                .send()
+                .await
+                .map_err(|e| ApiError::GoogleError {
+                    message: format!("Request failed: {}", super::error_chain(&e)),
+                    type_: "request_failed".to_string(),
+                    param: None,
+                    code: None,
+                    provider_request_id: None
+                })?;
+
+            if !response.status().is_success() {
+                Err(Self::error_response(response).await)?;
+                // The error above ends the stream
+                return;
+            }
+
+            let mut stream = response.bytes_stream();
+
+            // Buffer raw bytes so multi-byte characters split across chunks
+            // decode correctly. Google ends events with CRLF pairs; dropping
+            // carriage returns leaves plain blank-line separators.
+            let mut data: Vec<u8> = Vec::new();
+
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(|e| ApiError::GoogleError {
+                    message: format!("Stream error: {}", e),
+                    type_: "stream_error".to_string(),
+                    param: None,
+                    code: None,
+                    provider_request_id: None
+                })?;
+                data.extend(chunk.iter().filter(|&&byte| byte != b'\r'));
+
+                let mut start = 0;
+                while let Some(end) = data[start..].windows(2).position(|w| w == b"\n\n") {
+                    let end = start + end;
+                    let event = super::decode_body("Gemini", &data[start..end]);
+                    start = end + 2;
+
+                    for line in event.lines() {
+                        if let Some(json_data) = line.trim().strip_prefix("data: ") {
+                            if let Ok(response) = serde_json::from_str::<GenerateContentResponse>(json_data) {
+                                yield client.convert_stream_response(response)?;
+                            }
+                        }
+                    }
+                }
+
+                if start > 0 {
+                    data.drain(..start);
+                }
+            }
+        })
+    }
+
+    /// Converts an unsuccessful response into `ApiError::GoogleError`.
+    ///
+    /// Google reports errors as `{"error": {"code", "message", "status"}}`;
+    /// the status, such as `RESOURCE_EXHAUSTED`, becomes the error type.
+    async fn error_response(response: reqwest::Response) -> ApiError {
+        let provider_request_id = super::provider_request_id(response.headers());
+        let error = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        let details = super::parse_error_body(&error);
+        let status = serde_json::from_str::<serde_json::Value>(&error)
+            .ok()
+            .and_then(|value| value["error"]["status"].as_str().map(str::to_string));
+        ApiError::GoogleError {
+            message: error,
+            type_: details.type_.or(status).unwrap_or_else(|| "api_error".to_string()),
+            param: details.param,
+            code: details.code,
+            provider_request_id
+        }
     }
 
     /// Builds a GenerateContentRequest for the Gemini API.
-    fn build_request(&self, messages: Vec<Message>, config: &ApiConfig) -> GenerateContentRequest {
-        let contents: Vec<Part> = messages.into_iter().map(|msg| {
-            Part::text(msg.content)
+    ///
+    /// System messages are sent as the request's system instruction rather
+    /// than as content, so the reasoning appended after the conversation
+    /// can't be mistaken for, or dilute, the system prompt. Streams always
+    /// request a single candidate.
+    fn build_request(&self, messages: Vec<Message>, stream: bool, config: &ApiConfig) -> GenerateContentRequest {
+        let (system, conversation): (Vec<Message>, Vec<Message>) = messages
+            .into_iter()
+            .partition(|msg| matches!(msg.role, Role::System));
+
+        let contents: Vec<Content> = conversation.into_iter().map(|msg| Content {
+            role: Some(match msg.role {
+                Role::Assistant => "model",
+                _ => "user",
+            }.to_string()),
+            parts: Self::build_parts(msg.content),
         }).collect();
 
-        GenerateContentRequest::new(&self.model, contents)
-            .temperature(config.temperature)
-            .max_output_tokens(config.max_tokens.unwrap_or(2048))
-            .top_p(config.top_p)
+        // Sampling parameters are only sent when set, so Gemini's own
+        // defaults apply otherwise; an explicit 0.0 is still sent
+        let mut generation_config = GenerationConfig {
+            max_output_tokens: Some(config.max_tokens().unwrap_or(DEFAULT_MAX_OUTPUT_TOKENS)),
+            seed: config.seed,
+            temperature: config.temperature(),
+            top_p: config.top_p(),
+            top_k: config.top_k(),
+            candidate_count: config.n.filter(|&n| n > 1 && !stream),
+            ..Default::default()
+        };
+
+        match &config.response_format {
+            Some(ResponseFormat::JsonObject) => {
+                generation_config.response_mime_type = Some("application/json".to_string());
+            }
+            Some(ResponseFormat::JsonSchema { schema }) => {
+                generation_config.response_mime_type = Some("application/json".to_string());
+                generation_config.response_schema = Some(schema.clone());
+            }
+            _ => {}
+        }
+
+        if let Some(params) = config.extra_params() {
+            Self::apply_extra_params(&mut generation_config, params, stream);
+        }
+
+        let system_instruction = (!system.is_empty()).then(|| Content {
+            role: None,
+            parts: vec![Part {
+                text: Some(
+                    system
+                        .iter()
+                        .map(|msg| msg.content.text())
+                        .collect::<Vec<_>>()
+                        .join("\n\n"),
+                ),
+                ..Default::default()
+            }],
+        });
+
+        GenerateContentRequest {
+            contents,
+            system_instruction,
+            safety_settings: config
+                .safety_settings
+                .iter()
+                .map(|(category, threshold)| SafetySetting {
+                    category: category.clone(),
+                    threshold: threshold.clone(),
+                })
+                .collect(),
+            generation_config,
+        }
+    }
+
+    /// Applies the `extra_params` Gemini's generation config can carry,
+    /// taking precedence over the same settings from the rest of the
+    /// config. Keys without a counterpart, or with a value of the wrong
+    /// type, are dropped with a warning, as is a candidate count on a stream.
+    fn apply_extra_params(
+        generation_config: &mut GenerationConfig,
+        params: &serde_json::Map<String, serde_json::Value>,
+        stream: bool,
+    ) {
+        for (key, value) in params {
+            let number = value.as_f64().map(|v| v as f32);
+            let count = value.as_u64().map(|v| v.min(u32::MAX as u64) as u32);
+            match (key.as_str(), number, count) {
+                ("temperature", Some(v), _) => generation_config.temperature = Some(v),
+                ("topP" | "top_p", Some(v), _) => generation_config.top_p = Some(v),
+                ("topK" | "top_k", _, Some(v)) => generation_config.top_k = Some(v),
+                ("maxOutputTokens" | "max_output_tokens", _, Some(v)) => {
+                    generation_config.max_output_tokens = Some(v)
+                }
+                ("candidateCount" | "candidate_count", _, Some(v)) if !stream => {
+                    generation_config.candidate_count = Some(v)
+                }
+                ("seed", _, _) if value.is_u64() => generation_config.seed = value.as_u64(),
+                ("responseMimeType" | "response_mime_type", _, _) if value.is_string() => {
+                    generation_config.response_mime_type = value.as_str().map(str::to_string)
+                }
+                ("responseSchema" | "response_schema", _, _) => {
+                    generation_config.response_schema = Some(value.clone())
+                }
+                _ => tracing::warn!("Gemini extra_params key {} is unsupported or invalid, dropped", key),
+            }
+        }
     }
 
-    /// Converts a Gemini response to our internal GeminiResponse format
-    fn convert_response(&self, response: GenerateContentResponse) -> GeminiResponse {
-        // TODO: Implement proper conversion from Gemini response format
-        GeminiResponse {
-            choices: vec![Choice {
-                message: AssistantMessage {
-                    role: "assistant".to_string(),
-                    content: response.text().unwrap_or_default().to_string(),
+    /// Maps message content onto Gemini parts, including inline and remote images.
+    fn build_parts(content: MessageContent) -> Vec<Part> {
+        match content {
+            MessageContent::Text(text) => vec![Part { text: Some(text), ..Default::default() }],
+            MessageContent::Parts(parts) => parts.into_iter().map(|part| match part {
+                ContentPart::Text { text } => Part { text: Some(text), ..Default::default() },
+                ContentPart::Image { mime_type, data } => Part {
+                    inline_data: Some(InlineData { mime_type, data }),
+                    ..Default::default()
                 },
-                finish_reason: Some("stop".to_string()),
-            }],
-            usage: None, // Gemini API currently doesn't provide detailed token usage
+                ContentPart::ImageUrl { mime_type, url } => Part {
+                    file_data: Some(FileData { mime_type, file_uri: url }),
+                    ..Default::default()
+                },
+            }).collect(),
         }
     }
 
+    /// Extracts the raw finish reason of the first candidate, if reported.
+    fn finish_reason(response: &GenerateContentResponse) -> Option<String> {
+        response
+            .candidates
+            .first()
+            .and_then(|candidate| candidate.finish_reason.clone())
+    }
+
+    /// Fails with `ApiError::PromptBlocked` if Gemini rejected the prompt
+    /// itself, or `ApiError::ContentFiltered` if it stopped the candidate
+    /// for safety reasons.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - Ok if the response was not filtered
+    fn check_safety(response: &GenerateContentResponse) -> Result<()> {
+        // A blocked prompt comes back without candidates, so it must be
+        // caught before the response reads as an empty answer
+        if let Some(feedback) = &response.prompt_feedback {
+            if let Some(reason) = &feedback.block_reason {
+                return Err(ApiError::PromptBlocked {
+                    reason: reason.clone(),
+                    safety_ratings: feedback
+                        .safety_ratings
+                        .iter()
+                        .map(|rating| format!("{}={}", rating.category, rating.probability))
+                        .collect(),
+                });
+            }
+        }
+
+        let candidate_blocked = Self::finish_reason(response)
+            .is_some_and(|reason| FinishReason::from_gemini(&reason) == FinishReason::Safety);
+        if !candidate_blocked {
+            return Ok(());
+        }
+
+        let mut categories: Vec<String> = response
+            .candidates
+            .iter()
+            .flat_map(|candidate| candidate.safety_ratings.iter())
+            .filter(|rating| rating.blocked.unwrap_or(false))
+            .map(|rating| rating.category.clone())
+            .collect();
+        categories.sort();
+        categories.dedup();
+
+        Err(ApiError::ContentFiltered { categories })
+    }
+
+    /// Converts a Gemini response to our internal GeminiResponse format
+    fn convert_response(&self, response: GenerateContentResponse) -> Result<GeminiResponse> {
+        Self::check_safety(&response)?;
+
+        // No candidates and no block reason: report an explicitly empty answer
+        if response.candidates.is_empty() {
+            tracing::warn!("Gemini returned no candidates");
+            return Ok(GeminiResponse {
+                choices: vec![Choice {
+                    message: AssistantMessage {
+                        role: "assistant".to_string(),
+                        content: String::new(),
+                        parts: Vec::new(),
+                    },
+                    finish_reason: Some(NO_CANDIDATES_FINISH_REASON.to_string()),
+                }],
+                usage: response.usage(),
+                citations: Vec::new(),
+            });
+        }
+
+        // Each candidate becomes a choice, keeping its text parts separate
+        let choices = response
+            .candidates
+            .iter()
+            .map(|candidate| {
+                let mut parts: Vec<String> = candidate
+                    .content
+                    .parts
+                    .iter()
+                    .filter_map(|part| part.text.clone())
+                    .collect();
+                if let Some(first) = parts.first_mut() {
+                    *first = super::strip_bom("Gemini", first).to_string();
+                }
+                let content = parts.concat();
+                if parts.len() < 2 {
+                    parts.clear();
+                }
+                Choice {
+                    message: AssistantMessage {
+                        role: "assistant".to_string(),
+                        content,
+                        parts,
+                    },
+                    finish_reason: candidate.finish_reason.clone(),
+                }
+            })
+            .collect();
+
+        Ok(GeminiResponse {
+            choices,
+            usage: response.usage(),
+            citations: Self::citations(&response),
+        })
+    }
+
     /// Converts a Gemini streaming response to our internal StreamResponse format
-    fn convert_stream_response(&self, response: GenerateContentResponse) -> StreamResponse {
-        StreamResponse {
+    fn convert_stream_response(&self, response: GenerateContentResponse) -> Result<StreamResponse> {
+        Self::check_safety(&response)?;
+
+        Ok(StreamResponse {
             id: "gemini".to_string(), // Gemini doesn't provide response IDs
             choices: vec![StreamChoice {
                 delta: StreamDelta {
                     role: Some("assistant".to_string()),
-                    content: Some(response.text().unwrap_or_default().to_string()),
+                    content: Some(super::strip_bom("Gemini", &response.text()).to_string()),
                 },
-                finish_reason: None,
+                finish_reason: Self::finish_reason(&response),
             }],
             created: chrono::Utc::now().timestamp() as u64,
             model: self.model.clone(),
-            usage: None,
+            usage: response.usage(),
+            citations: Self::citations(&response),
+        })
+    }
+
+    /// Extracts the sources cited by the first candidate, skipping any
+    /// without a URI.
+    fn citations(response: &GenerateContentResponse) -> Vec<Citation> {
+        response
+            .candidates
+            .first()
+            .and_then(|candidate| candidate.citation_metadata.as_ref())
+            .map(|metadata| {
+                metadata
+                    .citation_sources
+                    .iter()
+                    .filter_map(|source| {
+                        Some(Citation {
+                            uri: source.uri.clone()?,
+                            title: None, // Citation sources carry a license, not a title
+                            start_index: source.start_index.and_then(|i| u32::try_from(i).ok()),
+                            end_index: source.end_index.and_then(|i| u32::try_from(i).ok()),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn build_request_sets_the_generation_config_seed() {
+        let client = GeminiClient::with_http_client(String::new(), Client::new());
+        let config: ApiConfig = serde_json::from_value(serde_json::json!({"seed": 42})).unwrap();
+
+        let request = client.build_request(test_support::user_message("hi"), false, &config);
+
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["generationConfig"]["seed"], serde_json::json!(42));
+    }
+
+    #[test]
+    fn safety_finish_reports_the_blocked_categories() {
+        let response: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true},
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "LOW"}
+                ]
+            }]
+        }))
+        .unwrap();
+
+        match GeminiClient::check_safety(&response) {
+            Err(ApiError::ContentFiltered { categories }) => {
+                assert_eq!(categories, ["HARM_CATEGORY_DANGEROUS_CONTENT"]);
+            }
+            other => panic!("expected ContentFiltered, got {:?}", other),
         }
     }
+
+    #[test]
+    fn top_k_is_sent_only_when_set() {
+        let client = GeminiClient::with_http_client(String::new(), Client::new());
+        let set: ApiConfig = serde_json::from_value(serde_json::json!({"body": {"top_k": 40}})).unwrap();
+
+        let with = serde_json::to_value(client.build_request(test_support::user_message("hi"), false, &set)).unwrap();
+        let without = serde_json::to_value(
+            client.build_request(test_support::user_message("hi"), false, &ApiConfig::default()),
+        )
+        .unwrap();
+
+        assert_eq!(with["generationConfig"]["topK"], serde_json::json!(40));
+        assert!(without["generationConfig"].get("topK").is_none());
+        assert!(without["generationConfig"].get("temperature").is_none());
+    }
+
+    #[test]
+    fn multi_part_candidates_keep_their_part_boundaries() {
+        let client = GeminiClient::with_http_client(String::new(), Client::new());
+        let response: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "Here is code:\n"}, {"text": "fn main() {}"}]},
+                "finishReason": "STOP"
+            }]
+        }))
+        .unwrap();
+
+        let converted = client.convert_response(response).unwrap();
+
+        assert_eq!(converted.parts(), ["Here is code:\n", "fn main() {}"]);
+        assert_eq!(converted.choices[0].message.content, "Here is code:\nfn main() {}");
+        assert_eq!(converted.choices[0].finish_reason.as_deref(), Some("STOP"));
+    }
+
+    #[test]
+    fn single_part_candidates_report_no_parts() {
+        let client = GeminiClient::with_http_client(String::new(), Client::new());
+        let response: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{"content": {"parts": [{"text": "Hello"}]}, "finishReason": "STOP"}]
+        }))
+        .unwrap();
+
+        let converted = client.convert_response(response).unwrap();
+
+        assert!(converted.parts().is_empty());
+        assert_eq!(converted.choices[0].message.content, "Hello");
+    }
+
+    #[test]
+    fn empty_candidates_return_an_explicit_empty_answer() {
+        let client = GeminiClient::with_http_client(String::new(), Client::new());
+        let response: GenerateContentResponse = serde_json::from_value(serde_json::json!({"candidates": []})).unwrap();
+
+        let converted = client.convert_response(response).unwrap();
+
+        assert_eq!(converted.choices.len(), 1);
+        assert_eq!(converted.choices[0].message.content, "");
+        assert_eq!(converted.choices[0].finish_reason.as_deref(), Some(NO_CANDIDATES_FINISH_REASON));
+    }
 }
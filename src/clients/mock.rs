@@ -0,0 +1,256 @@
+//! Offline stand-in for the upstream providers.
+//!
+//! With `pipeline.provider_mode = "mock"`, the DeepSeek and answer clients
+//! return the canned reasoning and answer from the `[mock]` section instead
+//! of calling their APIs, so the full handler pipeline can run without API
+//! tokens or network access. Streams deliver the text in small deltas
+//! shaped like the real providers' chunks, and usage is estimated from the
+//! text lengths.
+
+use super::{
+    answer::{AnswerChunk, AnswerResponse, AnswerUsage},
+    deepseek,
+};
+use crate::{
+    config::{AnswerProvider, Config, MockConfig, ProviderMode},
+    error::Result,
+    models::{ApiConfig, FinishReason, Message},
+};
+use futures::Stream;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    time::Duration,
+};
+
+/// Prefix of the IDs given to mock responses.
+const MOCK_ID_PREFIX: &str = "mock-";
+
+/// Canned provider output.
+#[derive(Debug, Clone)]
+pub struct MockProvider {
+    config: MockConfig,
+}
+
+impl MockProvider {
+    /// Creates a mock provider serving the fixtures in `config`.
+    pub fn new(config: &MockConfig) -> Self {
+        Self { config: config.clone() }
+    }
+
+    /// Returns the mock provider to use under `config`, or None when
+    /// providers are live.
+    pub fn for_config(config: &Config) -> Option<Self> {
+        (config.pipeline.provider_mode == ProviderMode::Mock).then(|| Self::new(&config.mock))
+    }
+
+    /// Returns a canned DeepSeek response carrying the mock reasoning.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Messages of the request, used to estimate input tokens
+    /// * `config` - Configuration of the request, for the model name
+    pub fn deepseek_response(&self, messages: &[Message], config: &ApiConfig) -> deepseek::DeepSeekResponse {
+        deepseek::DeepSeekResponse {
+            id: mock_id(),
+            object: "chat.completion".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model: deepseek_model(config),
+            choices: vec![deepseek::Choice {
+                index: 0,
+                message: deepseek::AssistantMessage {
+                    role: "assistant".to_string(),
+                    content: Some(String::new()),
+                    reasoning_content: Some(self.config.reasoning.clone()),
+                },
+                logprobs: None,
+                finish_reason: Some("stop".to_string()),
+            }],
+            usage: self.deepseek_usage(messages),
+            system_fingerprint: "mock".to_string(),
+        }
+    }
+
+    /// Streams the mock reasoning as DeepSeek chunks: a role chunk,
+    /// reasoning deltas, then a finish chunk carrying the usage.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Messages of the request, used to estimate input tokens
+    /// * `config` - Configuration of the request, for the model name
+    pub fn deepseek_stream(
+        &self,
+        messages: &[Message],
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<deepseek::StreamResponse>> + Send>> {
+        let id = mock_id();
+        let created = chrono::Utc::now().timestamp();
+        let model = deepseek_model(config);
+        let usage = self.deepseek_usage(messages);
+        let deltas = chunks(&self.config.reasoning, self.config.chunk_chars);
+        let delay = self.delay();
+
+        let chunk = move |delta: deepseek::StreamDelta, finish_reason: Option<String>, usage: Option<deepseek::Usage>| {
+            deepseek::StreamResponse {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model.clone(),
+                choices: vec![deepseek::StreamChoice {
+                    index: 0,
+                    delta,
+                    logprobs: None,
+                    finish_reason,
+                }],
+                usage,
+                system_fingerprint: "mock".to_string(),
+            }
+        };
+
+        Box::pin(async_stream::stream! {
+            yield Ok(chunk(
+                deepseek::StreamDelta {
+                    role: Some("assistant".to_string()),
+                    content: None,
+                    reasoning_content: Some(String::new()),
+                },
+                None,
+                None,
+            ));
+            for delta in deltas {
+                tokio::time::sleep(delay).await;
+                yield Ok(chunk(
+                    deepseek::StreamDelta {
+                        role: None,
+                        content: None,
+                        reasoning_content: Some(delta),
+                    },
+                    None,
+                    None,
+                ));
+            }
+            yield Ok(chunk(
+                deepseek::StreamDelta {
+                    role: None,
+                    content: Some(String::new()),
+                    reasoning_content: None,
+                },
+                Some("stop".to_string()),
+                Some(usage),
+            ));
+        })
+    }
+
+    /// Returns the canned answer.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The answer provider being mocked
+    /// * `messages` - Messages of the request, used to estimate input tokens
+    pub fn answer_response(&self, provider: AnswerProvider, messages: &[Message]) -> AnswerResponse {
+        AnswerResponse {
+            provider,
+            text: self.config.answer.clone(),
+            parts: Vec::new(),
+            alternatives: Vec::new(),
+            citations: Vec::new(),
+            finish_reason: Some(FinishReason::Stop),
+            usage: self.answer_usage(messages),
+            retries: 0,
+            raw: serde_json::json!({ "mock": true, "text": self.config.answer }),
+        }
+    }
+
+    /// Streams the canned answer in deltas, with the finish reason and
+    /// usage on the last chunk.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Messages of the request, used to estimate input tokens
+    pub fn answer_stream(&self, messages: &[Message]) -> Pin<Box<dyn Stream<Item = Result<AnswerChunk>> + Send>> {
+        let usage = self.answer_usage(messages);
+        let deltas = chunks(&self.config.answer, self.config.chunk_chars);
+        let delay = self.delay();
+
+        Box::pin(async_stream::stream! {
+            for delta in deltas {
+                tokio::time::sleep(delay).await;
+                yield Ok(AnswerChunk {
+                    text: Some(delta),
+                    ..Default::default()
+                });
+            }
+            yield Ok(AnswerChunk {
+                text: None,
+                citations: Vec::new(),
+                finish_reason: Some(FinishReason::Stop),
+                usage: Some(usage),
+            });
+        })
+    }
+
+    fn deepseek_usage(&self, messages: &[Message]) -> deepseek::Usage {
+        let prompt_tokens = input_tokens(messages);
+        let reasoning_tokens = estimate_tokens(&self.config.reasoning);
+        deepseek::Usage {
+            prompt_tokens,
+            completion_tokens: reasoning_tokens,
+            total_tokens: prompt_tokens + reasoning_tokens,
+            prompt_tokens_details: deepseek::PromptTokensDetails { cached_tokens: 0 },
+            completion_tokens_details: deepseek::CompletionTokensDetails { reasoning_tokens },
+            prompt_cache_hit_tokens: 0,
+            prompt_cache_miss_tokens: prompt_tokens,
+        }
+    }
+
+    fn answer_usage(&self, messages: &[Message]) -> AnswerUsage {
+        AnswerUsage {
+            input_tokens: input_tokens(messages),
+            output_tokens: estimate_tokens(&self.config.answer),
+            cached_input_tokens: 0,
+            cache_creation_input_tokens: 0,
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        Duration::from_millis(self.config.chunk_delay_ms)
+    }
+}
+
+/// Returns a unique ID for a mock response.
+fn mock_id() -> String {
+    format!("{}{:016x}", MOCK_ID_PREFIX, RandomState::new().build_hasher().finish())
+}
+
+/// Returns the DeepSeek model the request selected, or the default.
+fn deepseek_model(config: &ApiConfig) -> String {
+    config
+        .body
+        .get("model")
+        .and_then(|model| model.as_str())
+        .unwrap_or(deepseek::DEFAULT_MODEL)
+        .to_string()
+}
+
+/// Estimates the tokens of all message text.
+fn input_tokens(messages: &[Message]) -> u32 {
+    messages
+        .iter()
+        .map(|message| estimate_tokens(&message.content.text()))
+        .sum()
+}
+
+/// Estimates tokens at roughly four characters each.
+fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+/// Splits `text` into pieces of at most `size` characters.
+fn chunks(text: &str, size: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .chunks(size.max(1))
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
@@ -1,23 +1,33 @@
 //! Client implementations for external AI model providers.
 //!
 //! This module contains client implementations for different AI model providers:
-//! - `anthropic`: Client for Google's Claude models
+//! - `anthropic`: Client for Anthropic's Claude models
 //! - `gemini`: Client for Google's Gemini models
 //! - `deepseek`: Client for DeepSeek's reasoning models
+//! - `answer`: Provider-neutral wrapper over the answer model clients
+//! - `mock`: Canned provider output for offline tests and demos
+//! - `pinning`: HTTP clients with optional leaf certificate pinning
 //!
 //! Each client handles authentication, request building, and response parsing
 //! specific to its provider's API.
 
 
+pub mod anthropic;
+pub mod answer;
 pub mod deepseek;
 pub mod gemini;
+pub mod mock;
+pub mod pinning;
 
+pub use anthropic::AnthropicClient;
+pub use answer::AnswerClient;
 pub use deepseek::DeepSeekClient;
 pub use gemini::GeminiClient;
+pub use mock::MockProvider;
 
 use crate::error::Result;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 /// Converts a HashMap of string headers to a reqwest HeaderMap.
 ///
@@ -57,3 +67,117 @@ pub(crate) fn build_headers(headers: &HashMap<String, String>) -> Result<HeaderM
     
     Ok(header_map)
 }
+
+const UTF8_BOM: &str = "\u{feff}";
+
+/// Decodes a provider response body into text.
+///
+/// Strips a leading UTF-8 byte order mark and replaces invalid UTF-8
+/// sequences with U+FFFD rather than failing, logging a warning when
+/// the body had to be repaired.
+///
+/// # Arguments
+///
+/// * `provider` - Provider name used in log messages
+/// * `bytes` - The raw response body
+///
+/// # Returns
+///
+/// * `Cow<str>` - The decoded body, borrowed when no repair was needed
+pub(crate) fn decode_body<'a>(provider: &str, bytes: &'a [u8]) -> Cow<'a, str> {
+    let text = String::from_utf8_lossy(bytes);
+    if let Cow::Owned(_) = text {
+        tracing::warn!("{} response contained invalid UTF-8, decoded lossily", provider);
+    }
+
+    match text {
+        Cow::Borrowed(s) => Cow::Borrowed(strip_bom(provider, s)),
+        Cow::Owned(s) => Cow::Owned(strip_bom(provider, &s).to_string()),
+    }
+}
+
+/// Strips a leading UTF-8 byte order mark, logging a warning if present.
+pub(crate) fn strip_bom<'a>(provider: &str, text: &'a str) -> &'a str {
+    match text.strip_prefix(UTF8_BOM) {
+        Some(stripped) => {
+            tracing::warn!("{} response started with a byte order mark, stripped", provider);
+            stripped
+        }
+        None => text,
+    }
+}
+
+/// Response headers providers use to identify a request for support.
+const REQUEST_ID_HEADERS: &[&str] = &["request-id", "x-request-id", "x-ds-trace-id", "x-trace-id"];
+
+/// Extracts the provider's request/trace ID from response headers.
+///
+/// # Arguments
+///
+/// * `headers` - The upstream response headers
+///
+/// # Returns
+///
+/// The first request ID header found, if any
+pub(crate) fn provider_request_id(headers: &HeaderMap) -> Option<String> {
+    REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Error details a provider reported in an error response body.
+#[derive(Debug, Default)]
+pub(crate) struct ProviderErrorBody {
+    /// The provider's error type, e.g. `rate_limit_exceeded`.
+    pub type_: Option<String>,
+    pub param: Option<String>,
+    pub code: Option<String>,
+}
+
+/// Extracts the error type, parameter, and code from a provider error body.
+///
+/// Understands the `{"error": {"type", "param", "code"}}` shape DeepSeek
+/// and Anthropic use. Bodies that aren't JSON, or lack these fields,
+/// yield empty details.
+///
+/// # Arguments
+///
+/// * `body` - The upstream error response body
+///
+/// # Returns
+///
+/// * `ProviderErrorBody` - The fields found, if any
+pub(crate) fn parse_error_body(body: &str) -> ProviderErrorBody {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return ProviderErrorBody::default();
+    };
+    let error = &value["error"];
+    let field = |name: &str| match &error[name] {
+        serde_json::Value::String(text) if !text.is_empty() => Some(text.clone()),
+        serde_json::Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    };
+
+    ProviderErrorBody {
+        type_: field("type"),
+        param: field("param"),
+        code: field("code"),
+    }
+}
+
+/// Formats an error together with its chain of sources.
+///
+/// reqwest's own message omits the underlying cause, so connection
+/// failures such as a TLS pin mismatch would otherwise be opaque.
+pub(crate) fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
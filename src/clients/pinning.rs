@@ -0,0 +1,319 @@
+//! Leaf certificate pinning for provider connections.
+//!
+//! When a pin is configured, the HTTP client still performs normal
+//! certificate chain validation, and additionally rejects any server whose
+//! leaf certificate's SHA-256 fingerprint differs from the pin.
+
+use crate::{
+    config::{AnswerProvider, Config},
+    error::{ApiError, Result},
+};
+use reqwest::Client;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    client::WebPkiServerVerifier,
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Verifies the certificate chain as usual, then checks the leaf
+/// certificate against a pinned SHA-256 fingerprint.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pin: [u8; 32],
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Sha256::digest(end_entity.as_ref());
+        if fingerprint.as_slice() != self.pin {
+            return Err(rustls::Error::General(format!(
+                "certificate pin mismatch for {}: expected sha256 {}, got {:x}",
+                server_name.to_str(),
+                hex(&self.pin),
+                fingerprint
+            )));
+        }
+
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// HTTP clients for each provider, pinned where configured.
+///
+/// Built once at startup and shared by every request, so connections are
+/// pooled per provider.
+#[derive(Debug, Clone)]
+pub struct ProviderHttp {
+    pub deepseek: Client,
+    pub gemini: Client,
+    pub anthropic: Client,
+}
+
+impl ProviderHttp {
+    /// Builds the provider clients from each provider's `pinned_cert_sha256`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::Internal` if a pin is invalid, as `http_client` does
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            deepseek: http_client("DeepSeek", config.deepseek.pinned_cert_sha256.as_deref())?,
+            gemini: http_client("Gemini", config.gemini.pinned_cert_sha256.as_deref())?,
+            anthropic: http_client("Anthropic", config.anthropic.pinned_cert_sha256.as_deref())?,
+        })
+    }
+
+    /// Returns the client for an answer provider.
+    pub fn answer(&self, provider: AnswerProvider) -> Client {
+        match provider {
+            AnswerProvider::Gemini => self.gemini.clone(),
+            AnswerProvider::Anthropic => self.anthropic.clone(),
+        }
+    }
+}
+
+/// Builds an HTTP client, pinning the leaf certificate if a pin is given.
+///
+/// # Arguments
+///
+/// * `provider` - Provider name, used in error messages
+/// * `pin` - Hex SHA-256 fingerprint of the expected leaf certificate.
+///   Colons and case are ignored.
+///
+/// # Returns
+///
+/// * `Result<Client>` - A default client when `pin` is None, otherwise a pinned client
+///
+/// # Errors
+///
+/// Returns `ApiError::Internal` if the pin is not a valid SHA-256
+/// fingerprint or the TLS configuration cannot be built.
+pub fn http_client(provider: &str, pin: Option<&str>) -> Result<Client> {
+    let Some(pin) = pin else {
+        return Ok(Client::new());
+    };
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    pinned_client(provider, pin, roots)
+}
+
+/// Builds a client that validates chains against `roots` and pins the
+/// leaf certificate, so tests can trust their own CA.
+fn pinned_client(provider: &str, pin: &str, roots: RootCertStore) -> Result<Client> {
+    let pin = parse_fingerprint(pin).ok_or_else(|| ApiError::Internal {
+        message: format!("Invalid {} pinned_cert_sha256: expected 64 hex characters", provider),
+    })?;
+
+    let tls_error = |e: &dyn std::fmt::Display| ApiError::Internal {
+        message: format!("Failed to build pinned TLS config for {}: {}", provider, e),
+    };
+
+    let crypto: Arc<CryptoProvider> = Arc::new(ring::default_provider());
+    let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), crypto.clone())
+        .build()
+        .map_err(|e| tls_error(&e))?;
+    let tls = ClientConfig::builder_with_provider(crypto)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| tls_error(&e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { inner, pin }))
+        .with_no_client_auth();
+
+    Client::builder()
+        .use_preconfigured_tls(tls)
+        .build()
+        .map_err(|e| tls_error(&e))
+}
+
+/// Parses a hex SHA-256 fingerprint, ignoring colons and case.
+fn parse_fingerprint(pin: &str) -> Option<[u8; 32]> {
+    let digits: Vec<u8> = pin.bytes().filter(|&b| b != b':').collect();
+    if digits.len() != 64 || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    let mut fingerprint = [0u8; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(fingerprint)
+}
+
+/// Formats bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clients::GeminiClient, models::{ApiConfig, Message, Role}};
+    use rustls::ServerConfig;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    // A test CA and a localhost leaf it signed, generated with the openssl CLI
+    const CA_PEM: &str = include_str!("../../tests/fixtures/tls/ca.pem");
+    const LEAF_PEM: &str = include_str!("../../tests/fixtures/tls/leaf.pem");
+    const LEAF_KEY: &str = include_str!("../../tests/fixtures/tls/leaf.key");
+
+    const GEMINI_BODY: &str =
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"pinned"}]},"finishReason":"STOP"}]}"#;
+
+    fn certs(pem: &str) -> Vec<CertificateDer<'static>> {
+        rustls_pemfile::certs(&mut pem.as_bytes())
+            .collect::<std::result::Result<_, _>>()
+            .unwrap()
+    }
+
+    fn test_roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        for cert in certs(CA_PEM) {
+            roots.add(cert).unwrap();
+        }
+        roots
+    }
+
+    fn leaf_pin() -> String {
+        hex(&Sha256::digest(certs(LEAF_PEM)[0].as_ref()))
+    }
+
+    /// Serves `body` as JSON over TLS with the test leaf certificate, and
+    /// returns the server's base URL.
+    async fn serve_tls(body: &'static str) -> String {
+        let key = rustls_pemfile::private_key(&mut LEAF_KEY.as_bytes()).unwrap().unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs(LEAF_PEM), key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // A rejected handshake is the expected outcome of the mismatch tests
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 4096];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        format!("https://localhost:{}", port)
+    }
+
+    #[tokio::test]
+    async fn matching_pin_connects() {
+        let url = serve_tls("{}").await;
+        let client = pinned_client("Test", &leaf_pin(), test_roots()).unwrap();
+
+        let response = client.get(&url).send().await.unwrap();
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn mismatched_pin_fails() {
+        let url = serve_tls("{}").await;
+        let client = pinned_client("Test", &"00".repeat(32), test_roots()).unwrap();
+
+        let error = client.get(&url).send().await.unwrap_err();
+
+        assert!(
+            crate::clients::error_chain(&error).contains("certificate pin mismatch"),
+            "unexpected error: {}",
+            crate::clients::error_chain(&error)
+        );
+    }
+
+    #[tokio::test]
+    async fn gemini_requests_use_the_pinned_client() {
+        let url = serve_tls(GEMINI_BODY).await;
+        let messages = vec![Message {
+            role: Role::User,
+            content: "ping".to_string().into(),
+        }];
+
+        let pinned = pinned_client("Gemini", &leaf_pin(), test_roots()).unwrap();
+        let response = GeminiClient::with_http_client("token".to_string(), pinned)
+            .with_base_url(Some(&url))
+            .chat(messages.clone(), &ApiConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(response.text(), "pinned");
+
+        let mismatched = pinned_client("Gemini", &"00".repeat(32), test_roots()).unwrap();
+        let error = GeminiClient::with_http_client("token".to_string(), mismatched)
+            .with_base_url(Some(&url))
+            .chat(messages, &ApiConfig::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("certificate pin mismatch"), "unexpected error: {}", error);
+    }
+
+    #[test]
+    fn invalid_pins_are_rejected() {
+        assert!(http_client("Gemini", Some("not hex")).is_err());
+        assert!(http_client("Gemini", Some(&"ab".repeat(31))).is_err());
+        assert!(http_client("Gemini", Some(&"AB:".repeat(32))).is_ok());
+    }
+}
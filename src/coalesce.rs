@@ -0,0 +1,91 @@
+//! Batching of streamed content deltas.
+//!
+//! Forwarding every upstream token as its own SSE event is costly for
+//! clients. With `stream_flush_interval_ms` set, consecutive deltas of the
+//! same type and kind are merged into one block, flushed when the interval
+//! elapses, when the block reaches `stream_flush_max_chars`, or when a delta
+//! of another type or kind arrives. An interval of 0 sends every delta as it comes.
+
+use crate::models::ContentBlock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Merges content deltas into fewer, larger blocks.
+#[derive(Debug)]
+pub struct DeltaCoalescer {
+    /// How long a delta may wait, or None when batching is disabled.
+    interval: Option<Duration>,
+    max_chars: usize,
+    pending: Option<ContentBlock>,
+    pending_chars: usize,
+    flush_at: Option<Instant>,
+}
+
+impl DeltaCoalescer {
+    /// Creates a coalescer.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval_ms` - Milliseconds a delta may be held back, or 0 to disable batching
+    /// * `max_chars` - Characters at which a batch is flushed early
+    pub fn new(interval_ms: u64, max_chars: usize) -> Self {
+        Self {
+            interval: (interval_ms > 0).then(|| Duration::from_millis(interval_ms)),
+            max_chars: max_chars.max(1),
+            pending: None,
+            pending_chars: 0,
+            flush_at: None,
+        }
+    }
+
+    /// Adds a delta.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<ContentBlock>` - Blocks that are ready to send, in order
+    pub fn push(&mut self, block: ContentBlock) -> Vec<ContentBlock> {
+        let Some(interval) = self.interval else {
+            return vec![block];
+        };
+
+        // A delta of another type or kind closes the current batch
+        let mut ready = Vec::new();
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.content_type != block.content_type || pending.kind != block.kind)
+        {
+            ready.extend(self.flush());
+        }
+
+        self.pending_chars += block.text.chars().count();
+        match &mut self.pending {
+            Some(pending) => pending.text.push_str(&block.text),
+            None => {
+                self.pending = Some(block);
+                self.flush_at = Some(Instant::now() + interval);
+            }
+        }
+
+        if self.pending_chars >= self.max_chars {
+            ready.extend(self.flush());
+        }
+        ready
+    }
+
+    /// Takes the pending batch, if any.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<ContentBlock>` - The pending block, or nothing
+    pub fn flush(&mut self) -> Vec<ContentBlock> {
+        self.pending_chars = 0;
+        self.flush_at = None;
+        self.pending.take().into_iter().collect()
+    }
+
+    /// Returns when the pending batch is due, or None if nothing is pending.
+    pub fn flush_at(&self) -> Option<Instant> {
+        self.flush_at
+    }
+}
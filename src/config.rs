@@ -5,7 +5,7 @@
 //! AI model providers and server settings.
 
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 /// Root configuration structure containing all application settings.
 ///
@@ -15,6 +15,106 @@ use std::path::Path;
 pub struct Config {
     pub server: ServerConfig,
     pub pricing: PricingConfig,
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub cost_format: CostFormat,
+    #[serde(default)]
+    pub deepseek: ProviderConnectionConfig,
+    #[serde(default)]
+    pub gemini: GeminiConfig,
+    #[serde(default)]
+    pub anthropic: ProviderConnectionConfig,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    #[serde(default)]
+    pub resume: ResumeConfig,
+    #[serde(default)]
+    pub usage_log: UsageLogConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub mock: MockConfig,
+    #[serde(default)]
+    pub errors: ErrorsConfig,
+    #[serde(default)]
+    pub abuse: AbuseConfig,
+
+    /// SSE event names to send in place of the built-in ones, keyed by the
+    /// built-in name, e.g. `content = "message"`. Only the SSE `event`
+    /// field changes; the `type` in each payload keeps the built-in name.
+    #[serde(default)]
+    pub sse_event_names: HashMap<String, String>,
+}
+
+/// Built-in names of the events sent in a stream.
+const SSE_EVENT_NAMES: &[&str] = &[
+    "start",
+    "content",
+    "usage",
+    "reasoning_usage",
+    "citations",
+    "truncated",
+    "final_message",
+    "done",
+    "cancelled",
+    "error",
+];
+
+/// Connection settings for a single upstream provider.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ProviderConnectionConfig {
+    /// Hex SHA-256 fingerprint of the provider's leaf TLS certificate.
+    /// When set, connections presenting any other certificate fail.
+    pub pinned_cert_sha256: Option<String>,
+
+    /// Base URL of the provider's API, e.g. a proxy or an OpenAI-compatible
+    /// mirror. The provider's public endpoint is used when unset.
+    pub base_url: Option<String>,
+
+    #[serde(flatten)]
+    pub models: ModelRules,
+}
+
+/// Which models clients may select for a provider.
+///
+/// An empty `allowed_models` allows every model not in `denied_models`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ModelRules {
+    pub allowed_models: Vec<String>,
+    pub denied_models: Vec<String>,
+}
+
+impl ModelRules {
+    /// Returns true if clients may select `model`.
+    pub fn allows(&self, model: &str) -> bool {
+        (self.allowed_models.is_empty() || self.allowed_models.iter().any(|m| m == model))
+            && !self.denied_models.iter().any(|m| m == model)
+    }
+}
+
+/// Gemini connection and request defaults.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct GeminiConfig {
+    /// Hex SHA-256 fingerprint of Gemini's leaf TLS certificate.
+    /// When set, connections presenting any other certificate fail.
+    pub pinned_cert_sha256: Option<String>,
+
+    /// Base URL of the Gemini API, e.g. a proxy. Google's public endpoint
+    /// is used when unset.
+    pub base_url: Option<String>,
+
+    /// Default safety thresholds by harm category, sent with every Gemini
+    /// request. Per-request `gemini_config.safety_settings` take precedence.
+    pub safety_settings: HashMap<String, String>,
+
+    #[serde(flatten)]
+    pub models: ModelRules,
 }
 
 /// Server-specific configuration settings.
@@ -25,6 +125,633 @@ pub struct Config {
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+
+    /// Capacity of the per-request channel between the streaming task and
+    /// the SSE response. When the buffer is full, sends wait for the client
+    /// to consume events, which in turn pauses upstream polling; events are
+    /// never dropped.
+    #[serde(default = "default_stream_channel_buffer")]
+    pub stream_channel_buffer: usize,
+
+    /// Milliseconds streamed reasoning and answer deltas may be held back
+    /// to be sent together in one `content` event. 0 sends each delta as
+    /// it arrives.
+    #[serde(default)]
+    pub stream_flush_interval_ms: u64,
+
+    /// Characters of batched deltas at which they are sent without
+    /// waiting for the flush interval.
+    #[serde(default = "default_stream_flush_max_chars")]
+    pub stream_flush_max_chars: usize,
+
+    /// Stream only `content`, `usage`, and `error` events unless a request
+    /// sets `minimal_events` itself. Clients must then treat the stream
+    /// closing, rather than a `done` event, as the end of the response.
+    #[serde(default)]
+    pub minimal_events: bool,
+
+    /// Seconds in-flight streams may keep running after a shutdown
+    /// signal before they are cut off.
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+
+    /// Maximum number of chat requests processed at once across the
+    /// whole server. Requests beyond the limit are queued, or rejected
+    /// with 503 once the queue is full. Unlimited when unset.
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Maximum number of requests waiting for a slot when the server is
+    /// at capacity. 0 rejects them immediately.
+    #[serde(default)]
+    pub max_queue_depth: usize,
+
+    /// Milliseconds a queued request waits for a slot before it is
+    /// rejected with 503.
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+
+    /// Add an `X-Reasoning-SHA256` header to non-streaming responses so
+    /// clients can verify the returned reasoning wasn't altered in transit.
+    #[serde(default)]
+    pub reasoning_hash_header: bool,
+
+    /// Compress responses with gzip or brotli when the client's
+    /// `Accept-Encoding` allows it. SSE streams are never compressed.
+    #[serde(default)]
+    pub compression_enabled: bool,
+
+    /// Token required in the `X-Admin-Token` header by admin endpoints
+    /// such as pricing reload. Admin endpoints are disabled when unset.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    /// Keys callers must present as `Authorization: Bearer <key>` to use
+    /// the API endpoints. The API is open when empty. Admin endpoints are
+    /// guarded by `admin_token` instead.
+    #[serde(default)]
+    pub service_api_keys: Vec<String>,
+
+    /// Paths that don't require a service API key, such as health checks.
+    #[serde(default)]
+    pub auth_exempt_paths: Vec<String>,
+
+    /// Maximum number of requests from a single batch processed at once.
+    #[serde(default = "default_batch_max_concurrency")]
+    pub batch_max_concurrency: usize,
+
+    /// Maximum number of requests in a single batch.
+    #[serde(default = "default_batch_max_requests")]
+    pub batch_max_requests: usize,
+
+    /// Maximum request body size in bytes. Larger bodies are rejected
+    /// with 413 before they are parsed.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+
+    /// Maximum number of messages in a single chat request.
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+
+    /// Maximum characters of text in a single message, including the
+    /// system prompt. Unlimited when unset.
+    #[serde(default)]
+    pub max_message_chars: Option<usize>,
+
+    /// Run the startup self-test (as with `--self-test`) and refuse to
+    /// start if it fails.
+    #[serde(default)]
+    pub self_test_on_start: bool,
+}
+
+fn default_stream_channel_buffer() -> usize {
+    100
+}
+
+fn default_stream_flush_max_chars() -> usize {
+    512
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_batch_max_concurrency() -> usize {
+    4
+}
+
+fn default_batch_max_requests() -> usize {
+    100
+}
+
+fn default_max_body_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_max_messages() -> usize {
+    1000
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+/// How costs are rendered in usage statistics.
+///
+/// Prices are configured in US dollars; `exchange_rate` converts reported
+/// costs into another currency. The defaults render `$0.012`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CostFormat {
+    pub symbol: String,
+    pub position: SymbolPosition,
+    pub decimal_places: usize,
+    /// Multiplier applied to dollar costs before formatting.
+    pub exchange_rate: f64,
+}
+
+impl Default for CostFormat {
+    fn default() -> Self {
+        Self {
+            symbol: "$".to_string(),
+            position: SymbolPosition::Prefix,
+            decimal_places: 3,
+            exchange_rate: 1.0,
+        }
+    }
+}
+
+/// Placement of the currency symbol relative to the amount.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolPosition {
+    #[default]
+    Prefix,
+    Suffix,
+}
+
+/// Cross-origin resource sharing configuration for browser clients.
+///
+/// A `"*"` entry in any list allows everything (development mode);
+/// otherwise only the listed values are allowed (production allowlist).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Response headers browsers may read, e.g. `X-Reasoning-SHA256`.
+    pub expose_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "OPTIONS".to_string()],
+            allowed_headers: vec![
+                "Content-Type".to_string(),
+                "X-DeepSeek-API-Token".to_string(),
+                "X-Gemini-API-Token".to_string(),
+                "X-Anthropic-API-Token".to_string(),
+                "Last-Event-ID".to_string(),
+            ],
+            expose_headers: vec!["X-Reasoning-SHA256".to_string(), "Retry-After".to_string()],
+        }
+    }
+}
+
+/// Input screening applied before any upstream call.
+///
+/// Keywords match as case-insensitive substrings; patterns are
+/// case-insensitive regular expressions. Empty lists disable screening.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ModerationConfig {
+    pub keywords: Vec<String>,
+    pub patterns: Vec<String>,
+    /// Tokens that skip screening when sent in the `X-Moderation-Bypass`
+    /// header, for trusted internal callers.
+    pub bypass_tokens: Vec<String>,
+}
+
+/// Resumable stream settings.
+///
+/// When enabled, each stream's events are buffered so a client that loses
+/// its connection can reconnect with `Last-Event-ID` and continue. Memory
+/// is bounded by `max_streams` x `max_events_per_stream` events; see
+/// `replay` for the eviction rules.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ResumeConfig {
+    pub enabled: bool,
+    /// Seconds a finished stream stays resumable.
+    pub ttl_secs: u64,
+    /// Maximum number of streams buffered at once.
+    pub max_streams: usize,
+    /// Maximum events kept per stream; the oldest are dropped beyond this.
+    pub max_events_per_stream: usize,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_secs: 300,
+            max_streams: 1000,
+            max_events_per_stream: 10_000,
+        }
+    }
+}
+
+/// Where usage records are written.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageSinkKind {
+    /// Usage records are not written.
+    #[default]
+    None,
+    /// One JSON line per record on stdout.
+    Stdout,
+    /// One JSON line per record, appended to `path`.
+    File,
+}
+
+/// Usage record settings, for billing and analytics.
+///
+/// A record is written for every completed request, streaming or not.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct UsageLogConfig {
+    pub sink: UsageSinkKind,
+    /// File records are appended to when `sink = "file"`.
+    pub path: String,
+}
+
+impl Default for UsageLogConfig {
+    fn default() -> Self {
+        Self {
+            sink: UsageSinkKind::None,
+            path: "usage.jsonl".to_string(),
+        }
+    }
+}
+
+/// Distributed tracing settings.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// OTLP collector that request and upstream call spans are exported
+    /// to, e.g. `http://localhost:4317`. Requires the `otel` feature;
+    /// spans are only logged locally when unset.
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Canned provider output used when `pipeline.provider_mode` is `mock`.
+///
+/// Streams split each text into deltas of `chunk_chars` characters, sent
+/// `chunk_delay_ms` apart. Usage is estimated from the text lengths.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MockConfig {
+    /// Reasoning returned by the mock DeepSeek client.
+    pub reasoning: String,
+    /// Answer returned by the mock answer client.
+    pub answer: String,
+    /// Characters per streamed delta.
+    pub chunk_chars: usize,
+    /// Milliseconds between streamed deltas.
+    pub chunk_delay_ms: u64,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            reasoning: "The user asked a question. I will think it through step by step and then answer."
+                .to_string(),
+            answer: "This is a mock answer.".to_string(),
+            chunk_chars: 8,
+            chunk_delay_ms: 20,
+        }
+    }
+}
+
+/// Per-client request logging and abuse detection.
+///
+/// Clients are identified by a hash of the credential they send, or by IP
+/// address; credentials are never logged in plaintext.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AbuseConfig {
+    /// Log each request's client, request and response sizes, and token
+    /// counts, and count each client's requests.
+    pub enabled: bool,
+    /// Length of the sliding window requests are counted over.
+    pub window_secs: u64,
+    /// Requests per window above which a client is logged at warning
+    /// level. No threshold when unset.
+    pub abuse_threshold: Option<usize>,
+    /// Reject requests over the threshold with 429 instead of only
+    /// logging them.
+    pub reject_over_threshold: bool,
+}
+
+impl Default for AbuseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_secs: 60,
+            abuse_threshold: None,
+            reject_over_threshold: false,
+        }
+    }
+}
+
+/// How errors reported by the providers are surfaced to clients.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ErrorsConfig {
+    /// Status and normalized `error.type` for each provider error type,
+    /// such as DeepSeek's `rate_limit_exceeded`. Entries override the
+    /// built-in mappings for rate limit, authentication, permission,
+    /// invalid request, and overload errors. Unmapped provider errors are
+    /// reported as 400 with a provider-prefixed type.
+    pub provider_errors: HashMap<String, ProviderErrorMapping>,
+}
+
+/// Status and normalized type reported for one provider error type.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ProviderErrorMapping {
+    /// HTTP status code, 400 to 599.
+    pub status: u16,
+    /// Value of `error.type` in the error body.
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+/// Whether upstream providers are actually called.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderMode {
+    /// Requests go to the provider APIs.
+    #[default]
+    Live,
+    /// Providers are replaced by the canned output in `[mock]`; no API
+    /// tokens are needed and nothing is spent.
+    Mock,
+}
+
+/// Reasoning/answer pipeline configuration.
+///
+/// Controls how the handlers move data between the reasoning model
+/// and the answer model.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Whether providers are called or replaced by canned output, for
+    /// tests and demos without API keys.
+    pub provider_mode: ProviderMode,
+
+    /// Fail the request if DeepSeek returns no `reasoning_content`.
+    /// When false, regular content is used as the reasoning, or the
+    /// thinking block is skipped entirely if there is none.
+    pub require_reasoning: bool,
+
+    /// Minimum number of characters of reasoning DeepSeek must produce.
+    pub min_reasoning_chars: Option<usize>,
+
+    /// What to do when reasoning is shorter than `min_reasoning_chars`.
+    /// Streaming requests always error, since the reasoning has already
+    /// been sent to the client.
+    pub short_reasoning_action: ShortReasoningAction,
+
+    /// Number of DeepSeek retries when `short_reasoning_action` is `retry`.
+    pub short_reasoning_retries: u32,
+
+    /// Provider used for the post-reasoning answer call.
+    pub answer_provider: AnswerProvider,
+
+    /// Provider the answer call is handed to when `answer_provider` fails
+    /// with a retryable error, so the reasoning isn't wasted. Used only
+    /// for requests that also send the fallback provider's token. Streams
+    /// fall back only if no answer content was sent yet.
+    pub fallback_answer_provider: Option<AnswerProvider>,
+
+    /// Skip the responder when the reasoning already ends with a line
+    /// starting `Final answer:`, returning that answer directly.
+    pub short_circuit_if_reasoning_answers: bool,
+
+    /// Maximum number of reasoning results kept for reuse by identical
+    /// non-streaming requests. Zero disables the cache.
+    pub reasoning_cache_size: usize,
+
+    /// Seconds a cached reasoning result stays valid. Never expires when unset.
+    pub reasoning_cache_ttl_secs: Option<u64>,
+
+    /// Output tokens assumed for each phase when estimating dry-run cost.
+    /// Defaults to 1024 when unset.
+    pub dry_run_output_tokens: Option<u32>,
+
+    /// Seconds Gemini may take to produce its first content chunk after
+    /// the reasoning phase ends, when streaming. Independent of any
+    /// per-chunk or overall request timeout.
+    pub gemini_first_token_timeout_secs: Option<u64>,
+
+    /// Times a non-streaming answer call is repeated when it returns a
+    /// blank answer that wasn't blocked for safety. 0 disables retries.
+    pub retry_on_empty_answer: u32,
+
+    /// Milliseconds between running usage estimates sent in a stream,
+    /// flagged `estimated`. While DeepSeek is reasoning the answer
+    /// provider's usage covers only finished pipelined drafts, if any;
+    /// during the answer, the reasoning usage is final and the answer's
+    /// output is estimated from the text so far.
+    /// The last `usage` event carries the provider-reported counts.
+    /// Disabled when unset or zero.
+    pub usage_update_interval_ms: Option<u64>,
+
+    /// Share of an `X-Request-Deadline-Ms` budget, between 0 and 1, that
+    /// the reasoning phase may use. The answer phase gets the rest.
+    /// Defaults to 0.6.
+    pub deadline_reasoning_share: Option<f64>,
+
+    /// Text prepended to every system prompt, for org-wide guardrails the
+    /// client can't omit. Used as the whole system prompt when the client
+    /// sends none. Both the reasoner and the responder see the result.
+    pub system_prompt_prefix: Option<String>,
+
+    /// Text appended to every system prompt, after the client's prompt.
+    pub system_prompt_suffix: Option<String>,
+
+    /// System prompt used when the client sends none. It stands in for the
+    /// client's prompt, so the prefix and suffix still wrap it.
+    pub default_system_prompt: Option<String>,
+
+    /// Apply `default_system_prompt` when the client's system prompt is
+    /// empty, instead of treating an empty prompt as deliberately blank.
+    pub treat_empty_system_prompt_as_missing: bool,
+
+    /// Example turns inserted after the system prompt and before the
+    /// client's messages, so both the reasoner and the responder see them
+    /// and their tokens are billed with the request. Must be user and
+    /// assistant messages, ending with an assistant message. They sit
+    /// before the conversation so they stay in the cacheable prompt prefix.
+    pub few_shot_examples: Vec<crate::models::Message>,
+
+    /// When a request's `max_tokens` exceeds the model's
+    /// `max_output_tokens`, lower it to the limit with a logged warning
+    /// instead of rejecting the request.
+    pub clamp_max_tokens: bool,
+
+    /// How much of the reasoning is forwarded to the answer model, unless
+    /// the request sets `reasoning_to_answer`.
+    pub reasoning_to_answer: ReasoningToAnswer,
+
+    /// Where the forwarded reasoning goes in the answer model's prompt:
+    /// an assistant turn, the last user turn, or the system prompt.
+    pub reasoning_injection: ReasoningInjection,
+
+    /// Characters of reasoning kept in `summary` mode. Defaults to 2000.
+    pub reasoning_summary_chars: Option<usize>,
+
+    /// New reasoning characters between the draft answers of a request
+    /// with `pipelined_answer`. Defaults to 2000.
+    pub pipelined_draft_interval_chars: Option<usize>,
+
+    /// How reasoning is rendered in response content.
+    pub thinking_render_mode: ThinkingRenderMode,
+
+    /// Caps on the reasoning and answer text returned to clients. Requests
+    /// may set lower caps with `max_response_chars`.
+    pub max_response_chars: ResponseCharLimits,
+}
+
+/// Maximum characters of reasoning and of answer returned to a client.
+///
+/// Content beyond a limit is cut off and the response is flagged as
+/// truncated. Each limit is independent; unset means unlimited. The
+/// answer model still receives the reasoning as configured by
+/// `reasoning_to_answer`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ResponseCharLimits {
+    pub reasoning: Option<usize>,
+    pub answer: Option<usize>,
+}
+
+impl ResponseCharLimits {
+    /// Combines two sets of limits, keeping the lower of each.
+    pub fn min(self, other: Self) -> Self {
+        let lower = |a: Option<usize>, b: Option<usize>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Self {
+            reasoning: lower(self.reasoning, other.reasoning),
+            answer: lower(self.answer, other.answer),
+        }
+    }
+}
+
+/// How reasoning is rendered in response content.
+///
+/// Applies to the `<thinking>` markers and reasoning of both streaming and
+/// non-streaming responses. JSON mode and interleaved streams never use
+/// markers.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkingRenderMode {
+    /// Reasoning wrapped in `<thinking>` tags.
+    #[default]
+    Tags,
+    /// Reasoning under a bold "Reasoning" heading, separated from the
+    /// answer by a horizontal rule.
+    Markdown,
+    /// Reasoning left out of the content entirely.
+    Hidden,
+}
+
+impl ThinkingRenderMode {
+    /// Returns the text placed before and after the reasoning, or None if
+    /// reasoning is hidden.
+    pub fn markers(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            ThinkingRenderMode::Tags => Some(("<thinking>\n", "\n</thinking>")),
+            ThinkingRenderMode::Markdown => Some(("**Reasoning**\n\n", "\n\n---")),
+            ThinkingRenderMode::Hidden => None,
+        }
+    }
+}
+
+/// How much of DeepSeek's reasoning is sent to the answer model.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningToAnswer {
+    /// The complete reasoning.
+    #[default]
+    Full,
+    /// Only the end of the reasoning, where its conclusions are.
+    Summary,
+    /// No reasoning; the answer model sees only the original messages.
+    None,
+}
+
+/// Where the forwarded reasoning is placed in the answer model's prompt.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningInjection {
+    /// A final assistant turn. Some models read this as an answer they
+    /// have already started.
+    #[default]
+    Assistant,
+    /// Appended to the last user turn as additional context.
+    User,
+    /// Appended to the system prompt.
+    System,
+}
+
+/// Provider that produces the final answer after reasoning.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerProvider {
+    #[default]
+    Gemini,
+    Anthropic,
+}
+
+impl AnswerProvider {
+    /// Returns the model used when the request does not select one.
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            AnswerProvider::Gemini => crate::clients::gemini::DEFAULT_MODEL,
+            AnswerProvider::Anthropic => crate::clients::anthropic::DEFAULT_MODEL,
+        }
+    }
+
+    /// Returns the request header carrying this provider's API token.
+    pub fn token_header(&self) -> &'static str {
+        match self {
+            AnswerProvider::Gemini => "X-Gemini-API-Token",
+            AnswerProvider::Anthropic => "X-Anthropic-API-Token",
+        }
+    }
+
+    /// Returns the provider's display name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnswerProvider::Gemini => "Gemini",
+            AnswerProvider::Anthropic => "Anthropic",
+        }
+    }
+}
+
+/// Action taken when DeepSeek's reasoning is too short.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortReasoningAction {
+    #[default]
+    Error,
+    Retry,
 }
 
 /// Pricing configuration for all supported AI models.
@@ -35,6 +762,8 @@ pub struct ServerConfig {
 pub struct PricingConfig {
     pub deepseek: DeepSeekPricing,
     pub gemini: GeminiPricing,
+    #[serde(default)]
+    pub anthropic: AnthropicPricing,
 }
 
 /// DeepSeek-specific pricing configuration.
@@ -46,6 +775,79 @@ pub struct DeepSeekPricing {
     pub input_cache_hit_price: f64,   // per million tokens
     pub input_cache_miss_price: f64,  // per million tokens
     pub output_price: f64,            // per million tokens
+
+    /// Maximum output tokens the model accepts, if known.
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+
+    /// Whether reasoning tokens are billed on top of the reported
+    /// completion tokens, at the output rate. Leave false when completion
+    /// tokens already include reasoning, as in DeepSeek's API.
+    #[serde(default)]
+    pub reasoning_billed_separately: bool,
+
+    /// Kind and pricing of other DeepSeek models, keyed by model name.
+    /// Unlisted models use the rates above.
+    #[serde(default)]
+    pub models: HashMap<String, DeepSeekModelPricing>,
+}
+
+/// Whether a DeepSeek model produces separate reasoning content.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeepSeekModelKind {
+    /// Streams `reasoning_content` before its answer, like `deepseek-reasoner`.
+    #[default]
+    Reasoner,
+    /// Produces only regular content, like `deepseek-chat`. Its output is
+    /// passed to the answer model as the reasoning.
+    Chat,
+}
+
+/// Kind and pricing of a specific DeepSeek model.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeepSeekModelPricing {
+    #[serde(default)]
+    pub kind: DeepSeekModelKind,
+    pub input_cache_hit_price: f64,   // per million tokens
+    pub input_cache_miss_price: f64,  // per million tokens
+    pub output_price: f64,            // per million tokens
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+}
+
+impl DeepSeekPricing {
+    /// Returns the kind of the given model.
+    ///
+    /// Unlisted models are treated as chat models if their name contains
+    /// `chat`, and as reasoners otherwise.
+    pub fn kind(&self, model: &str) -> DeepSeekModelKind {
+        match self.models.get(model) {
+            Some(pricing) => pricing.kind,
+            None if model.contains("chat") => DeepSeekModelKind::Chat,
+            None => DeepSeekModelKind::Reasoner,
+        }
+    }
+
+    /// Returns the (cache hit, cache miss, output) prices for the given model.
+    pub fn rates(&self, model: &str) -> (f64, f64, f64) {
+        match self.models.get(model) {
+            Some(pricing) => (
+                pricing.input_cache_hit_price,
+                pricing.input_cache_miss_price,
+                pricing.output_price,
+            ),
+            None => (self.input_cache_hit_price, self.input_cache_miss_price, self.output_price),
+        }
+    }
+
+    /// Returns the maximum output tokens of the given model, if known.
+    pub fn max_output_tokens(&self, model: &str) -> Option<u32> {
+        match self.models.get(model) {
+            Some(pricing) => pricing.max_output_tokens,
+            None => self.max_output_tokens,
+        }
+    }
 }
 
 /// Gemini-specific pricing configuration.
@@ -55,6 +857,71 @@ pub struct DeepSeekPricing {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct GeminiPricing {
     pub gemini_pro: ModelPricing,
+
+    /// Pricing and output limits of other Gemini models, keyed by model
+    /// name. Unlisted models use `gemini_pro`.
+    #[serde(default)]
+    pub models: HashMap<String, ModelPricing>,
+}
+
+impl GeminiPricing {
+    /// Returns the pricing for the given model name.
+    pub fn for_model(&self, model: &str) -> &ModelPricing {
+        self.models.get(model).unwrap_or(&self.gemini_pro)
+    }
+}
+
+/// Anthropic-specific pricing configuration.
+///
+/// Contains pricing information for the Claude model families.
+/// Models are matched by family name (`haiku`, `opus`), with
+/// everything else priced as Sonnet.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnthropicPricing {
+    pub claude_3_sonnet: ModelPricing,
+    pub claude_3_haiku: ModelPricing,
+    pub claude_3_opus: ModelPricing,
+}
+
+impl AnthropicPricing {
+    /// Returns the pricing for the given model name.
+    pub fn for_model(&self, model: &str) -> &ModelPricing {
+        if model.contains("haiku") {
+            &self.claude_3_haiku
+        } else if model.contains("opus") {
+            &self.claude_3_opus
+        } else {
+            &self.claude_3_sonnet
+        }
+    }
+}
+
+impl Default for AnthropicPricing {
+    fn default() -> Self {
+        Self {
+            claude_3_sonnet: ModelPricing {
+                input_price: 3.0,
+                output_price: 15.0,
+                cache_write_price: 3.75,
+                cache_read_price: 0.30,
+                max_output_tokens: Some(8192),
+            },
+            claude_3_haiku: ModelPricing {
+                input_price: 0.80,
+                output_price: 4.0,
+                cache_write_price: 1.0,
+                cache_read_price: 0.08,
+                max_output_tokens: Some(8192),
+            },
+            claude_3_opus: ModelPricing {
+                input_price: 15.0,
+                output_price: 75.0,
+                cache_write_price: 18.75,
+                cache_read_price: 1.50,
+                max_output_tokens: Some(4096),
+            },
+        }
+    }
 }
 
 /// Generic model pricing configuration.
@@ -67,6 +934,10 @@ pub struct ModelPricing {
     pub output_price: f64,            // per million tokens
     pub cache_write_price: f64,       // per million tokens
     pub cache_read_price: f64,        // per million tokens
+
+    /// Maximum output tokens the model accepts, if known.
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
 }
 
 impl Config {
@@ -93,6 +964,73 @@ impl Config {
 
         Ok(config.try_deserialize()?)
     }
+
+    /// Checks settings that deserialization alone can't validate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a provider base URL is not an absolute
+    /// `http` or `https` URL, or if the fallback answer provider is the
+    /// primary one
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, base_url) in [
+            ("deepseek.base_url", &self.deepseek.base_url),
+            ("gemini.base_url", &self.gemini.base_url),
+            ("anthropic.base_url", &self.anthropic.base_url),
+            ("telemetry.otlp_endpoint", &self.telemetry.otlp_endpoint),
+        ] {
+            if let Some(base_url) = base_url {
+                let url = reqwest::Url::parse(base_url)
+                    .map_err(|e| anyhow::anyhow!("{} {:?} is not a valid URL: {}", name, base_url, e))?;
+                if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+                    anyhow::bail!("{} {:?} must be an http or https URL", name, base_url);
+                }
+            }
+        }
+        if self
+            .pipeline
+            .few_shot_examples
+            .iter()
+            .any(|example| matches!(example.role, crate::models::Role::System))
+        {
+            anyhow::bail!("pipeline.few_shot_examples must not contain system messages");
+        }
+        if self
+            .pipeline
+            .few_shot_examples
+            .last()
+            .is_some_and(|example| !matches!(example.role, crate::models::Role::Assistant))
+        {
+            anyhow::bail!("pipeline.few_shot_examples must end with an assistant message");
+        }
+        if self.pipeline.pipelined_draft_interval_chars == Some(0) {
+            anyhow::bail!("pipeline.pipelined_draft_interval_chars must be greater than 0");
+        }
+        for (event, name) in &self.sse_event_names {
+            if !SSE_EVENT_NAMES.contains(&event.as_str()) {
+                anyhow::bail!("sse_event_names.{} is not a stream event", event);
+            }
+            if name.is_empty() || name.contains(['\r', '\n']) {
+                anyhow::bail!("sse_event_names.{} must be non-empty and on one line", event);
+            }
+        }
+        if self.abuse.enabled && self.abuse.window_secs == 0 {
+            anyhow::bail!("abuse.window_secs must be greater than 0");
+        }
+        for (provider_type, mapping) in &self.errors.provider_errors {
+            if !(400..=599).contains(&mapping.status) {
+                anyhow::bail!(
+                    "errors.provider_errors.{} status {} must be between 400 and 599",
+                    provider_type,
+                    mapping.status
+                );
+            }
+        }
+        if self.pipeline.fallback_answer_provider == Some(self.pipeline.answer_provider) {
+            anyhow::bail!("pipeline.fallback_answer_provider must differ from pipeline.answer_provider");
+        }
+        Ok(())
+    }
 }
 
 /// Provides default configuration values.
@@ -105,12 +1043,43 @@ impl Default for Config {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 3000,
+                stream_channel_buffer: default_stream_channel_buffer(),
+                stream_flush_interval_ms: 0,
+                stream_flush_max_chars: default_stream_flush_max_chars(),
+                minimal_events: false,
+                shutdown_grace_secs: default_shutdown_grace_secs(),
+                max_concurrent_requests: None,
+                max_queue_depth: 0,
+                queue_timeout_ms: default_queue_timeout_ms(),
+                reasoning_hash_header: false,
+                compression_enabled: false,
+                admin_token: None,
+                service_api_keys: Vec::new(),
+                auth_exempt_paths: Vec::new(),
+                batch_max_concurrency: default_batch_max_concurrency(),
+                batch_max_requests: default_batch_max_requests(),
+                max_body_bytes: default_max_body_bytes(),
+                max_messages: default_max_messages(),
+                max_message_chars: None,
+                self_test_on_start: false,
             },
             pricing: PricingConfig {
                 deepseek: DeepSeekPricing {
                     input_cache_hit_price: 0.14,
                     input_cache_miss_price: 0.55,
                     output_price: 2.19,
+                    max_output_tokens: Some(8192),
+                    reasoning_billed_separately: false,
+                    models: HashMap::from([(
+                        "deepseek-chat".to_string(),
+                        DeepSeekModelPricing {
+                            kind: DeepSeekModelKind::Chat,
+                            input_cache_hit_price: 0.07,
+                            input_cache_miss_price: 0.27,
+                            output_price: 1.10,
+                            max_output_tokens: Some(8192),
+                        },
+                    )]),
                 },
                 gemini: GeminiPricing {
                     gemini_pro: ModelPricing {
@@ -118,9 +1087,26 @@ impl Default for Config {
                         output_price: 0.375, // $0.375 per million tokens
                         cache_write_price: 0.0,
                         cache_read_price: 0.0,
+                        max_output_tokens: Some(8192),
                     },
+                    models: HashMap::new(),
                 },
+                anthropic: AnthropicPricing::default(),
             },
+            pipeline: PipelineConfig::default(),
+            cors: CorsConfig::default(),
+            moderation: ModerationConfig::default(),
+            resume: ResumeConfig::default(),
+            usage_log: UsageLogConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            mock: MockConfig::default(),
+            errors: ErrorsConfig::default(),
+            abuse: AbuseConfig::default(),
+            sse_event_names: HashMap::new(),
+            cost_format: CostFormat::default(),
+            deepseek: ProviderConnectionConfig::default(),
+            gemini: GeminiConfig::default(),
+            anthropic: ProviderConnectionConfig::default(),
         }
     }
 }
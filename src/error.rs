@@ -7,12 +7,13 @@
 //! - Type aliases for common Result types
 
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response, sse::Event},
     Json,
 };
+use crate::config::ProviderErrorMapping;
 use serde::{Deserialize, Serialize};
-use std::convert::Infallible;
+use std::{collections::HashMap, convert::Infallible};
 use thiserror::Error;
 use tokio_stream::wrappers::ReceiverStream;
 
@@ -22,18 +23,20 @@ use tokio_stream::wrappers::ReceiverStream;
 /// returned by the API endpoints.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
-    pub error: ErrorDetails,
+    pub error: ErrorPayload,
 }
 
 /// Detailed error information included in error responses.
 ///
-/// Contains specific information about what went wrong, including:
+/// This is the canonical error shape: non-streaming responses wrap it in
+/// `ErrorResponse`, and streaming `error` events carry it under `error`.
+/// It contains specific information about what went wrong, including:
 /// - A human-readable error message
 /// - The type of error that occurred
 /// - Optional parameter that caused the error
 /// - Optional error code for more specific error handling
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorDetails {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorPayload {
     pub message: String,
     #[serde(rename = "type")]
     pub type_: String,
@@ -41,6 +44,40 @@ pub struct ErrorDetails {
     pub param: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<String>,
+    /// Upstream provider the error came from (`deepseek`, `gemini`, or
+    /// `anthropic`), if it came from one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_request_id: Option<String>,
+}
+
+/// Built-in status and normalized type for common provider error types.
+/// Entries in `[errors.provider_errors]` take precedence.
+const DEFAULT_PROVIDER_ERRORS: &[(&str, u16, &str)] = &[
+    ("rate_limit_exceeded", 429, "rate_limited"),
+    ("rate_limit_error", 429, "rate_limited"),
+    ("authentication_error", 401, "authentication_failed"),
+    ("invalid_api_key", 401, "authentication_failed"),
+    ("permission_error", 403, "permission_denied"),
+    ("invalid_request_error", 400, "invalid_request"),
+    ("overloaded_error", 503, "provider_overloaded"),
+];
+
+/// Looks up the status and normalized type for a provider error type,
+/// preferring the configured `mappings` over the built-in ones.
+fn provider_error_mapping(
+    type_: &str,
+    mappings: &HashMap<String, ProviderErrorMapping>,
+) -> Option<(StatusCode, String)> {
+    if let Some(mapping) = mappings.get(type_) {
+        let status = StatusCode::from_u16(mapping.status).ok()?;
+        return Some((status, mapping.type_.clone()));
+    }
+    DEFAULT_PROVIDER_ERRORS
+        .iter()
+        .find(|(name, _, _)| *name == type_)
+        .and_then(|(_, status, normalized)| Some((StatusCode::from_u16(*status).ok()?, normalized.to_string())))
 }
 
 /// Enumeration of all possible API errors.
@@ -55,6 +92,16 @@ pub enum ApiError {
         message: String,
     },
 
+    #[error("Unauthorized: {message}")]
+    Unauthorized {
+        message: String,
+    },
+
+    #[error("Not found: {message}")]
+    NotFound {
+        message: String,
+    },
+
     #[error("Missing required header: {header}")]
     MissingHeader {
         header: String,
@@ -69,14 +116,77 @@ pub enum ApiError {
         type_: String,
         param: Option<String>,
         code: Option<String>,
+        /// Request/trace ID reported by the provider, for quoting to its support.
+        provider_request_id: Option<String>,
     },
 
     #[error("Google API error: {message}")]
-    googleError {
+    GoogleError {
+        message: String,
+        type_: String,
+        param: Option<String>,
+        code: Option<String>,
+        /// Request/trace ID reported by the provider, for quoting to its support.
+        provider_request_id: Option<String>,
+    },
+
+    #[error("Anthropic API error: {message}")]
+    AnthropicError {
         message: String,
         type_: String,
         param: Option<String>,
         code: Option<String>,
+        /// Request/trace ID reported by the provider, for quoting to its support.
+        provider_request_id: Option<String>,
+    },
+
+    #[error("Upstream returned malformed output: {message}")]
+    UpstreamFormat {
+        message: String,
+    },
+
+    #[error("Content blocked by provider safety filters: {}", categories.join(", "))]
+    ContentFiltered {
+        /// Harm categories the provider reported as blocked, if any
+        categories: Vec<String>,
+    },
+
+    #[error("Prompt blocked by provider: {reason}")]
+    PromptBlocked {
+        /// Block reason the provider reported, e.g. `Safety`
+        reason: String,
+        /// The provider's safety ratings of the prompt, as `category=probability`
+        safety_ratings: Vec<String>,
+    },
+
+    #[error("Model not allowed: {model}")]
+    ModelNotAllowed {
+        model: String,
+    },
+
+    #[error("Request rejected by content moderation: {reason}")]
+    ContentRejected {
+        reason: String,
+    },
+
+    #[error("Request body exceeds the {limit} byte limit")]
+    PayloadTooLarge {
+        limit: usize,
+    },
+
+    #[error("Request deadline exceeded: {message}")]
+    DeadlineExceeded {
+        message: String,
+    },
+
+    #[error("Server is at capacity, retry after {retry_after_secs} seconds")]
+    Overloaded {
+        retry_after_secs: u64,
+    },
+
+    #[error("Too many requests from this client, retry after {retry_after_secs} seconds")]
+    TooManyRequests {
+        retry_after_secs: u64,
     },
 
     #[error("Internal server error: {message}")]
@@ -94,89 +204,356 @@ pub enum ApiError {
 ///
 /// Maps each error variant to an appropriate HTTP status code and
 /// formats the error details into a consistent JSON response structure.
+/// Only the built-in provider error mappings apply here; the error is kept
+/// in the response extensions so `handlers::map_provider_errors` can
+/// render it again with the configured ones.
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let (status, error_response) = match &self {
+        let mut response = self.to_response(&HashMap::new());
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+impl ApiError {
+    /// Returns true for upstream failures another provider is unlikely to
+    /// share: connection errors, provider-side API errors such as rate
+    /// limits or outages, and broken streams. Malformed output, safety
+    /// blocks, and problems with the request itself are not retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ApiError::DeepSeekError { type_, .. }
+            | ApiError::GoogleError { type_, .. }
+            | ApiError::AnthropicError { type_, .. } => type_ != "parse_error",
+            ApiError::Other { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the status and normalized `error.type` mapped to a provider
+    /// error's type, or None for other errors and unmapped types.
+    ///
+    /// # Arguments
+    ///
+    /// * `mappings` - The configured `[errors.provider_errors]`, which take
+    ///   precedence over the built-in mappings
+    pub fn provider_error_mapping(
+        &self,
+        mappings: &HashMap<String, ProviderErrorMapping>,
+    ) -> Option<(StatusCode, String)> {
+        match self {
+            ApiError::DeepSeekError { type_, .. }
+            | ApiError::GoogleError { type_, .. }
+            | ApiError::AnthropicError { type_, .. } => provider_error_mapping(type_, mappings),
+            _ => None,
+        }
+    }
+
+    /// Renders the error as an HTTP response, adding `Retry-After` to
+    /// capacity and rate limit errors.
+    ///
+    /// # Arguments
+    ///
+    /// * `mappings` - The configured provider error mappings
+    pub fn to_response(&self, mappings: &HashMap<String, ProviderErrorMapping>) -> Response {
+        let (status, error_response) = self.status_and_body(mappings);
+
+        let mut response = (status, Json(error_response)).into_response();
+        if let ApiError::Overloaded { retry_after_secs } | ApiError::TooManyRequests { retry_after_secs } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+        }
+        response
+    }
+
+    /// Maps the error to its HTTP status code and JSON error body.
+    ///
+    /// Provider errors with a mapped type take the mapped status and
+    /// normalized type, keeping the provider's own type in `code` when the
+    /// provider sent no code. Unmapped provider errors are reported as 400
+    /// with a provider-prefixed type.
+    ///
+    /// # Arguments
+    ///
+    /// * `mappings` - The configured `[errors.provider_errors]`, which take
+    ///   precedence over the built-in mappings
+    ///
+    /// # Returns
+    ///
+    /// * `(StatusCode, ErrorResponse)` - The status and body sent to clients
+    pub fn status_and_body(&self, mappings: &HashMap<String, ProviderErrorMapping>) -> (StatusCode, ErrorResponse) {
+        match self {
             ApiError::BadRequest { message } => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
-                    error: ErrorDetails {
+                    error: ErrorPayload {
                         message: message.clone(),
                         type_: "bad_request".to_string(),
                         param: None,
                         code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::Unauthorized { message } => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: message.clone(),
+                        type_: "unauthorized".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::NotFound { message } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: message.clone(),
+                        type_: "not_found".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
             ApiError::MissingHeader { header } => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
-                    error: ErrorDetails {
+                    error: ErrorPayload {
                         message: format!("Missing required header: {}", header),
                         type_: "missing_header".to_string(),
                         param: Some(header.clone()),
                         code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
             ApiError::InvalidSystemPrompt => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
-                    error: ErrorDetails {
+                    error: ErrorPayload {
                         message: "System prompt can only be provided once, either in root or messages array".to_string(),
                         type_: "invalid_system_prompt".to_string(),
                         param: None,
                         code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
-            ApiError::DeepSeekError { message, type_, param, code } => (
-                StatusCode::BAD_REQUEST,
+            ApiError::DeepSeekError { message, type_, param, code, provider_request_id } => {
+                let (status, normalized_type, code) = match self.provider_error_mapping(mappings) {
+                    Some((status, normalized)) => (status, normalized, code.clone().or_else(|| Some(type_.clone()))),
+                    None => (StatusCode::BAD_REQUEST, format!("deepseek_{}", type_), code.clone()),
+                };
+                (
+                    status,
+                    ErrorResponse {
+                        error: ErrorPayload {
+                            message: format!("DeepSeek API Error: {}", message),
+                            type_: normalized_type,
+                            param: param.clone(),
+                            code,
+                            provider_request_id: provider_request_id.clone(),
+                            provider: Some("deepseek".to_string()),
+                        },
+                    },
+                )
+            }
+            ApiError::GoogleError { message, type_, param, code, provider_request_id } => {
+                let (status, normalized_type, code) = match self.provider_error_mapping(mappings) {
+                    Some((status, normalized)) => (status, normalized, code.clone().or_else(|| Some(type_.clone()))),
+                    None => (StatusCode::BAD_REQUEST, format!("Google_{}", type_), code.clone()),
+                };
+                (
+                    status,
+                    ErrorResponse {
+                        error: ErrorPayload {
+                            message: format!("Google API Error: {}", message),
+                            type_: normalized_type,
+                            param: param.clone(),
+                            code,
+                            provider_request_id: provider_request_id.clone(),
+                            provider: Some("gemini".to_string()),
+                        },
+                    },
+                )
+            }
+            ApiError::AnthropicError { message, type_, param, code, provider_request_id } => {
+                let (status, normalized_type, code) = match self.provider_error_mapping(mappings) {
+                    Some((status, normalized)) => (status, normalized, code.clone().or_else(|| Some(type_.clone()))),
+                    None => (StatusCode::BAD_REQUEST, format!("anthropic_{}", type_), code.clone()),
+                };
+                (
+                    status,
+                    ErrorResponse {
+                        error: ErrorPayload {
+                            message: format!("Anthropic API Error: {}", message),
+                            type_: normalized_type,
+                            param: param.clone(),
+                            code,
+                            provider_request_id: provider_request_id.clone(),
+                            provider: Some("anthropic".to_string()),
+                        },
+                    },
+                )
+            }
+            ApiError::UpstreamFormat { message } => (
+                StatusCode::BAD_GATEWAY,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: message.clone(),
+                        type_: "upstream_format".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::ContentFiltered { categories } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "content_filtered".to_string(),
+                        param: (!categories.is_empty()).then(|| categories.join(",")),
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::PromptBlocked { reason, safety_ratings } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: if safety_ratings.is_empty() {
+                            self.to_string()
+                        } else {
+                            format!("{} ({})", self, safety_ratings.join(", "))
+                        },
+                        type_: "prompt_blocked".to_string(),
+                        param: Some(reason.clone()),
+                        code: None,
+                        provider_request_id: None,
+                        provider: Some("gemini".to_string()),
+                    },
+                },
+            ),
+            ApiError::ModelNotAllowed { model } => (
+                StatusCode::FORBIDDEN,
                 ErrorResponse {
-                    error: ErrorDetails {
-                        message: format!("DeepSeek API Error: {}", message),
-                        type_: format!("deepseek_{}", type_),
-                        param: param.clone(),
-                        code: code.clone(),
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "model_not_allowed".to_string(),
+                        param: Some(model.clone()),
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
-            ApiError::GoogleError { message, type_, param, code } => (
+            ApiError::ContentRejected { .. } => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
-                    error: ErrorDetails {
-                        message: format!("Google API Error: {}", message),
-                        type_: format!("Google_{}", type_),
-                        param: param.clone(),
-                        code: code.clone(),
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "content_rejected".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::PayloadTooLarge { .. } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "payload_too_large".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::DeadlineExceeded { .. } => (
+                StatusCode::GATEWAY_TIMEOUT,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "deadline_exceeded".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::Overloaded { .. } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "overloaded".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
+                    },
+                },
+            ),
+            ApiError::TooManyRequests { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorResponse {
+                    error: ErrorPayload {
+                        message: self.to_string(),
+                        type_: "too_many_requests".to_string(),
+                        param: None,
+                        code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
             ApiError::Internal { message } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ErrorResponse {
-                    error: ErrorDetails {
+                    error: ErrorPayload {
                         message: message.clone(),
                         type_: "internal_error".to_string(),
                         param: None,
                         code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
             ApiError::Other { message } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ErrorResponse {
-                    error: ErrorDetails {
+                    error: ErrorPayload {
                         message: format!("Internal server error: {}", message),
                         type_: "internal_error".to_string(),
                         param: None,
                         code: None,
+                        provider_request_id: None,
+                        provider: None,
                     },
                 },
             ),
-        };
-
-        (status, Json(error_response)).into_response()
+        }
     }
 }
 
@@ -213,3 +590,66 @@ pub type SseStream = ReceiverStream<SseResult>;
 ///
 /// Represents the complete SSE response type used by the API endpoints.
 pub type SseResponse = axum::response::sse::Sse<SseStream>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deepseek_error(type_: &str) -> ApiError {
+        ApiError::DeepSeekError {
+            message: "upstream said no".to_string(),
+            type_: type_.to_string(),
+            param: None,
+            code: None,
+            provider_request_id: None,
+        }
+    }
+
+    #[test]
+    fn built_in_mappings_apply_without_configured_ones() {
+        let (status, body) = deepseek_error("rate_limit_exceeded").status_and_body(&HashMap::new());
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body.error.type_, "rate_limited");
+        assert_eq!(body.error.code.as_deref(), Some("rate_limit_exceeded"));
+        assert_eq!(body.error.provider.as_deref(), Some("deepseek"));
+    }
+
+    #[test]
+    fn configured_mappings_override_the_built_in_ones() {
+        let mappings = HashMap::from([(
+            "rate_limit_exceeded".to_string(),
+            ProviderErrorMapping {
+                status: 503,
+                type_: "busy".to_string(),
+            },
+        )]);
+
+        let (status, body) = deepseek_error("rate_limit_exceeded").status_and_body(&mappings);
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body.error.type_, "busy");
+    }
+
+    #[test]
+    fn unmapped_provider_errors_are_bad_requests_with_a_prefixed_type() {
+        let (status, body) = deepseek_error("context_length_exceeded").status_and_body(&HashMap::new());
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.error.type_, "deepseek_context_length_exceeded");
+        assert!(deepseek_error("context_length_exceeded")
+            .provider_error_mapping(&HashMap::new())
+            .is_none());
+    }
+
+    #[test]
+    fn into_response_keeps_the_error_for_remapping() {
+        let response = deepseek_error("authentication_error").into_response();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(matches!(
+            response.extensions().get::<ApiError>(),
+            Some(ApiError::DeepSeekError { type_, .. }) if type_ == "authentication_error"
+        ));
+    }
+}
@@ -6,48 +6,130 @@
 //! usage tracking and cost calculations.
 
 use crate::{
-    clients::{AnthropicClient, DeepSeekClient},
-    config::Config,
-    error::{ApiError, Result, SseResponse},
+    abuse::{self, AbuseMonitor, ClientTally, RequestLog, RequestTally},
+    cache::{CachedReasoning, ReasoningCache},
+    coalesce::DeltaCoalescer,
+    clients::{AnswerClient, DeepSeekClient, MockProvider},
+    clients::answer::{AnswerChunk, AnswerResponse, AnswerUsage},
+    clients::deepseek::{self, DeepSeekResponse},
+    clients::pinning::ProviderHttp,
+    limiter::{RequestLimiter, RequestPermit},
+    moderation::ModerationFilter,
+    replay::{ReplayRegistry, ResumeError, StreamBuffer},
+    telemetry,
+    usage::{UsageRecord, UsageSink, UsageStats},
+    config::{AnswerProvider, Config, DeepSeekModelKind, ModelPricing, PricingConfig, ProviderMode, ReasoningInjection, ReasoningToAnswer, ShortReasoningAction},
+    error::{ApiError, ErrorPayload, Result, SseResponse, SseResult},
     models::{
-        ApiRequest, ApiResponse, ContentBlock, CombinedUsage, DeepSeekUsage, AnthropicUsage,
-        ExternalApiResponse, Message, Role, StreamEvent,
+        ApiConfig, ApiRequest, ApiResponse, BatchItemResult, ChatQuery, BatchRequest, BatchResponse, CancelResponse, CostRequest, DebugPromptResponse, DryRunResponse, ContentBlock, ContentKind, ContentType, CombinedUsage, DeepSeekCost, DeepSeekUsage,
+        ExternalApiResponse, FingerprintResponse, FinishReason, InterleavePolicy, LoadResponse, PipelineOrder, Message, ModelDefaults, ModelInfo, ModelRole,
+        ModelsResponse, ProviderUsage, Role, StreamEvent, StreamTiming, TokenCounts, TokenizeRequest, TokenizeResponse, UpstreamPrompt, UsageSummary, format_cost,
     },
 };
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Path, Query, State},
     response::{sse::Event, IntoResponse},
     Json,
 };
 use chrono::Utc;
 use futures::StreamExt;
-use std::{sync::Arc, collections::HashMap};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 use tokio_stream::wrappers::ReceiverStream;
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 /// Application state shared across request handlers.
 ///
 /// Contains configuration that needs to be accessible
 /// to all request handlers.
 pub struct AppState {
-    pub config: Config,
+    /// Current configuration. Only the pricing section changes at runtime,
+    /// through the reload endpoint; take a snapshot with `config()`.
+    pub config: RwLock<Arc<Config>>,
+    /// Cancelled once the shutdown grace period expires, telling
+    /// in-flight streams to stop.
+    pub shutdown: CancellationToken,
+    /// Server-wide limit on concurrent chat requests and their queue.
+    pub request_limiter: RequestLimiter,
+    /// Reusable reasoning results for identical requests, if enabled.
+    pub reasoning_cache: Option<ReasoningCache>,
+    /// Shared HTTP clients for each provider, pinned if configured.
+    pub http: ProviderHttp,
+    /// In-flight streams, by request ID.
+    pub active_streams: Mutex<HashMap<String, ActiveStream>>,
+    /// Input screening rules, compiled at startup.
+    pub moderation: ModerationFilter,
+    /// Event buffers of resumable streams, if enabled.
+    pub replay: Option<ReplayRegistry>,
+    /// Destination of per-request usage records, if enabled.
+    pub usage_sink: Option<Box<dyn UsageSink>>,
+    /// Aggregate usage since startup, for the admin usage endpoint.
+    pub usage_stats: UsageStats,
+    /// Recent requests per client, if abuse detection is enabled.
+    pub abuse_monitor: Option<AbuseMonitor>,
+}
+
+impl AppState {
+    /// Returns a snapshot of the current configuration.
+    ///
+    /// Handlers take one snapshot per request, so a pricing reload never
+    /// changes the prices used partway through a request.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
 }
 
+/// Header carrying the admin token for admin endpoints.
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+/// Header a reconnecting SSE client sends with the last event id it received.
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// Header carrying the caller's end-to-end deadline, in Unix epoch milliseconds.
+const REQUEST_DEADLINE_HEADER: &str = "X-Request-Deadline-Ms";
+
+/// Share of a deadline budget the reasoning phase may use when not configured.
+const DEFAULT_DEADLINE_REASONING_SHARE: f64 = 0.6;
+
+/// Header carrying a token that skips content moderation.
+const MODERATION_BYPASS_HEADER: &str = "X-Moderation-Bypass";
+
+/// Response header carrying the SHA-256 of the returned reasoning.
+const REASONING_HASH_HEADER: &str = "x-reasoning-sha256";
+
+/// Data of the `error` event sent if even the serialization error can't be serialized.
+const SERIALIZATION_ERROR_EVENT: &str =
+    r#"{"type":"error","error":{"message":"Failed to serialize event","type":"internal_error"},"status":500}"#;
+
+/// Output tokens assumed per phase for dry-run estimates when not configured.
+const DEFAULT_DRY_RUN_OUTPUT_TOKENS: u32 = 1024;
+
 /// Extracts API tokens from request headers.
 ///
 /// # Arguments
 ///
 /// * `headers` - The HTTP headers containing the API tokens
+/// * `answer_provider` - The provider whose token is needed for the answer call
+/// * `answer_required` - Whether the answer provider will be called
 ///
 /// # Returns
 ///
-/// * `Result<(String, String)>` - A tuple of (DeepSeek token, Gemini token)
+/// * `Result<(String, String)>` - A tuple of (DeepSeek token, answer provider
+///   token); the answer token is empty if not required and not sent
 ///
 /// # Errors
 ///
-/// Returns `ApiError::MissingHeader` if either token is missing
+/// Returns `ApiError::MissingHeader` if a required token is missing
 /// Returns `ApiError::BadRequest` if tokens are malformed
 fn extract_api_tokens(
     headers: &axum::http::HeaderMap,
+    answer_provider: AnswerProvider,
+    answer_required: bool,
 ) -> Result<(String, String)> {
     let deepseek_token = headers
         .get("X-DeepSeek-API-Token")
@@ -60,45 +142,68 @@ fn extract_api_tokens(
         })?
         .to_string();
 
-    let gemini_token = headers
-        .get("X-Gemini-API-Token")
+    let answer_header = answer_provider.token_header();
+    if !answer_required && !headers.contains_key(answer_header) {
+        return Ok((deepseek_token, String::new()));
+    }
+    let answer_token = headers
+        .get(answer_header)
         .ok_or_else(|| ApiError::MissingHeader { 
-            header: "X-Gemini-API-Token".to_string() 
+            header: answer_header.to_string() 
         })?
         .to_str()
         .map_err(|_| ApiError::BadRequest { 
-            message: "Invalid Gemini API token".to_string() 
+            message: format!("Invalid {} API token", answer_provider.name()) 
         })?
         .to_string();
 
-    Ok((deepseek_token, gemini_token))
+    Ok((deepseek_token, answer_token))
 }
 
 /// Calculates the cost of DeepSeek API usage.
 ///
+/// By default `output_tokens` is taken to already include the reasoning
+/// tokens, as DeepSeek reports them. When `reasoning_billed_separately` is
+/// set in the pricing config, `output_tokens` is taken to exclude them and
+/// the reasoning tokens are charged on top at the output rate. Either way
+/// the reasoning tokens are reported as their own component, and the
+/// output component covers only the remaining output tokens.
+///
 /// # Arguments
 ///
 /// * `input_tokens` - Number of input tokens processed
 /// * `output_tokens` - Number of output tokens generated
-/// * `_reasoning_tokens` - Number of tokens used for reasoning
+/// * `reasoning_tokens` - Number of tokens used for reasoning
 /// * `cached_tokens` - Number of tokens retrieved from cache
+/// * `model` - The DeepSeek model used, which selects the rates
 /// * `config` - Configuration containing pricing information
 ///
 /// # Returns
 ///
-/// The total cost in dollars for the API usage
+/// The cost in dollars, split into cache-hit input, cache-miss input,
+/// output, and reasoning components
 fn calculate_deepseek_cost(
     input_tokens: u32,
     output_tokens: u32,
-    _reasoning_tokens: u32,
+    reasoning_tokens: u32,
     cached_tokens: u32,
+    model: &str,
     config: &Config,
-) -> f64 {
-    let cache_hit_cost = (cached_tokens as f64 / 1_000_000.0) * config.pricing.deepseek.input_cache_hit_price;
-    let cache_miss_cost = ((input_tokens - cached_tokens) as f64 / 1_000_000.0) * config.pricing.deepseek.input_cache_miss_price;
-    let output_cost = (output_tokens as f64 / 1_000_000.0) * config.pricing.deepseek.output_price;
-    
-    cache_hit_cost + cache_miss_cost + output_cost
+) -> DeepSeekCost {
+    let pricing = &config.pricing.deepseek;
+    let (cache_hit_price, cache_miss_price, output_price) = pricing.rates(model);
+    let billed_output_tokens = if pricing.reasoning_billed_separately {
+        output_tokens.saturating_add(reasoning_tokens)
+    } else {
+        output_tokens.max(reasoning_tokens)
+    };
+
+    DeepSeekCost {
+        cache_hit_cost: (cached_tokens as f64 / 1_000_000.0) * cache_hit_price,
+        cache_miss_cost: (input_tokens.saturating_sub(cached_tokens) as f64 / 1_000_000.0) * cache_miss_price,
+        output_cost: (billed_output_tokens.saturating_sub(reasoning_tokens) as f64 / 1_000_000.0) * output_price,
+        reasoning_cost: (reasoning_tokens as f64 / 1_000_000.0) * output_price,
+    }
 }
 
 /// Calculates the cost of Gemini API usage.
@@ -107,6 +212,7 @@ fn calculate_deepseek_cost(
 ///
 /// * `input_tokens` - Number of input tokens processed
 /// * `output_tokens` - Number of output tokens generated
+/// * `model` - The Gemini model used, which selects the rates
 /// * `config` - Configuration containing pricing information
 ///
 /// # Returns
@@ -115,9 +221,10 @@ fn calculate_deepseek_cost(
 fn calculate_gemini_cost(
     input_tokens: u32,
     output_tokens: u32,
+    model: &str,
     config: &Config,
 ) -> f64 {
-    let pricing = &config.pricing.gemini.gemini_pro;
+    let pricing = config.pricing.gemini.for_model(model);
 
     let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
     let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
@@ -125,448 +232,5077 @@ fn calculate_gemini_cost(
     input_cost + output_cost
 }
 
-/// Formats a cost value as a dollar amount string.
+/// Calculates the cost of Anthropic API usage.
 ///
 /// # Arguments
 ///
-/// * `cost` - The cost value to format
+/// * `input_tokens` - Uncached input tokens. Anthropic reports cache
+///   reads and writes separately, so none of these are cached.
+/// * `output_tokens` - Number of output tokens generated
+/// * `cache_read_tokens` - Number of input tokens read from cache
+/// * `cache_write_tokens` - Number of input tokens written to cache
+/// * `model` - The Claude model used, which selects the price tier
+/// * `config` - Configuration containing pricing information
 ///
 /// # Returns
 ///
-/// A string representing the cost with 3 decimal places and $ prefix
-fn format_cost(cost: f64) -> String {
-    format!("${:.3}", cost)
+/// The total cost in dollars for the API usage
+fn calculate_anthropic_cost(
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_read_tokens: u32,
+    cache_write_tokens: u32,
+    model: &str,
+    config: &Config,
+) -> f64 {
+    let pricing = config.pricing.anthropic.for_model(model);
+
+    let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
+    let cache_read_cost = (cache_read_tokens as f64 / 1_000_000.0) * pricing.cache_read_price;
+    let cache_write_cost = (cache_write_tokens as f64 / 1_000_000.0) * pricing.cache_write_price;
+    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
+
+    input_cost + cache_read_cost + cache_write_cost + output_cost
 }
 
-/// Main handler for chat requests.
-///
-/// Routes requests to either streaming or non-streaming handlers
-/// based on the request configuration.
+/// Returns the DeepSeek model a request config selects.
+fn deepseek_model(deepseek_config: &ApiConfig) -> &str {
+    deepseek_config.model().unwrap_or(deepseek::DEFAULT_MODEL)
+}
+
+/// Resolves the answer model a request uses, falling back to the provider default.
+fn answer_model(provider: AnswerProvider, answer_config: &ApiConfig) -> &str {
+    answer_config.model().unwrap_or(match provider {
+        AnswerProvider::Gemini => crate::clients::gemini::DEFAULT_MODEL,
+        AnswerProvider::Anthropic => crate::clients::anthropic::DEFAULT_MODEL,
+    })
+}
+
+/// Builds the usage record for the DeepSeek reasoning phase.
 ///
 /// # Arguments
 ///
-/// * `state` - Application state containing configuration
-/// * `headers` - HTTP request headers
-/// * `request` - The parsed chat request
+/// * `usage` - Token usage reported by DeepSeek
+/// * `model` - The DeepSeek model used
+/// * `config` - Configuration containing pricing information
 ///
 /// # Returns
 ///
-/// * `Result<Response>` - The API response or an error
-pub async fn handle_chat(
-    state: State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-    Json(request): Json<ApiRequest>,
-) -> Result<axum::response::Response> {
-    if request.stream {
-        let stream_response = chat_stream(state, headers, Json(request)).await?;
-        Ok(stream_response.into_response())
-    } else {
-        let json_response = chat(state, headers, Json(request)).await?;
-        Ok(json_response.into_response())
+/// The priced `ProviderUsage` for the reasoning call
+fn reasoning_usage(usage: &deepseek::Usage, model: &str, config: &Config) -> ProviderUsage {
+    let cost_breakdown = calculate_deepseek_cost(
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.completion_tokens_details.reasoning_tokens,
+        usage.cached_tokens(),
+        model,
+        config,
+    );
+
+    ProviderUsage {
+        provider: "deepseek".to_string(),
+        input_tokens: usage.prompt_tokens,
+        output_tokens: usage.completion_tokens,
+        reasoning_tokens: usage.completion_tokens_details.reasoning_tokens,
+        cached_input_tokens: usage.cached_tokens(),
+        total_tokens: usage.total_tokens,
+        cost: cost_breakdown.total(),
+        cost_breakdown: Some(cost_breakdown),
+        cached: false,
+        forwarded_reasoning_tokens: None,
+        retries: 0,
     }
 }
 
-/// Handler for non-streaming chat requests.
-///
-/// Processes the request through both AI models sequentially,
-/// combining their responses and tracking usage.
+/// Builds the usage record for the answer phase.
 ///
 /// # Arguments
 ///
-/// * `state` - Application state containing configuration
-/// * `headers` - HTTP request headers
-/// * `request` - The parsed chat request
+/// * `provider` - The provider that served the answer
+/// * `usage` - Token usage reported by the provider
+/// * `answer_config` - Request config for the provider, used to resolve the model
+/// * `config` - Configuration containing pricing information
 ///
 /// # Returns
 ///
-/// * `Result<Json<ApiResponse>>` - The combined API response or an error
-pub(crate) async fn chat(
-    State(state): State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-    Json(request): Json<ApiRequest>,
-) -> Result<Json<ApiResponse>> {
-    // Validate system prompt
-    if !request.validate_system_prompt() {
-        return Err(ApiError::InvalidSystemPrompt);
-    }
+/// The priced `ProviderUsage` for the answer call
+fn answer_usage(
+    provider: AnswerProvider,
+    usage: AnswerUsage,
+    answer_config: &ApiConfig,
+    config: &Config,
+) -> ProviderUsage {
+    let cost = match provider {
+        AnswerProvider::Gemini => {
+            let model = answer_config.model().unwrap_or(crate::clients::gemini::DEFAULT_MODEL);
+            calculate_gemini_cost(usage.input_tokens, usage.output_tokens, model, config)
+        }
+        AnswerProvider::Anthropic => {
+            let model = answer_config
+                .body
+                .get("model")
+                .and_then(|m| m.as_str())
+                .unwrap_or(crate::clients::anthropic::DEFAULT_MODEL);
+            calculate_anthropic_cost(
+                usage.input_tokens,
+                usage.output_tokens,
+                usage.cached_input_tokens,
+                usage.cache_creation_input_tokens,
+                model,
+                config,
+            )
+        }
+    };
 
-    // Extract API tokens
-    let (deepseek_token, anthropic_token) = extract_api_tokens(&headers)?;
+    ProviderUsage {
+        provider: provider.name().to_ascii_lowercase(),
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+        reasoning_tokens: 0,
+        cached_input_tokens: usage.cached_input_tokens,
+        total_tokens: usage.input_tokens.saturating_add(usage.output_tokens),
+        cost,
+        cost_breakdown: None,
+        cached: false,
+        forwarded_reasoning_tokens: None,
+        retries: 0,
+    }
+}
 
-    // Initialize clients
-    let deepseek_client = DeepSeekClient::new(deepseek_token);
-    let google_client =Googlkelient::new(google_token);
+/// Characters per token assumed by the token estimates.
+const CHARS_PER_TOKEN: usize = 4;
 
-    // Get messages with system prompt
-    let messages = request.get_messages_with_system();
+/// Approximates the token count of a text.
+///
+/// Uses the common heuristic of roughly four characters per token,
+/// which is close enough for cost estimates across providers.
+fn estimate_tokens(text: &str) -> u32 {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN) as u32
+}
 
-    // Call DeepSeek API
-    let deepseek_response = deepseek_client.chat(messages.clone(), &request.deepseek_config).await?;
-    
-    // Store response metadata
-    let deepseek_status: u16 = 200;
-    let deepseek_headers = HashMap::new(); // Headers not available when using high-level chat method
+/// Splits a text into the tokens `estimate_tokens` counts.
+///
+/// # Returns
+///
+/// * `Vec<[usize; 2]>` - Start and end character offsets of each token
+fn token_boundaries(text: &str) -> Vec<[usize; 2]> {
+    let chars = text.chars().count();
+    (0..chars)
+        .step_by(CHARS_PER_TOKEN)
+        .map(|start| [start, (start + CHARS_PER_TOKEN).min(chars)])
+        .collect()
+}
 
-    // Extract reasoning content and wrap in thinking tags
-    let reasoning_content = deepseek_response
-        .choices
-        .first()
-        .and_then(|c| c.message.reasoning_content.as_ref())
-        .ok_or_else(|| ApiError::DeepSeekError { 
-            message: "No reasoning content in response".to_string(),
-            type_: "missing_content".to_string(),
-            param: None,
-            code: None
-        })?;
+/// Estimates usage and cost for a request without calling any provider.
+///
+/// The reasoner sees the conversation; the responder additionally sees
+/// the reasoning, which is assumed to be `dry_run_output_tokens` long,
+/// as is the answer.
+///
+/// # Arguments
+///
+/// * `request` - The chat request to estimate
+/// * `config` - Configuration containing pricing information
+///
+/// # Returns
+///
+/// * `CombinedUsage` - The estimated usage for both phases
+fn estimate_usage(request: &ApiRequest, config: &Config) -> CombinedUsage {
+    let output_tokens = config
+        .pipeline
+        .dry_run_output_tokens
+        .unwrap_or(DEFAULT_DRY_RUN_OUTPUT_TOKENS);
+    let input_tokens: u32 = request
+        .get_messages_with_system()
+        .iter()
+        .map(|msg| estimate_tokens(&msg.content.text_with_placeholders()))
+        .sum();
 
-    let thinking_content = format!("<thinking>\n{}\n</thinking>", reasoning_content);
+    let reasoning = estimated_reasoning_usage(
+        input_tokens,
+        output_tokens,
+        deepseek_model(&request.deepseek_config),
+        config,
+    );
 
-    // Add thinking content to messages for Gemini
-    let mut gemini_messages = messages;
-    gemini_messages.push(Message {
-        role: Role::Assistant,
-        content: thinking_content.clone(),
-    });
+    let answer_provider = config.pipeline.answer_provider;
+    let answer = answer_usage(
+        answer_provider,
+        AnswerUsage {
+            input_tokens: input_tokens + output_tokens,
+            output_tokens,
+            cached_input_tokens: 0,
+            cache_creation_input_tokens: 0,
+        },
+        request.answer_config(answer_provider),
+        config,
+    );
 
-    // Call Gemini API
-    let gemini_response = gemini_client.chat(
-        gemini_messages,
-        request.get_system_prompt().map(String::from),
-        &request.gemini_config
-    ).await?;
-    
-    // Store response metadata
-    let gemini_status: u16 = 200;
-    let gemini_headers = HashMap::new(); // Headers not available when using high-level chat method
+    CombinedUsage {
+        reasoning,
+        answer,
+        cost_format: config.cost_format.clone(),
+        timing: StreamTiming::default(),
+    }
+}
 
-    // Calculate usage costs
-    let deepseek_cost = calculate_deepseek_cost(
-        deepseek_response.usage.prompt_tokens,
-        deepseek_response.usage.completion_tokens,
-        deepseek_response.usage.completion_tokens_details.reasoning_tokens,
-        deepseek_response.usage.prompt_tokens_details.cached_tokens,
-        &state.config,
-    );
+/// Computes latency metrics for a streamed response.
+///
+/// # Arguments
+///
+/// * `started` - When the request was received
+/// * `first_reasoning` - When the first reasoning delta arrived, if any
+/// * `first_answer` - When the first answer delta arrived, if any
+/// * `completed` - When the answer stream ended
+/// * `answer_tokens` - Output tokens produced by the answer model
+///
+/// # Returns
+///
+/// * `StreamTiming` - The measured timings; unmeasured fields are None
+fn stream_timing(
+    started: Instant,
+    first_reasoning: Option<Instant>,
+    first_answer: Option<Instant>,
+    completed: Instant,
+    answer_tokens: u32,
+) -> StreamTiming {
+    let millis_since_start = |at: Instant| at.duration_since(started).as_millis() as u64;
+    let answer_tokens_per_sec = first_answer
+        .map(|first| completed.duration_since(first).as_secs_f64())
+        .filter(|&secs| secs > 0.0 && answer_tokens > 0)
+        .map(|secs| answer_tokens as f64 / secs);
 
-    let gemini_cost = calculate_gemini_cost(
-        gemini_response.usage.as_ref().map(|u| u.prompt_tokens).unwrap_or(0),
-        gemini_response.usage.as_ref().map(|u| u.completion_tokens).unwrap_or(0),
-        &state.config,
-    );
+    StreamTiming {
+        time_to_first_reasoning_ms: first_reasoning.map(millis_since_start),
+        time_to_first_answer_ms: first_answer.map(millis_since_start),
+        answer_tokens_per_sec,
+        ..Default::default()
+    }
+}
 
-    // Combine thinking content with Gemini's response
-    let mut content = Vec::new();
-    
-    // Add thinking block first
-    content.push(ContentBlock::text(thinking_content));
-    
-    // Add Gemini's response blocks
-    content.extend(gemini_response.content.clone().into_iter()
-        .map(ContentBlock::from_gemini));
+/// Characters of reasoning kept in `summary` mode when not configured.
+const DEFAULT_REASONING_SUMMARY_CHARS: usize = 2000;
 
-    // Build response with captured headers
-    let response = ApiResponse {
-        created: Utc::now(),
-        content,
-        deepseek_response: request.verbose.then(|| ExternalApiResponse {
-            status: deepseek_status,
-            headers: deepseek_headers,
-            body: serde_json::to_value(&deepseek_response).unwrap_or_default(),
-        }),
-        gemini_response: request.verbose.then(|| ExternalApiResponse {
-            status: gemini_status,
-            headers: gemini_headers,
-            body: serde_json::to_value(&gemini_response).unwrap_or_default(),
-        }),
-        combined_usage: CombinedUsage {
-            total_cost: format_cost(deepseek_cost + anthropic_cost),
-            deepseek_usage: DeepSeekUsage {
-                input_tokens: deepseek_response.usage.prompt_tokens,
-                output_tokens: deepseek_response.usage.completion_tokens,
-                reasoning_tokens: deepseek_response.usage.completion_tokens_details.reasoning_tokens,
-                cached_input_tokens: deepseek_response.usage.prompt_tokens_details.cached_tokens,
-                total_tokens: deepseek_response.usage.total_tokens,
-                total_cost: format_cost(deepseek_cost),
-            },
-            gemini_usage: GeminiUsage {
-                input_tokens: gemini_response.usage.as_ref().map(|u| u.prompt_tokens).unwrap_or(0),
-                output_tokens: gemini_response.usage.as_ref().map(|u| u.completion_tokens).unwrap_or(0),
-                total_tokens: gemini_response.usage.as_ref().map(|u| u.total_tokens).unwrap_or(0),
-                total_cost: format_cost(gemini_cost),
-            },
-        },
+/// Builds the thinking block forwarded to the answer model.
+///
+/// # Arguments
+///
+/// * `reasoning` - The complete reasoning
+/// * `mode` - How much of it to forward
+/// * `config` - Configuration containing the summary length
+///
+/// # Returns
+///
+/// * `Option<(String, u32)>` - The thinking block and its estimated
+///   reasoning tokens, or None when nothing is forwarded
+fn forwarded_reasoning(reasoning: &str, mode: ReasoningToAnswer, config: &Config) -> Option<(String, u32)> {
+    let forwarded = match mode {
+        ReasoningToAnswer::None => return None,
+        ReasoningToAnswer::Full => reasoning.to_string(),
+        ReasoningToAnswer::Summary => {
+            // Keep the end of the reasoning, where its conclusions are
+            let limit = config
+                .pipeline
+                .reasoning_summary_chars
+                .unwrap_or(DEFAULT_REASONING_SUMMARY_CHARS);
+            let total = reasoning.chars().count();
+            if total <= limit {
+                reasoning.to_string()
+            } else {
+                let tail: String = reasoning.chars().skip(total - limit).collect();
+                format!("[Earlier reasoning omitted]\n{}", tail)
+            }
+        }
     };
 
-    Ok(Json(response))
+    let tokens = estimate_tokens(&forwarded);
+    Some((format!("<thinking>\n{}\n</thinking>", forwarded), tokens))
 }
 
-/// Handler for streaming chat requests.
+/// Builds reasoning usage from estimated token counts.
 ///
-/// Processes the request through both AI models sequentially,
-/// streaming their responses as Server-Sent Events.
+/// For reasoner models all output tokens are treated as reasoning tokens,
+/// since the estimate is taken before DeepSeek reports its own usage.
 ///
 /// # Arguments
 ///
-/// * `state` - Application state containing configuration
-/// * `headers` - HTTP request headers
-/// * `request` - The parsed chat request
+/// * `input_tokens` - Estimated prompt tokens
+/// * `output_tokens` - Estimated output tokens
+/// * `model` - The DeepSeek model used
+/// * `config` - Configuration containing pricing information
 ///
 /// # Returns
 ///
-/// * `Result<SseResponse>` - A stream of Server-Sent Events or an error
-pub(crate) async fn chat_stream(
-    State(state): State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-    Json(request): Json<ApiRequest>,
-) -> Result<SseResponse> {
-    // Validate system prompt
-    if !request.validate_system_prompt() {
-        return Err(ApiError::InvalidSystemPrompt);
-    }
+/// * `ProviderUsage` - The estimated DeepSeek usage and cost
+fn estimated_reasoning_usage(input_tokens: u32, output_tokens: u32, model: &str, config: &Config) -> ProviderUsage {
+    let reasoning_tokens = match config.pricing.deepseek.kind(model) {
+        DeepSeekModelKind::Reasoner => output_tokens,
+        DeepSeekModelKind::Chat => 0,
+    };
 
-    // Extract API tokens
-    let (deepseek_token, gemini_token) = extract_api_tokens(&headers)?;
+    let cost_breakdown = calculate_deepseek_cost(
+        input_tokens,
+        output_tokens - reasoning_tokens,
+        reasoning_tokens,
+        0,
+        model,
+        config,
+    );
 
-    // Initialize clients
-    let deepseek_client = DeepSeekClient::new(deepseek_token);
-    let gemini_client = GeminiClient::new(gemini_token);
+    ProviderUsage {
+        provider: "deepseek".to_string(),
+        input_tokens,
+        output_tokens,
+        reasoning_tokens,
+        cached_input_tokens: 0,
+        total_tokens: input_tokens + output_tokens,
+        cost: cost_breakdown.total(),
+        cost_breakdown: Some(cost_breakdown),
+        cached: false,
+        forwarded_reasoning_tokens: None,
+        retries: 0,
+    }
+}
 
-    // Get messages with system prompt
-    let messages = request.get_messages_with_system();
+/// Applies server-side defaults to a request: fills in the default system
+/// prompt, wraps the system prompt in the configured prefix and suffix,
+/// inserts the few-shot examples, applies the server's response size caps,
+/// and fills in Gemini safety settings for categories the request doesn't
+/// set.
+///
+/// Applied after validation, so the client's own system prompt placement
+/// is still checked.
+fn with_server_defaults(request: ApiRequest, config: &Config) -> ApiRequest {
+    let pipeline = &config.pipeline;
+    let mut request = request
+        .with_default_system_prompt(
+            pipeline.default_system_prompt.as_deref(),
+            pipeline.treat_empty_system_prompt_as_missing,
+        )
+        .with_system_prompt_wrapping(
+            pipeline.system_prompt_prefix.as_deref(),
+            pipeline.system_prompt_suffix.as_deref(),
+        )
+        .with_few_shot_examples(&pipeline.few_shot_examples);
 
-    // Create channel for stream events
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-    let tx = Arc::new(tx);
+    request.max_response_chars = request.max_response_chars.min(pipeline.max_response_chars);
 
-    // Spawn task to handle streaming
-    let config = state.config.clone();
-    let request_clone = request.clone();
-    tokio::spawn(async move {
-        let tx = tx.clone();
+    for (category, threshold) in &config.gemini.safety_settings {
+        request
+            .gemini_config
+            .safety_settings
+            .entry(category.clone())
+            .or_insert_with(|| threshold.clone());
+    }
 
-        // Start event
-        let _ = tx
-            .send(Ok(Event::default().event("start").data(
-                serde_json::to_string(&StreamEvent::Start {
-                    created: Utc::now(),
-                })
-                .unwrap_or_default(),
-            )))
-            .await;
+    request
+}
 
-        // Send initial thinking tag
-        let _ = tx
-            .send(Ok(Event::default().event("content").data(
-                serde_json::to_string(&StreamEvent::Content {
-                    content: vec![ContentBlock {
-                        content_type: "text".to_string(),
-                        text: "<thinking>\n".to_string(),
-                    }],
-                })
-                .unwrap_or_default(),
-            )))
-            .await;
+/// Checks a provider config's `max_tokens` against the model's output limit.
+///
+/// # Arguments
+///
+/// * `api_config` - The provider's request config, adjusted in place when clamping
+/// * `limit` - The model's maximum output tokens, if known
+/// * `model` - Model name used in messages
+/// * `clamp` - Lower an excessive `max_tokens` to the limit instead of failing
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if `max_tokens` exceeds the limit and
+/// clamping is disabled
+fn check_max_tokens(api_config: &mut ApiConfig, limit: Option<u32>, model: &str, clamp: bool) -> Result<()> {
+    let (Some(requested), Some(limit)) = (api_config.max_tokens(), limit) else {
+        return Ok(());
+    };
+    if requested <= limit {
+        return Ok(());
+    }
 
-        // Stream from DeepSeek
-        let mut deepseek_usage = None;
-        let mut complete_reasoning = String::new();
-        let mut deepseek_stream = deepseek_client.chat_stream(messages.clone(), &request_clone.deepseek_config);
-        
-        while let Some(chunk) = deepseek_stream.next().await {
-            match chunk {
-                Ok(response) => {
-                    if let Some(choice) = response.choices.first() {
-                        // Check if reasoning_content is null and break if it is
-                        if choice.delta.reasoning_content.is_none() {
-                            break;
-                        }
+    if !clamp {
+        return Err(ApiError::BadRequest {
+            message: format!(
+                "max_tokens {} exceeds the {} output limit of {} tokens",
+                requested, model, limit
+            ),
+        });
+    }
 
-                        // Handle delta reasoning_content for streaming
-                        if let Some(reasoning) = &choice.delta.reasoning_content {
-                            if !reasoning.is_empty() {
-                                // Stream the reasoning content as a delta
-                                let _ = tx
-                                    .send(Ok(Event::default().event("content").data(
-                                        serde_json::to_string(&StreamEvent::Content {
-                                            content: vec![ContentBlock {
-                                                content_type: "text_delta".to_string(),
-                                                text: reasoning.to_string(),
-                                            }],
-                                        })
-                                        .unwrap_or_default(),
-                                    )))
-                                    .await;
-                                
-                                // Accumulate complete reasoning for later use
-                                complete_reasoning.push_str(reasoning);
-                            }
-                        }
-                    }
-                    
-                    // Store usage information if present
-                    if let Some(usage) = response.usage {
-                        deepseek_usage = Some(usage);
-                    }
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("error").data(
-                            serde_json::to_string(&StreamEvent::Error {
-                                message: e.to_string(),
-                                code: 500,
-                            })
-                            .unwrap_or_default(),
-                        )))
-                        .await;
-                    return;
-                }
-            }
-        }
+    tracing::warn!("Clamping max_tokens from {} to {} for {}", requested, limit, model);
+    api_config.set_max_tokens(limit);
+    Ok(())
+}
 
-        // Send closing thinking tag
-        let _ = tx
-            .send(Ok(Event::default().event("content").data(
-                serde_json::to_string(&StreamEvent::Content {
-                    content: vec![ContentBlock {
-                        content_type: "text".to_string(),
-                        text: "\n</thinking>".to_string(),
-                    }],
-                })
-                .unwrap_or_default(),
-            )))
-            .await;
+/// Applies the configured per-model output token limits to a request.
+///
+/// # Arguments
+///
+/// * `request` - The chat request
+/// * `config` - Configuration containing model limits and the clamp setting
+///
+/// # Returns
+///
+/// * `Result<ApiRequest>` - The request, with `max_tokens` clamped if enabled
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if a `max_tokens` exceeds its model's
+/// limit and `clamp_max_tokens` is disabled
+fn with_max_tokens_limits(mut request: ApiRequest, config: &Config) -> Result<ApiRequest> {
+    let clamp = config.pipeline.clamp_max_tokens;
+    let pricing = &config.pricing;
 
-        // Add complete thinking content to messages for Gemini
-        let mut gemini_messages = messages;
-        gemini_messages.push(Message {
-            role: Role::Assistant,
-            content: format!("<thinking>\n{}\n</thinking>", complete_reasoning),
-        });
+    let deepseek_model = deepseek_model(&request.deepseek_config).to_string();
+    check_max_tokens(
+        &mut request.deepseek_config,
+        pricing.deepseek.max_output_tokens(&deepseek_model),
+        &deepseek_model,
+        clamp,
+    )?;
 
-        // Stream from Gemini
-        let mut gemini_stream = gemini_client.chat_stream(
-            gemini_messages,
-            request_clone.get_system_prompt().map(String::from),
-            &request_clone.gemini_config,
-        );
+    let gemini_model = request
+        .gemini_config
+        .model()
+        .unwrap_or(crate::clients::gemini::DEFAULT_MODEL)
+        .to_string();
+    check_max_tokens(
+        &mut request.gemini_config,
+        pricing.gemini.for_model(&gemini_model).max_output_tokens,
+        &gemini_model,
+        clamp,
+    )?;
 
-        while let Some(chunk) = gemini_stream.next().await {
-            match chunk {
-                Ok(event) => match event {
-                    crate::clients::gemini::StreamEvent::MessageStart { message } => {
-                        // Only send content event if there's actual content to send
-                        if !message.content.is_empty() {
-                            let _ = tx
-                                .send(Ok(Event::default().event("content").data(
-                                    serde_json::to_string(&StreamEvent::Content { 
-                                        content: message.content.into_iter()
-                                            .map(ContentBlock::from_gemini)
-                                            .collect()
-                                    })
-                                    .unwrap_or_default(),
-                                )))
-                                .await;
-                        }
-                    }
-                    crate::clients::gemini::StreamEvent::ContentBlockDelta { delta, .. } => {
-                        // Send content update
-                        let _ = tx
-                            .send(Ok(Event::default().event("content").data(
-                                serde_json::to_string(&StreamEvent::Content {
-                                    content: vec![ContentBlock {
-                                        content_type: delta.delta_type,
-                                        text: delta.text,
-                                    }],
-                                })
-                                .unwrap_or_default(),
-                            )))
-                            .await;
-                    }
-                    crate::clients::gemini::StreamEvent::MessageDelta { usage, .. } => {
-                        // Send final usage stats if available
-                        if let Some(usage) = usage {
-                            let gemini_usage = GeminiUsage::from_gemini(&usage);
-                            let gemini_cost = calculate_gemini_cost(
-                                gemini_usage.input_tokens,
-                                gemini_usage.output_tokens,
-                                &config,
-                            );
+    let anthropic_model = request
+        .anthropic_config
+        .body
+        .get("model")
+        .and_then(|m| m.as_str())
+        .unwrap_or(crate::clients::anthropic::DEFAULT_MODEL)
+        .to_string();
+    check_max_tokens(
+        &mut request.anthropic_config,
+        pricing.anthropic.for_model(&anthropic_model).max_output_tokens,
+        &anthropic_model,
+        clamp,
+    )?;
 
-                            // Calculate DeepSeek costs if usage is available
-                            let (deepseek_usage, deepseek_cost) = if let Some(usage) = deepseek_usage.as_ref() {
-                                let cost = calculate_deepseek_cost(
-                                    usage.prompt_tokens,
-                                    usage.completion_tokens,
-                                    usage.completion_tokens_details.reasoning_tokens,
-                                    usage.prompt_tokens_details.cached_tokens,
-                                    &config,
-                                );
-                                
-                                (DeepSeekUsage {
-                                    input_tokens: usage.prompt_tokens,
-                                    output_tokens: usage.completion_tokens,
-                                    reasoning_tokens: usage.completion_tokens_details.reasoning_tokens,
-                                    cached_input_tokens: usage.prompt_tokens_details.cached_tokens,
-                                    total_tokens: usage.total_tokens,
-                                    total_cost: format_cost(cost),
-                                }, cost)
-                            } else {
-                                (DeepSeekUsage {
-                                    input_tokens: 0,
-                                    output_tokens: 0,
-                                    reasoning_tokens: 0,
-                                    cached_input_tokens: 0,
-                                    total_tokens: 0,
-                                    total_cost: "$0.00".to_string(),
-                                }, 0.0)
-                            };
-
-                            let _ = tx
-                                .send(Ok(Event::default().event("usage").data(
-                                    serde_json::to_string(&StreamEvent::Usage {
-                                        usage: CombinedUsage {
-                                            total_cost: format_cost(deepseek_cost + gemini_cost),
-                                            deepseek_usage,
-                                            gemini_usage: GeminiUsage {
-                                                input_tokens: gemini_usage.input_tokens,
-                                                output_tokens: gemini_usage.output_tokens,
-                                                total_tokens: gemini_usage.total_tokens,
-                                                total_cost: format_cost(gemini_cost),
-                                            },
-                                        },
-                                    })
-                                    .unwrap_or_default(),
-                                )))
-                                .await;
-                        }
-                    }
-                    _ => {} // Handle other events if needed
-                },
-                Err(e) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("error").data(
-                            serde_json::to_string(&StreamEvent::Error {
-                                message: e.to_string(),
-                                code: 500,
-                            })
-                            .unwrap_or_default(),
-                        )))
-                        .await;
-                    return;
-                }
-            }
-        }
+    Ok(request)
+}
 
-        // Send done event
-        let _ = tx
-            .send(Ok(Event::default().event("done").data(
-                serde_json::to_string(&StreamEvent::Done)
-                    .unwrap_or_default(),
-            )))
-            .await;
-    });
+/// Checks the DeepSeek, Gemini, and Anthropic models a request selects
+/// against the configured allow and deny lists.
+///
+/// # Errors
+///
+/// Returns `ApiError::ModelNotAllowed` for the first disallowed model
+fn check_allowed_models(request: &ApiRequest, config: &Config) -> Result<()> {
+    let deepseek_model = deepseek_model(&request.deepseek_config);
+    if !config.deepseek.models.allows(deepseek_model) {
+        return Err(ApiError::ModelNotAllowed {
+            model: deepseek_model.to_string(),
+        });
+    }
 
-    // Convert receiver into stream
+    let gemini_model = request
+        .gemini_config
+        .model()
+        .unwrap_or(crate::clients::gemini::DEFAULT_MODEL);
+    if !config.gemini.models.allows(gemini_model) {
+        return Err(ApiError::ModelNotAllowed {
+            model: gemini_model.to_string(),
+        });
+    }
+
+    let anthropic_model = request
+        .anthropic_config
+        .model()
+        .unwrap_or(crate::clients::anthropic::DEFAULT_MODEL);
+    if !config.anthropic.models.allows(anthropic_model) {
+        return Err(ApiError::ModelNotAllowed {
+            model: anthropic_model.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Removes surrounding markdown code fences from JSON output.
+///
+/// Handles both plain ```` ``` ```` fences and language-tagged fences
+/// such as ```` ```json ````. Content without fences is returned unchanged.
+///
+/// # Arguments
+///
+/// * `content` - The raw answer text
+///
+/// # Returns
+///
+/// The content with any enclosing fence removed
+fn strip_json_fences(content: &str) -> &str {
+    let trimmed = content.trim();
+    let Some(inner) = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+    else {
+        return content;
+    };
+
+    // Skip the optional language tag on the opening fence line
+    match inner.split_once('\n') {
+        Some((tag, body)) if !tag.trim().starts_with(['{', '[']) => body.trim(),
+        _ => inner.trim(),
+    }
+}
+
+/// Validates that answer content is well-formed JSON.
+///
+/// # Arguments
+///
+/// * `content` - The answer text produced in JSON mode
+///
+/// # Errors
+///
+/// Returns `ApiError::UpstreamFormat` if the content does not parse as JSON
+fn validate_json_content(content: &str) -> Result<()> {
+    serde_json::from_str::<serde_json::Value>(content)
+        .map(|_| ())
+        .map_err(|e| ApiError::UpstreamFormat {
+            message: format!("Answer is not valid JSON: {}", e),
+        })
+}
+
+/// Extracts the reasoning text from a DeepSeek response.
+///
+/// Falls back to the regular message content when `reasoning_content`
+/// is absent, unless `require_reasoning` is set.
+///
+/// # Returns
+///
+/// * `Result<Option<String>>` - The reasoning, or None if there is none
+///
+/// # Errors
+///
+/// Returns `ApiError::DeepSeekError` if reasoning is required but missing
+fn extract_reasoning(response: &DeepSeekResponse, require_reasoning: bool) -> Result<Option<String>> {
+    let choice = response.choices.first();
+    let reasoning = match choice.and_then(|c| c.message.reasoning_content.clone()) {
+        Some(reasoning) => Some(reasoning),
+        None if require_reasoning => {
+            return Err(ApiError::DeepSeekError { 
+                message: "No reasoning content in response".to_string(),
+                type_: "missing_content".to_string(),
+                param: None,
+                code: None,
+                provider_request_id: None
+            });
+        }
+        None => {
+            tracing::warn!("DeepSeek returned no reasoning content, falling back to message content");
+            choice.and_then(|c| c.message.content.clone())
+        }
+    };
+
+    Ok(reasoning.filter(|reasoning| !reasoning.is_empty()))
+}
+
+/// Appends an instruction to the system prompt, adding a system message if there is none.
+fn with_system_instruction(mut messages: Vec<Message>, instruction: &str) -> Vec<Message> {
+    match messages.iter_mut().find(|msg| matches!(msg.role, Role::System)) {
+        Some(msg) => msg.content = format!("{}\n\n{}", msg.content.text(), instruction).into(),
+        None => messages.insert(0, Message {
+            role: Role::System,
+            content: instruction.to_string().into(),
+        }),
+    }
+    messages
+}
+
+/// Answers longer than this multiple of the target word count produce a warning.
+const ANSWER_LENGTH_TOLERANCE: f64 = 1.5;
+
+/// Builds the messages and system prompt for the answer call, adding the
+/// forwarded reasoning where configured and responder-only instructions
+/// such as the answer-length target.
+///
+/// # Arguments
+///
+/// * `request` - The chat request
+/// * `messages` - Messages for the answer call, including the system prompt
+/// * `reasoning` - The thinking block forwarded to the answer model, if any
+/// * `injection` - Where in the prompt the thinking block goes
+///
+/// # Returns
+///
+/// A tuple of (messages, system prompt) for the answer provider
+fn responder_inputs(
+    request: &ApiRequest,
+    mut messages: Vec<Message>,
+    reasoning: Option<&str>,
+    injection: ReasoningInjection,
+) -> (Vec<Message>, Option<String>) {
+    let mut system = request.get_system_prompt();
+    if let Some(thinking) = reasoning {
+        match injection {
+            ReasoningInjection::Assistant => messages.push(Message {
+                role: Role::Assistant,
+                content: thinking.to_string().into(),
+            }),
+            ReasoningInjection::User => match messages.iter_mut().rev().find(|msg| matches!(msg.role, Role::User)) {
+                Some(msg) => msg.content.append_text(thinking),
+                None => messages.push(Message {
+                    role: Role::User,
+                    content: thinking.to_string().into(),
+                }),
+            },
+            ReasoningInjection::System => {
+                messages = with_system_instruction(messages, thinking);
+                system = Some(match system {
+                    Some(system) => format!("{}\n\n{}", system, thinking),
+                    None => thinking.to_string(),
+                });
+            }
+        }
+    }
+
+    match request.answer_length_instruction() {
+        Some(instruction) => with_responder_instruction((messages, system), &instruction),
+        None => (messages, system),
+    }
+}
+
+/// Adds an instruction to both the messages and the system prompt of
+/// responder inputs, so it reaches providers that read either.
+fn with_responder_instruction(
+    (messages, system): (Vec<Message>, Option<String>),
+    instruction: &str,
+) -> (Vec<Message>, Option<String>) {
+    (
+        with_system_instruction(messages, instruction),
+        Some(match system {
+            Some(system) => format!("{}\n\n{}", system, instruction),
+            None => instruction.to_string(),
+        }),
+    )
+}
+
+/// Remaining characters of one kind of content that may be returned to
+/// the client under `max_response_chars`.
+#[derive(Debug)]
+struct CharBudget {
+    /// Characters left, or None when unlimited.
+    remaining: Option<usize>,
+    truncated: bool,
+}
+
+impl CharBudget {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            remaining: limit,
+            truncated: false,
+        }
+    }
+
+    /// Takes as much of `text` as the budget allows.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of (the part of `text` to return, whether this call cut
+    /// the content off); later calls after a cut return nothing
+    fn take<'a>(&mut self, text: &'a str) -> (&'a str, bool) {
+        let Some(remaining) = self.remaining.as_mut() else {
+            return (text, false);
+        };
+        if self.truncated {
+            return ("", false);
+        }
+        match text.char_indices().nth(*remaining) {
+            Some((end, _)) => {
+                *remaining = 0;
+                self.truncated = true;
+                (&text[..end], true)
+            }
+            None => {
+                *remaining -= text.chars().count();
+                (text, false)
+            }
+        }
+    }
+}
+
+/// Checks the answer's length against the requested target.
+///
+/// # Arguments
+///
+/// * `answer` - The final answer text
+/// * `target_words` - The requested length in words, if any
+///
+/// # Returns
+///
+/// A tuple of (word count, warnings); the count is only reported when a target is set
+fn check_answer_length(answer: &str, target_words: Option<u32>) -> (Option<usize>, Vec<String>) {
+    let Some(target) = target_words else {
+        return (None, Vec::new());
+    };
+
+    let count = answer.split_whitespace().count();
+    let mut warnings = Vec::new();
+    if count as f64 > target as f64 * ANSWER_LENGTH_TOLERANCE {
+        tracing::warn!("Answer has {} words, target was about {}", count, target);
+        warnings.push(format!(
+            "Answer has {} words, substantially over the target of about {}",
+            count, target
+        ));
+    }
+    (Some(count), warnings)
+}
+
+/// Marker the reasoner uses to state its conclusion, matched case-insensitively.
+const FINAL_ANSWER_MARKER: &str = "final answer:";
+
+/// Extracts the final answer stated at the end of the reasoning, if any.
+///
+/// Looks for the last line starting with `Final answer:` and returns
+/// everything after the marker.
+///
+/// # Arguments
+///
+/// * `reasoning` - The reasoner's output
+///
+/// # Returns
+///
+/// The stated answer, or None if the reasoning doesn't state one
+fn extract_final_answer(reasoning: &str) -> Option<String> {
+    let line_start = reasoning
+        .match_indices('\n')
+        .map(|(index, _)| index + 1)
+        .chain(std::iter::once(0))
+        .filter(|&start| {
+            reasoning[start..]
+                .trim_start_matches(['*', '#', ' '])
+                .get(..FINAL_ANSWER_MARKER.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(FINAL_ANSWER_MARKER))
+        })
+        .max()?;
+
+    let line = reasoning[line_start..].trim_start_matches(['*', '#', ' ']);
+    let answer = line[FINAL_ANSWER_MARKER.len()..]
+        .trim_start_matches('*')
+        .trim();
+    (!answer.is_empty()).then(|| answer.to_string())
+}
+
+/// Prompt asking the reasoner to critique the responder's draft in the reversed pipeline.
+const CRITIQUE_INSTRUCTION: &str =
+    "Critique the draft answer above. Point out errors, omissions, and weak reasoning, and say how it should be improved.";
+
+/// Runs the reversed pipeline: the responder drafts an answer, then DeepSeek
+/// critiques it, optionally followed by a second answer pass that revises the draft.
+///
+/// # Arguments
+///
+/// * `deepseek_client` - Client for the reasoner
+/// * `answer_client` - Client for the responder
+/// * `request` - The chat request
+/// * `messages` - The original messages, including the system prompt
+///
+/// # Returns
+///
+/// * `Result<(DeepSeekResponse, Option<String>, AnswerResponse)>` - The critique
+///   response, the critique text, and the final (draft or revised) answer
+async fn answer_then_reason(
+    deepseek_client: &DeepSeekClient,
+    answer_client: &AnswerClient,
+    request: &ApiRequest,
+    messages: Vec<Message>,
+) -> Result<(DeepSeekResponse, Option<String>, AnswerResponse)> {
+    let answer_config = request.answer_config(answer_client.provider());
+
+    // Draft an answer from the original conversation
+    let (draft_messages, system) = responder_inputs(request, messages.clone(), None, ReasoningInjection::default());
+    let draft = answer_client.chat(draft_messages, system.clone(), answer_config).await?;
+    let draft_message = Message {
+        role: Role::Assistant,
+        content: draft.text.clone().into(),
+    };
+
+    // Have the reasoner critique the draft
+    let mut critique_messages = messages.clone();
+    critique_messages.push(draft_message.clone());
+    critique_messages.push(Message {
+        role: Role::User,
+        content: CRITIQUE_INSTRUCTION.to_string().into(),
+    });
+    let deepseek_response = deepseek_client.chat(critique_messages, &request.deepseek_config).await?;
+    let critique = deepseek_response
+        .choices
+        .first()
+        .and_then(|c| c.message.content.clone().or_else(|| c.message.reasoning_content.clone()))
+        .filter(|critique| !critique.is_empty());
+
+    let Some(critique_text) = critique.as_ref().filter(|_| request.revise_after_critique) else {
+        return Ok((deepseek_response, critique, draft));
+    };
+
+    // Revise the draft to address the critique
+    let mut revise_messages = messages;
+    revise_messages.push(draft_message);
+    revise_messages.push(Message {
+        role: Role::User,
+        content: format!(
+            "Here is a critique of your answer:\n\n{}\n\nRevise your answer to address it.",
+            critique_text
+        )
+        .into(),
+    });
+    let (revise_messages, _) = responder_inputs(request, revise_messages, None, ReasoningInjection::default());
+    let revised = answer_client.chat(revise_messages, system, answer_config).await?;
+
+    // Bill both answer passes
+    let usage = AnswerUsage {
+        input_tokens: draft.usage.input_tokens + revised.usage.input_tokens,
+        output_tokens: draft.usage.output_tokens + revised.usage.output_tokens,
+        cached_input_tokens: draft.usage.cached_input_tokens + revised.usage.cached_input_tokens,
+        cache_creation_input_tokens: draft.usage.cache_creation_input_tokens
+            + revised.usage.cache_creation_input_tokens,
+    };
+
+    Ok((deepseek_response, critique, AnswerResponse { usage, ..revised }))
+}
+
+/// Builds the error returned when reasoning is shorter than the configured minimum.
+fn insufficient_reasoning_error(min_chars: usize) -> ApiError {
+    ApiError::DeepSeekError {
+        message: format!("Reasoning shorter than the minimum of {} characters", min_chars),
+        type_: "insufficient_reasoning".to_string(),
+        param: None,
+        code: None,
+        provider_request_id: None,
+    }
+}
+
+/// Status recorded for streams whose client disconnected before `done`,
+/// following nginx's "client closed request" convention.
+const CLIENT_CLOSED_REQUEST: u16 = 499;
+
+/// Records the final status of a streaming request on the current span.
+///
+/// # Arguments
+///
+/// * `status` - HTTP-style status code describing the outcome
+fn record_stream_outcome(status: u16) {
+    tracing::Span::current().record("status", status);
+    if status == CLIENT_CLOSED_REQUEST {
+        tracing::info!(status, "Client disconnected before stream completed");
+    } else {
+        tracing::info!(status, "Stream completed");
+    }
+}
+
+/// Destination of a stream's events: the live SSE response, plus the
+/// replay buffer when the stream is resumable.
+///
+/// The replay buffer is marked finished when the sink is dropped at the
+/// end of the streaming task.
+struct StreamSink {
+    tx: tokio::sync::mpsc::Sender<SseResult>,
+    replay: Option<Arc<StreamBuffer>>,
+    /// Drop every event except `content`, `usage`, and `error`.
+    minimal: bool,
+    /// SSE names sent in place of built-in event names, from `sse_event_names`.
+    event_names: HashMap<String, String>,
+}
+
+/// Events still sent to clients that asked for minimal events.
+const MINIMAL_EVENTS: [&str; 3] = ["content", "usage", "error"];
+
+impl Drop for StreamSink {
+    fn drop(&mut self) {
+        if let Some(replay) = &self.replay {
+            replay.finish();
+        }
+    }
+}
+
+/// Sends an event on a streaming response channel.
+///
+/// A send only fails once the client has disconnected. For a resumable
+/// stream the event is still buffered and the stream carries on, so the
+/// client can reconnect; otherwise the outcome is recorded as
+/// `CLIENT_CLOSED_REQUEST`.
+///
+/// # Arguments
+///
+/// * `tx` - The sink feeding the SSE response
+/// * `name` - The built-in event name, sent as configured in `sse_event_names`
+/// * `event` - The event payload
+///
+/// # Returns
+///
+/// `true` if the stream should continue, `false` if the client is gone
+async fn send_event(tx: &StreamSink, name: &str, event: &StreamEvent) -> bool {
+    if tx.minimal && !MINIMAL_EVENTS.contains(&name) {
+        return true;
+    }
+    let (name, data) = serialize_event(name, event);
+    let name = tx.event_names.get(name).map_or(name, String::as_str);
+    let mut sse = Event::default().event(name);
+    if let Some(replay) = &tx.replay {
+        sse = sse.id(replay.push(name, &data));
+    }
+
+    let sent = tx.tx.send(Ok(sse.data(data))).await.is_ok();
+    if !sent && tx.replay.is_none() {
+        record_stream_outcome(CLIENT_CLOSED_REQUEST);
+        return false;
+    }
+    true
+}
+
+/// Serializes an event for SSE.
+///
+/// An event that fails to serialize is logged and replaced with an `error`
+/// event, so clients never receive an empty or malformed payload.
+///
+/// # Returns
+///
+/// * `(&str, String)` - The SSE event name and JSON data to send
+fn serialize_event<'a>(name: &'a str, event: &impl serde::Serialize) -> (&'a str, String) {
+    match serde_json::to_string(event) {
+        Ok(data) => (name, data),
+        Err(e) => {
+            tracing::error!("Failed to serialize {} event: {}", name, e);
+            let error = ApiError::Internal {
+                message: format!("Failed to serialize {} event", name),
+            };
+            let fallback = StreamEvent::Error {
+                error: error.status_and_body(&HashMap::new()).1.error,
+                status: 500,
+                partial: false,
+                delivered_chars: None,
+            };
+            let data = serde_json::to_string(&fallback)
+                .unwrap_or_else(|_| SERIALIZATION_ERROR_EVENT.to_string());
+            ("error", data)
+        }
+    }
+}
+
+/// Sends content blocks as one `content` event, doing nothing if there are none.
+///
+/// # Returns
+///
+/// * `bool` - False if the client has disconnected
+async fn send_content(tx: &StreamSink, content: Vec<ContentBlock>) -> bool {
+    if content.is_empty() {
+        return true;
+    }
+    send_event(tx, "content", &StreamEvent::Content { content }).await
+}
+
+/// Sends any batched content, then a `truncated` event for content of
+/// `kind` that reached its size cap.
+///
+/// # Returns
+///
+/// * `bool` - False if the client has disconnected
+async fn send_truncated_event(
+    tx: &StreamSink,
+    coalescer: &mut DeltaCoalescer,
+    kind: ContentKind,
+    max_chars: Option<usize>,
+) -> bool {
+    if !send_content(tx, coalescer.flush()).await {
+        return false;
+    }
+    let event = StreamEvent::Truncated {
+        kind,
+        max_chars: max_chars.unwrap_or_default(),
+    };
+    send_event(tx, "truncated", &event).await
+}
+
+/// Sends an error event on a streaming response channel and records
+/// `code` as the stream's outcome.
+///
+/// # Arguments
+///
+/// * `tx` - The sink feeding the SSE response
+/// * `type_` - Machine-readable error type
+/// * `message` - Human-readable error message
+/// * `code` - HTTP-style status code describing the error
+async fn send_error_event(
+    tx: &StreamSink,
+    type_: &str,
+    message: impl Into<String>,
+    code: u16,
+) {
+    let event = StreamEvent::Error {
+        error: ErrorPayload {
+            message: message.into(),
+            type_: type_.to_string(),
+            param: None,
+            code: None,
+            provider: None,
+            provider_request_id: None,
+        },
+        status: code,
+        partial: false,
+        delivered_chars: None,
+    };
+    if send_event(tx, "error", &event).await {
+        record_stream_outcome(code);
+    }
+}
+
+/// Sends a `cancelled` event after a stream was cancelled by the client.
+async fn send_cancelled_event(tx: &StreamSink) {
+    if send_event(tx, "cancelled", &StreamEvent::Cancelled {}).await {
+        tracing::Span::current().record("status", CLIENT_CLOSED_REQUEST);
+        tracing::info!(status = CLIENT_CLOSED_REQUEST, "Stream cancelled by client");
+    }
+}
+
+/// Sends an error event for an `ApiError`, with the same payload a
+/// non-streaming response would carry, including the provider and its
+/// request ID when the error came from upstream.
+///
+/// # Arguments
+///
+/// * `tx` - The sink feeding the SSE response
+/// * `error` - The upstream error
+/// * `code` - HTTP-style status code describing the error, unless the
+///   error is a provider error with a mapped status
+/// * `delivered_chars` - Characters of the answer already streamed, if any,
+///   marking the response as partial
+/// * `config` - Configuration containing the provider error mappings
+async fn send_upstream_error_event(
+    tx: &StreamSink,
+    error: &ApiError,
+    code: u16,
+    delivered_chars: Option<usize>,
+    config: &Config,
+) {
+    // Safety blocks are a property of the content, not a server failure,
+    // and mapped provider errors carry their configured status
+    let code = match error {
+        ApiError::ContentFiltered { .. } | ApiError::PromptBlocked { .. } => 422,
+        _ => error
+            .provider_error_mapping(&config.errors.provider_errors)
+            .map_or(code, |(status, _)| status.as_u16()),
+    };
+    let event = StreamEvent::Error {
+        error: error.status_and_body(&config.errors.provider_errors).1.error,
+        status: code,
+        partial: delivered_chars.is_some(),
+        delivered_chars,
+    };
+    if send_event(tx, "error", &event).await {
+        record_stream_outcome(code);
+    }
+}
+
+/// Upstream deadlines derived from the `X-Request-Deadline-Ms` header.
+#[derive(Debug, Clone, Copy)]
+struct PhaseDeadlines {
+    /// When the reasoning phase must finish.
+    reasoning: tokio::time::Instant,
+    /// When the answer phase, and so the whole request, must finish.
+    answer: tokio::time::Instant,
+}
+
+/// Splits the budget left before the caller's deadline between the
+/// reasoning and answer phases.
+///
+/// The reasoning phase may use `deadline_reasoning_share` of the budget;
+/// the answer phase runs until the deadline itself, so time the reasoning
+/// phase doesn't use carries over.
+///
+/// # Returns
+///
+/// * `Result<Option<PhaseDeadlines>>` - The deadlines, or None if the
+///   request carries no deadline
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the header is not a timestamp, or
+/// `ApiError::DeadlineExceeded` if the deadline has already passed
+fn phase_deadlines(headers: &axum::http::HeaderMap, config: &Config) -> Result<Option<PhaseDeadlines>> {
+    let Some(value) = headers.get(REQUEST_DEADLINE_HEADER) else {
+        return Ok(None);
+    };
+    let deadline_ms: i64 = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| ApiError::BadRequest {
+            message: format!("{} must be a Unix timestamp in milliseconds", REQUEST_DEADLINE_HEADER),
+        })?;
+
+    let remaining_ms = deadline_ms - Utc::now().timestamp_millis();
+    if remaining_ms <= 0 {
+        return Err(ApiError::DeadlineExceeded {
+            message: format!("deadline passed {} ms before the request arrived", -remaining_ms),
+        });
+    }
+
+    let share = config
+        .pipeline
+        .deadline_reasoning_share
+        .unwrap_or(DEFAULT_DEADLINE_REASONING_SHARE)
+        .clamp(0.0, 1.0);
+    let budget = Duration::from_millis(remaining_ms as u64);
+    let now = tokio::time::Instant::now();
+    Ok(Some(PhaseDeadlines {
+        reasoning: now + budget.mul_f64(share),
+        answer: now + budget,
+    }))
+}
+
+/// Runs an upstream call, failing it if it outlives `deadline`.
+///
+/// # Arguments
+///
+/// * `deadline` - When the call must finish, or None for no limit
+/// * `phase` - Name of the call, used in the error message
+/// * `call` - The upstream call
+///
+/// # Errors
+///
+/// Returns `ApiError::DeadlineExceeded` if the deadline passes first,
+/// or the call's own error
+async fn within_deadline<T>(
+    deadline: Option<tokio::time::Instant>,
+    phase: &str,
+    call: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(deadline) = deadline else {
+        return call.await;
+    };
+    tokio::time::timeout_at(deadline, call)
+        .await
+        .map_err(|_| ApiError::DeadlineExceeded {
+            message: format!("{} did not finish in time", phase),
+        })?
+}
+
+/// Generates a fresh request ID.
+///
+/// Stream request IDs are what the cancel endpoint and resumption address,
+/// so they come from the operating system's CSPRNG to be unguessable.
+fn new_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("operating system random number generator failed");
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("req_{}", hex)
+}
+
+/// Adds a completed request's usage to the running totals and writes it
+/// through the configured sink, if any.
+///
+/// The sink queues the record without blocking, and a failed write never
+/// affects the request.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the usage sink
+/// * `request_id` - ID of the completed request
+/// * `request` - The chat request, used to resolve the models
+/// * `config` - Configuration snapshot used for the request
+/// * `usage` - Final usage of the request
+/// * `started` - When the request started
+/// * `tally` - Token counts of the HTTP request, for the abuse log
+fn record_usage(
+    state: &AppState,
+    request_id: String,
+    request: &ApiRequest,
+    config: &Config,
+    usage: &CombinedUsage,
+    started: Instant,
+    tally: Option<&RequestTally>,
+) {
+    if let Some(tally) = tally {
+        tally.add(usage);
+    }
+
+    // Attribute the answer to the provider that served it, which may be the fallback
+    let answer_provider = [AnswerProvider::Gemini, AnswerProvider::Anthropic]
+        .into_iter()
+        .find(|provider| provider.name().eq_ignore_ascii_case(&usage.answer.provider))
+        .unwrap_or(config.pipeline.answer_provider);
+    let record = UsageRecord {
+        timestamp: Utc::now(),
+        request_id,
+        stream: request.stream == Some(true),
+        reasoning_model: deepseek_model(&request.deepseek_config).to_string(),
+        answer_model: answer_model(answer_provider, request.answer_config(answer_provider)).to_string(),
+        reasoning: usage.reasoning.clone(),
+        answer: usage.answer.clone(),
+        total_cost: usage.total_cost(),
+        latency_ms: started.elapsed().as_millis() as u64,
+    };
+
+    state.usage_stats.add(&record);
+    if let Some(sink) = &state.usage_sink {
+        sink.record(record);
+    }
+}
+
+/// An in-flight stream that the cancel endpoint can stop.
+pub struct ActiveStream {
+    token: CancellationToken,
+    /// Hash of the credential that started the stream; only the same
+    /// credential may cancel it.
+    owner: Option<String>,
+}
+
+/// Registration of an in-flight stream in `AppState::active_streams`.
+///
+/// Removes the entry when dropped, so the registry is cleaned up however
+/// the streaming task ends.
+struct StreamRegistration {
+    state: Arc<AppState>,
+    request_id: String,
+}
+
+impl StreamRegistration {
+    /// Registers a new stream under a fresh random request ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Application state holding the stream registry
+    /// * `owner` - Hash of the credential that started the stream
+    ///
+    /// # Returns
+    ///
+    /// A tuple of (registration, token cancelled by the cancel endpoint)
+    fn new(state: Arc<AppState>, owner: Option<String>) -> (Self, CancellationToken) {
+        let request_id = new_request_id();
+        let token = CancellationToken::new();
+        state
+            .active_streams
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(request_id.clone(), ActiveStream { token: token.clone(), owner });
+        (Self { state, request_id }, token)
+    }
+}
+
+impl Drop for StreamRegistration {
+    fn drop(&mut self) {
+        self.state
+            .active_streams
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.request_id);
+    }
+}
+
+/// Resumes a buffered stream after the client's last received event.
+///
+/// Replays the buffered events after `last_event_id`, then follows the
+/// stream live if it is still running. No upstream call is made.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the replay buffers
+/// * `config` - Configuration snapshot for the channel size
+/// * `headers` - Request headers carrying the client's credential
+/// * `last_event_id` - The `Last-Event-ID` header value
+///
+/// # Returns
+///
+/// * `Result<SseResponse>` - The resumed stream
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if resumable streams are disabled or the
+/// id is malformed, or `ApiError::NotFound` if the stream can no longer be
+/// resumed or was started with another credential
+fn resume_stream(
+    state: &AppState,
+    config: &Config,
+    headers: &axum::http::HeaderMap,
+    last_event_id: &str,
+) -> Result<SseResponse> {
+    let replay = state.replay.as_ref().ok_or_else(|| ApiError::BadRequest {
+        message: "Resumable streams are not enabled".to_string(),
+    })?;
+
+    let owner = abuse::credential_hash(headers);
+    match replay.resume(last_event_id, owner.as_deref(), config.server.stream_channel_buffer) {
+        Ok(rx) => {
+            tracing::info!(last_event_id, "Resuming stream");
+            Ok(SseResponse::new(ReceiverStream::new(rx)))
+        }
+        Err(ResumeError::InvalidId) => Err(ApiError::BadRequest {
+            message: format!("Invalid {} header: {}", LAST_EVENT_ID_HEADER, last_event_id),
+        }),
+        Err(ResumeError::UnknownStream) => Err(ApiError::NotFound {
+            message: format!("No resumable stream for event {}; it may have expired", last_event_id),
+        }),
+        Err(ResumeError::EventsDropped) => Err(ApiError::NotFound {
+            message: format!("Events after {} are no longer buffered", last_event_id),
+        }),
+    }
+}
+
+/// Screens a request against the moderation rules.
+///
+/// Requests carrying a configured bypass token in the
+/// `X-Moderation-Bypass` header are not screened.
+///
+/// # Errors
+///
+/// Returns `ApiError::ContentRejected` if any message matches a rule
+fn moderate(
+    state: &AppState,
+    config: &Config,
+    headers: &axum::http::HeaderMap,
+    request: &ApiRequest,
+) -> Result<()> {
+    let bypass = headers
+        .get(MODERATION_BYPASS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|token| {
+            config
+                .moderation
+                .bypass_tokens
+                .iter()
+                .any(|trusted| constant_time_eq(trusted.as_bytes(), token.as_bytes()))
+        });
+    if bypass {
+        return Ok(());
+    }
+    state.moderation.check(request)
+}
+
+/// Decides whether a chat request streams.
+///
+/// The query parameter wins, then the body flag. When neither is given, the
+/// `Accept` header decides if it names exactly one of `text/event-stream`
+/// and `application/json`. An `Accept` header naming both, neither, or a
+/// wildcard is ambiguous, and the response is not streamed.
+///
+/// # Arguments
+///
+/// * `query` - The `stream` query parameter, if given
+/// * `headers` - HTTP request headers
+/// * `body` - The body's `stream` flag, if given
+///
+/// # Returns
+///
+/// * `bool` - Whether to stream the response
+fn stream_mode(query: Option<bool>, headers: &axum::http::HeaderMap, body: Option<bool>) -> bool {
+    if let Some(stream) = query.or(body) {
+        return stream;
+    }
+
+    let mut wants_sse = false;
+    let mut wants_json = false;
+    for value in headers.get_all(axum::http::header::ACCEPT) {
+        let Ok(value) = value.to_str() else { continue };
+        for media_type in value.split(',') {
+            let media_type = media_type.split(';').next().unwrap_or_default().trim();
+            if media_type.eq_ignore_ascii_case("text/event-stream") {
+                wants_sse = true;
+            } else if media_type.eq_ignore_ascii_case("application/json") {
+                wants_json = true;
+            }
+        }
+    }
+
+    wants_sse && !wants_json
+}
+
+/// Main handler for chat requests.
+///
+/// Routes requests to either streaming or non-streaming handlers. The mode
+/// is taken from the `stream` query parameter if present, then from the
+/// body's `stream` flag, and finally from an `Accept` header naming only
+/// one of `text/event-stream` and `application/json`.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `query` - Query parameters, which may override the streaming mode
+/// * `headers` - HTTP request headers
+/// * `tally` - Token counts of the HTTP request, set by `track_client`
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Response>` - The API response or an error
+///
+/// # Errors
+///
+/// Returns `ApiError::ContentRejected` if the input fails moderation,
+/// `ApiError::NotFound` if a resumed stream is no longer buffered, or
+/// `ApiError::Overloaded` if no request slot frees up in time
+pub async fn handle_chat(
+    state: State<Arc<AppState>>,
+    Query(query): Query<ChatQuery>,
+    headers: axum::http::HeaderMap,
+    ClientTally(tally): ClientTally,
+    Json(mut request): Json<ApiRequest>,
+) -> Result<axum::response::Response> {
+    let config = state.config();
+    let stream = stream_mode(query.stream, &headers, request.stream);
+    request.stream = Some(stream);
+
+    // Screen the input before spending anything upstream
+    moderate(&state, &config, &headers, &request)?;
+
+    // A reconnecting client resumes its earlier stream instead of re-running it
+    let last_event_id = headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if let Some(last_event_id) = last_event_id.filter(|_| stream) {
+        return Ok(resume_stream(&state, &config, &headers, last_event_id)?.into_response());
+    }
+
+    // Don't start work the caller has already given up on
+    phase_deadlines(&headers, &config)?;
+
+    // Refuse streaming options that would silently degrade; dry runs never stream
+    if stream && !request.dry_run {
+        request.validate_streamable()?;
+    }
+
+    // Reserve a slot for the lifetime of the request, including any stream
+    let permit = state.request_limiter.acquire().await?;
+
+    // Dry runs only estimate usage, so they never stream or need provider tokens
+    if request.dry_run {
+        if !request.validate_system_prompt() {
+            return Err(ApiError::InvalidSystemPrompt);
+        }
+        request.validate_message_count(config.server.max_messages)?;
+        request.validate_message_chars(config.server.max_message_chars)?;
+        request.validate_messages()?;
+        let request = with_server_defaults(request, &config);
+        return Ok(Json(DryRunResponse {
+            dry_run: true,
+            estimated_usage: estimate_usage(&request, &config),
+        })
+        .into_response());
+    }
+
+    if stream {
+        let stream_response = chat_stream(state, headers, Json(request), permit, tally).await?;
+        Ok(stream_response.into_response())
+    } else {
+        let json_response = chat(state, headers, Json(request), tally).await?;
+        drop(permit);
+        Ok(json_response.into_response())
+    }
+}
+
+/// Handler for batch requests.
+///
+/// Runs each request through the non-streaming pipeline, at most
+/// `batch_max_concurrency` at a time. A failing request is reported in its
+/// result slot and does not fail the batch. Each request takes its own slot
+/// under the concurrent request limit; one that gets none is reported as
+/// overloaded in its result slot.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers, shared by every request in the batch
+/// * `tally` - Token counts of the HTTP request, set by `track_client`
+/// * `batch` - The requests to process
+///
+/// # Returns
+///
+/// * `Result<Json<BatchResponse>>` - Per-request results and the total cost
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the batch holds more than
+/// `batch_max_requests` requests
+pub async fn handle_batch(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    ClientTally(tally): ClientTally,
+    Json(batch): Json<BatchRequest>,
+) -> Result<Json<BatchResponse>> {
+    let config = state.config();
+    if batch.requests.len() > config.server.batch_max_requests {
+        return Err(ApiError::BadRequest {
+            message: format!(
+                "Too many batch requests: {} exceeds the limit of {}",
+                batch.requests.len(),
+                config.server.batch_max_requests
+            ),
+        });
+    }
+
+    let concurrency = config.server.batch_max_concurrency.max(1);
+    let results: Vec<BatchItemResult> = futures::stream::iter(batch.requests.into_iter().enumerate())
+        .map(|(index, request)| {
+            let state = state.clone();
+            let config = config.clone();
+            let headers = headers.clone();
+            let tally = tally.clone();
+            async move {
+                let result = if request.stream == Some(true) || request.dry_run {
+                    Err(ApiError::BadRequest {
+                        message: "stream and dry_run are not supported in batch requests".to_string(),
+                    })
+                } else if let Err(e) = moderate(&state, &config, &headers, &request) {
+                    Err(e)
+                } else {
+                    match state.request_limiter.acquire().await {
+                        Ok(_permit) => chat(State(state.clone()), headers, Json(request), tally).await,
+                        Err(e) => Err(e),
+                    }
+                };
+
+                match result {
+                    Ok((_, Json(response))) => BatchItemResult {
+                        index,
+                        response: Some(response),
+                        status: None,
+                        error: None,
+                    },
+                    Err(e) => {
+                        let (status, body) = e.status_and_body(&config.errors.provider_errors);
+                        BatchItemResult {
+                            index,
+                            response: None,
+                            status: Some(status.as_u16()),
+                            error: Some(body.error),
+                        }
+                    }
+                }
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let total_cost: f64 = results
+        .iter()
+        .filter_map(|result| result.response.as_ref())
+        .map(|response| response.combined_usage.total_cost())
+        .sum();
+    let succeeded = results.iter().filter(|result| result.response.is_some()).count();
+
+    Ok(Json(BatchResponse {
+        failed: results.len() - succeeded,
+        succeeded,
+        total_cost: format_cost(total_cost, &config.cost_format),
+        results,
+    }))
+}
+
+/// Handler for cancelling an in-flight stream.
+///
+/// Triggers the stream's cancellation token; the streaming task stops
+/// polling upstream and sends a `cancelled` event. Only a request with the
+/// credential that started the stream may cancel it.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the stream registry
+/// * `request_id` - ID from the stream's `start` event
+/// * `headers` - Request headers carrying the caller's credential
+///
+/// # Returns
+///
+/// * `Result<Json<CancelResponse>>` - Confirmation of the cancellation
+///
+/// # Errors
+///
+/// Returns `ApiError::NotFound` if no stream with that ID is in flight,
+/// or if it was started with another credential
+pub async fn cancel_stream(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<CancelResponse>> {
+    // Another client's stream is reported as missing, so IDs can't be probed
+    let caller = abuse::credential_hash(&headers);
+    let token = state
+        .active_streams
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&request_id)
+        .filter(|stream| stream.owner == caller)
+        .map(|stream| stream.token.clone())
+        .ok_or_else(|| ApiError::NotFound {
+            message: format!("No in-flight stream with request ID {}", request_id),
+        })?;
+    token.cancel();
+
+    Ok(Json(CancelResponse {
+        cancelled: true,
+        request_id,
+    }))
+}
+
+/// Handler for the cost endpoint.
+///
+/// Prices saved token counts with the server's current pricing, without
+/// calling any provider.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing pricing configuration
+/// * `request` - Token counts for the reasoning and answer calls
+///
+/// # Returns
+///
+/// * `Result<Json<CombinedUsage>>` - The priced usage for both phases
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if any token count is negative or too large
+pub async fn calculate_cost(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CostRequest>,
+) -> Result<Json<CombinedUsage>> {
+    let config = &state.config();
+    let deepseek = &request.deepseek;
+    let input_tokens = TokenCounts::checked("deepseek.input_tokens", deepseek.input_tokens)?;
+    let output_tokens = TokenCounts::checked("deepseek.output_tokens", deepseek.output_tokens)?;
+    let reasoning_tokens = TokenCounts::checked("deepseek.reasoning_tokens", deepseek.reasoning_tokens)?;
+    let cached_input_tokens = TokenCounts::checked("deepseek.cached_input_tokens", deepseek.cached_input_tokens)?;
+    let cost_breakdown = calculate_deepseek_cost(
+        input_tokens,
+        output_tokens,
+        reasoning_tokens,
+        cached_input_tokens,
+        deepseek.model.as_deref().unwrap_or(deepseek::DEFAULT_MODEL),
+        config,
+    );
+    let reasoning = ProviderUsage {
+        provider: "deepseek".to_string(),
+        input_tokens,
+        output_tokens,
+        reasoning_tokens,
+        cached_input_tokens,
+        total_tokens: input_tokens.saturating_add(output_tokens),
+        cost: cost_breakdown.total(),
+        cost_breakdown: Some(cost_breakdown),
+        cached: false,
+        forwarded_reasoning_tokens: None,
+        retries: 0,
+    };
+
+    let answer = &request.answer;
+    let answer_provider = request.answer_provider.unwrap_or(config.pipeline.answer_provider);
+    let answer_counts = AnswerUsage {
+        input_tokens: TokenCounts::checked("answer.input_tokens", answer.input_tokens)?,
+        output_tokens: TokenCounts::checked("answer.output_tokens", answer.output_tokens)?,
+        cached_input_tokens: TokenCounts::checked("answer.cached_input_tokens", answer.cached_input_tokens)?,
+        cache_creation_input_tokens: TokenCounts::checked(
+            "answer.cache_creation_input_tokens",
+            answer.cache_creation_input_tokens,
+        )?,
+    };
+    // The model only affects pricing through the request config
+    let answer_config = ApiConfig {
+        body: answer
+            .model
+            .as_ref()
+            .map(|model| serde_json::json!({ "model": model }))
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    Ok(Json(CombinedUsage {
+        reasoning,
+        answer: answer_usage(answer_provider, answer_counts, &answer_config, config),
+        cost_format: config.cost_format.clone(),
+        timing: StreamTiming::default(),
+    }))
+}
+
+/// Handler for reloading pricing from the config file.
+///
+/// Re-reads `config.toml` and replaces only the pricing section; other
+/// settings still require a restart. In-flight requests keep the prices
+/// they started with.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the configuration
+/// * `headers` - HTTP request headers, carrying `X-Admin-Token`
+///
+/// # Returns
+///
+/// * `Result<Json<PricingConfig>>` - The pricing now in effect
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - `ApiError::NotFound` - No admin token is configured
+/// - `ApiError::Unauthorized` - The admin token is missing or wrong
+/// - `ApiError::Internal` - The config file cannot be loaded or fails validation
+pub async fn reload_pricing(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<PricingConfig>> {
+    check_admin_token(&state.config(), &headers)?;
+
+    let loaded = Config::load().map_err(|e| ApiError::Internal {
+        message: format!("Failed to reload config: {}", e),
+    })?;
+
+    swap_pricing(&state, loaded).map(Json)
+}
+
+/// Replaces the pricing in effect with that of `loaded`, if `loaded`
+/// passes the same validation as at startup.
+///
+/// # Returns
+///
+/// * `Result<PricingConfig>` - The pricing now in effect
+///
+/// # Errors
+///
+/// Returns `ApiError::Internal` if `loaded` is invalid; the pricing in
+/// effect is left unchanged.
+fn swap_pricing(state: &AppState, loaded: Config) -> Result<PricingConfig> {
+    loaded.validate().map_err(|e| ApiError::Internal {
+        message: format!("Reloaded config is invalid: {}", e),
+    })?;
+
+    let mut config = state.config.write().unwrap_or_else(|e| e.into_inner());
+    tracing::info!(old = ?config.pricing, new = ?loaded.pricing, "Reloaded pricing");
+    let mut updated = Config::clone(&config);
+    updated.pricing = loaded.pricing;
+    *config = Arc::new(updated);
+
+    Ok(config.pricing.clone())
+}
+
+/// Handler for the admin load endpoint.
+///
+/// Reports how many chat requests are in flight and queued, against the
+/// configured limits.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the request limiter
+/// * `headers` - HTTP request headers, carrying `X-Admin-Token`
+///
+/// # Returns
+///
+/// * `Result<Json<LoadResponse>>` - The current load
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - `ApiError::NotFound` - No admin token is configured
+/// - `ApiError::Unauthorized` - The admin token is missing or wrong
+pub async fn load_status(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<LoadResponse>> {
+    let config = state.config();
+    check_admin_token(&config, &headers)?;
+
+    Ok(Json(LoadResponse {
+        in_flight: state.request_limiter.in_flight(),
+        queued: state.request_limiter.queued(),
+        max_concurrent_requests: config.server.max_concurrent_requests,
+        max_queue_depth: config.server.max_queue_depth,
+    }))
+}
+
+/// Handler for the admin usage endpoint.
+///
+/// Returns aggregate usage since startup: request count, tokens and cost
+/// per provider and per model, and uptime.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the usage totals
+/// * `headers` - HTTP request headers, carrying `X-Admin-Token`
+///
+/// # Returns
+///
+/// * `Result<Json<UsageSummary>>` - A snapshot of the totals
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - `ApiError::NotFound` - No admin token is configured
+/// - `ApiError::Unauthorized` - The admin token is missing or wrong
+pub async fn usage_summary(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<UsageSummary>> {
+    let config = state.config();
+    check_admin_token(&config, &headers)?;
+
+    Ok(Json(state.usage_stats.summary(&config.cost_format)))
+}
+
+/// Checks the `X-Admin-Token` header against the configured admin token.
+///
+/// # Errors
+///
+/// Returns `ApiError::NotFound` if no admin token is configured, or
+/// `ApiError::Unauthorized` if the header is missing or wrong
+fn check_admin_token(config: &Config, headers: &axum::http::HeaderMap) -> Result<()> {
+    let Some(admin_token) = config.server.admin_token.as_deref() else {
+        return Err(ApiError::NotFound {
+            message: "Admin endpoints are disabled".to_string(),
+        });
+    };
+    let provided = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !constant_time_eq(provided.as_bytes(), admin_token.as_bytes()) {
+        return Err(ApiError::Unauthorized {
+            message: format!("Missing or invalid {} header", ADMIN_TOKEN_HEADER),
+        });
+    }
+    Ok(())
+}
+
+/// Middleware requiring a configured service API key on API endpoints.
+///
+/// Passes every request through when no keys are configured, and requests
+/// to paths listed in `auth_exempt_paths`.
+///
+/// # Errors
+///
+/// Returns `ApiError::Unauthorized` if the `Authorization` header is
+/// missing or doesn't carry a configured key
+pub async fn require_service_key(
+    State(state): State<Arc<AppState>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<axum::response::Response> {
+    let config = state.config();
+    let server = &config.server;
+    if server.service_api_keys.is_empty()
+        || server.auth_exempt_paths.iter().any(|path| path == request.uri().path())
+    {
+        return Ok(next.run(request).await);
+    }
+
+    let provided = request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Check every key so timing doesn't reveal which one nearly matched
+    let authorized = server
+        .service_api_keys
+        .iter()
+        .fold(false, |found, key| found | constant_time_eq(provided.as_bytes(), key.as_bytes()));
+    if !authorized {
+        return Err(ApiError::Unauthorized {
+            message: "Missing or invalid service API key".to_string(),
+        });
+    }
+    Ok(next.run(request).await)
+}
+
+/// Middleware rendering provider errors with the configured
+/// `[errors.provider_errors]` mappings.
+///
+/// `ApiError`'s `IntoResponse` only knows the built-in mappings, and keeps
+/// the error in the response extensions; a provider error found there is
+/// rendered again here with the mappings of the current config.
+pub async fn map_provider_errors(
+    State(state): State<Arc<AppState>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let response = next.run(request).await;
+    let config = state.config();
+    match response.extensions().get::<ApiError>() {
+        Some(error) if !config.errors.provider_errors.is_empty() => error.to_response(&config.errors.provider_errors),
+        _ => response,
+    }
+}
+
+/// Middleware logging each request's client, sizes, and token counts, and
+/// counting its client's recent requests.
+///
+/// The log line is written once the response body has been sent, so
+/// streams are logged with their full size. Clients are identified as in
+/// `abuse::client_id`, so credentials appear only as hashes. Passes every
+/// request through untouched when abuse detection is disabled.
+///
+/// # Errors
+///
+/// Returns `ApiError::TooManyRequests` if the client is over
+/// `abuse.abuse_threshold` and `abuse.reject_over_threshold` is set
+pub async fn track_client(
+    State(state): State<Arc<AppState>>,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<axum::response::Response> {
+    let Some(monitor) = &state.abuse_monitor else {
+        return Ok(next.run(request).await);
+    };
+    let config = state.config();
+    let abuse = &config.abuse;
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<std::net::SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = crate::abuse::client_id(request.headers(), peer);
+    let recent = monitor.hit(&client);
+    if let Some(threshold) = abuse.abuse_threshold.filter(|&threshold| recent > threshold) {
+        tracing::warn!(
+            client = %client,
+            requests = recent,
+            threshold,
+            window_secs = abuse.window_secs,
+            "Client exceeded the abuse threshold"
+        );
+        if abuse.reject_over_threshold {
+            return Err(ApiError::TooManyRequests {
+                retry_after_secs: abuse.window_secs,
+            });
+        }
+    }
+
+    let tally = Arc::new(RequestTally::default());
+    request.extensions_mut().insert(tally.clone());
+    let mut log = RequestLog {
+        client,
+        method: request.method().to_string(),
+        path: request.uri().path().to_string(),
+        status: 0,
+        request_bytes: request
+            .headers()
+            .get(axum::http::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default(),
+        response_bytes: 0,
+        tally,
+    };
+
+    let response = next.run(request).await;
+    log.status = response.status().as_u16();
+    let (parts, body) = response.into_parts();
+    // The log moves into the body and is written when the body is dropped
+    let body = axum::body::Body::from_stream(body.into_data_stream().map(move |chunk| {
+        // Borrowing the whole log makes the closure own it; naming only the
+        // counter would capture a copy and log the request right away
+        let log = &mut log;
+        if let Ok(bytes) = &chunk {
+            log.response_bytes += bytes.len() as u64;
+        }
+        chunk
+    }));
+    Ok(axum::response::Response::from_parts(parts, body))
+}
+
+/// Compares two byte strings in time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Handler for the debug fingerprint endpoint.
+///
+/// Returns the normalized byte sequence and hash used to identify
+/// a request, without calling any upstream provider.
+///
+/// # Arguments
+///
+/// * `request` - The chat request to fingerprint
+///
+/// # Returns
+///
+/// * `Json<FingerprintResponse>` - The normalized request and its hash
+pub async fn debug_fingerprint(
+    Json(request): Json<ApiRequest>,
+) -> Json<FingerprintResponse> {
+    Json(FingerprintResponse {
+        normalized: String::from_utf8_lossy(&request.normalized_bytes()).into_owned(),
+        hash: request.fingerprint(),
+    })
+}
+
+/// Handler for the tokenize endpoint.
+///
+/// Counts tokens with the same approximation dry runs and usage estimates
+/// use, so client-side budgets match the server's. DeepSeek and Gemini
+/// share the approximation. No provider is called.
+///
+/// # Arguments
+///
+/// * `request` - The text to tokenize and the provider and model to assume
+///
+/// # Returns
+///
+/// * `Json<TokenizeResponse>` - The token count, and token ranges if requested
+pub async fn tokenize(
+    Json(request): Json<TokenizeRequest>,
+) -> Json<TokenizeResponse> {
+    Json(TokenizeResponse {
+        provider: request.provider,
+        model: request
+            .model
+            .unwrap_or_else(|| request.provider.default_model().to_string()),
+        tokens: estimate_tokens(&request.text),
+        boundaries: request.boundaries.then(|| token_boundaries(&request.text)),
+    })
+}
+
+/// Stands in for the reasoning in prompts shown by the debug prompt endpoint.
+const DEBUG_REASONING_PLACEHOLDER: &str = "<DeepSeek reasoning>";
+
+/// Handler for the debug prompt endpoint.
+///
+/// Assembles the prompts a chat request would send to DeepSeek and to the
+/// answer provider, after the server's system prompt defaults, wrapping,
+/// and responder instructions, without calling either. The reasoning
+/// forwarded to the answer provider is shown as a placeholder. Prompts
+/// follow the default reason-then-answer pipeline order.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers, carrying the admin token
+/// * `request` - The chat request to assemble
+///
+/// # Returns
+///
+/// * `Result<Json<DebugPromptResponse>>` - The prompts of each upstream call
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - `ApiError::NotFound` - No admin token is configured
+/// - `ApiError::Unauthorized` - The admin token is missing or wrong
+/// - `ApiError::InvalidSystemPrompt` or `ApiError::BadRequest` - The request is invalid
+pub async fn debug_prompt(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<DebugPromptResponse>> {
+    let config = state.config();
+    check_admin_token(&config, &headers)?;
+
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+    request.validate_messages()?;
+    let request = with_server_defaults(request, &config);
+    let request = if request.bust_cache {
+        request.with_cache_nonce()
+    } else {
+        request
+    };
+
+    // Reasoner prompt, as chat builds it; DeepSeek gets text only
+    let answer_provider = config.pipeline.answer_provider;
+    let messages = request.get_messages_with_system();
+    let deepseek = UpstreamPrompt {
+        provider: "deepseek".to_string(),
+        model: deepseek_model(&request.deepseek_config).to_string(),
+        system: None,
+        messages: messages.iter().map(Message::to_text_only).collect(),
+    };
+
+    // Responder prompt, with the reasoning forwarded as configured
+    let answer = (!request.reasoning_only).then(|| {
+        let reasoning_mode = request
+            .reasoning_to_answer
+            .unwrap_or(config.pipeline.reasoning_to_answer);
+        let thinking = forwarded_reasoning(DEBUG_REASONING_PLACEHOLDER, reasoning_mode, &config)
+            .map(|(thinking, _)| thinking);
+        let (answer_messages, answer_system) = responder_inputs(
+            &request,
+            messages,
+            thinking.as_deref(),
+            config.pipeline.reasoning_injection,
+        );
+
+        // Both answer providers take the system prompt apart from the messages:
+        // Gemini joins the system messages, Anthropic uses the system prompt
+        let (system_messages, conversation): (Vec<Message>, Vec<Message>) = answer_messages
+            .into_iter()
+            .partition(|msg| matches!(msg.role, Role::System));
+        let system = match answer_provider {
+            AnswerProvider::Gemini => (!system_messages.is_empty()).then(|| {
+                system_messages
+                    .iter()
+                    .map(|msg| msg.content.text())
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            }),
+            AnswerProvider::Anthropic => answer_system,
+        };
+        UpstreamPrompt {
+            provider: answer_provider.name().to_ascii_lowercase(),
+            model: answer_model(answer_provider, request.answer_config(answer_provider)).to_string(),
+            system,
+            messages: conversation,
+        }
+    });
+
+    Ok(Json(DebugPromptResponse { deepseek, answer }))
+}
+
+/// Handler for the models endpoint.
+///
+/// Lists the models configured in the pricing configuration, the
+/// pipeline role each serves, and the defaults used when a request
+/// does not select a model. The default DeepSeek and Gemini models are
+/// listed at the base rates unless the pricing configuration lists them.
+/// Makes no upstream calls.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+///
+/// # Returns
+///
+/// * `Json<ModelsResponse>` - The configured models and defaults
+pub async fn list_models(
+    State(state): State<Arc<AppState>>,
+) -> Json<ModelsResponse> {
+    let config = state.config();
+    let pricing = &config.pricing;
+    let answer_model = |id: &str, provider: &str, model: &ModelPricing| ModelInfo {
+        id: id.to_string(),
+        provider: provider.to_string(),
+        role: ModelRole::Answer,
+        kind: None,
+        input_price: model.input_price,
+        output_price: model.output_price,
+        cache_read_price: model.cache_read_price,
+        cache_write_price: Some(model.cache_write_price),
+    };
+    let with_default = |ids: Vec<&String>, default: &str| {
+        let mut ids: Vec<String> = ids.into_iter().cloned().collect();
+        if !ids.iter().any(|id| id == default) {
+            ids.push(default.to_string());
+        }
+        ids.sort();
+        ids
+    };
+
+    let deepseek_ids = with_default(pricing.deepseek.models.keys().collect(), crate::clients::deepseek::DEFAULT_MODEL);
+    let gemini_ids = with_default(pricing.gemini.models.keys().collect(), crate::clients::gemini::DEFAULT_MODEL);
+    let mut models: Vec<ModelInfo> = deepseek_ids
+        .iter()
+        .map(|id| {
+            let (cache_hit, cache_miss, output) = pricing.deepseek.rates(id);
+            ModelInfo {
+                id: id.clone(),
+                provider: "deepseek".to_string(),
+                role: ModelRole::Reasoning,
+                kind: Some(pricing.deepseek.kind(id)),
+                input_price: cache_miss,
+                output_price: output,
+                cache_read_price: cache_hit,
+                cache_write_price: None,
+            }
+        })
+        .collect();
+    models.extend(gemini_ids.iter().map(|id| answer_model(id, "gemini", pricing.gemini.for_model(id))));
+    models.extend([
+        answer_model("claude_3_sonnet", "anthropic", &pricing.anthropic.claude_3_sonnet),
+        answer_model("claude_3_haiku", "anthropic", &pricing.anthropic.claude_3_haiku),
+        answer_model("claude_3_opus", "anthropic", &pricing.anthropic.claude_3_opus),
+    ]);
+
+    let answer_provider = config.pipeline.answer_provider;
+    Json(ModelsResponse {
+        models,
+        defaults: ModelDefaults {
+            reasoning_model: crate::clients::deepseek::DEFAULT_MODEL.to_string(),
+            answer_provider: answer_provider.name().to_ascii_lowercase(),
+            answer_model: answer_provider.default_model().to_string(),
+        },
+    })
+}
+
+/// Handler for non-streaming chat requests.
+///
+/// Processes the request through both AI models sequentially,
+/// combining their responses and tracking usage.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+/// * `tally` - Token counts of the HTTP request, for the abuse log
+///
+/// # Returns
+///
+/// * `Result<(HeaderMap, Json<ApiResponse>)>` - The combined API response and
+///   any extra response headers, or an error
+pub(crate) async fn chat(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+    tally: Option<Arc<RequestTally>>,
+) -> Result<(axum::http::HeaderMap, Json<ApiResponse>)> {
+    let config = state.config();
+    let started = Instant::now();
+
+    // Budget the upstream calls against the caller's deadline, if any.
+    // Recomputed here so time spent queued counts against it.
+    let deadlines = phase_deadlines(&headers, &config)?;
+
+    // Validate system prompt
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+
+    // Reject oversized conversations before any upstream call
+    request.validate_message_count(config.server.max_messages)?;
+    request.validate_message_chars(config.server.max_message_chars)?;
+
+    // Reject models the server doesn't allow clients to select
+    check_allowed_models(&request, &config)?;
+
+    // Validate conversation role ordering
+    request.validate_messages()?;
+
+    // Validate multimodal content
+    request.validate_content()?;
+
+    // Provider-specific parameters are forwarded as an object
+    request.validate_extra_params()?;
+
+    // Apply the server-side system prompt and safety defaults
+    let request = with_server_defaults(request, &config);
+
+    // Enforce per-model output token limits
+    let request = with_max_tokens_limits(request, &config)?;
+
+    // Bust provider prompt caches if requested
+    let request = if request.bust_cache {
+        request.with_cache_nonce()
+    } else {
+        request
+    };
+
+    // Extract API tokens
+    let answer_provider = config.pipeline.answer_provider;
+    let (deepseek_token, answer_token) = match config.pipeline.provider_mode {
+        // Mock providers need no tokens
+        ProviderMode::Mock => (String::new(), String::new()),
+        ProviderMode::Live => extract_api_tokens(&headers, answer_provider, !request.reasoning_only)?,
+    };
+
+    // Initialize clients
+    let deepseek_client = DeepSeekClient::with_http_client(deepseek_token, state.http.deepseek.clone())
+        .with_base_url(config.deepseek.base_url.as_deref())
+        .with_mock(MockProvider::for_config(&config));
+    let answer_client =
+        AnswerClient::new(answer_provider, answer_token, &config, state.http.answer(answer_provider));
+
+    // Get messages with system prompt
+    let messages = request.get_messages_with_system();
+
+    // Reversed pipeline: the responder drafts first and the reasoner critiques
+    if request.pipeline_order == PipelineOrder::AnswerThenReason {
+        if request.reasoning_only {
+            return Err(ApiError::BadRequest {
+                message: "reasoning_only cannot be combined with pipeline_order \"answer_then_reason\"".to_string(),
+            });
+        }
+        let (deepseek_response, critique, answer_response) = within_deadline(
+            deadlines.map(|d| d.answer),
+            "The reversed pipeline",
+            answer_then_reason(&deepseek_client, &answer_client, &request, messages),
+        )
+        .await?;
+        let response = build_chat_response(
+            &config,
+            &request,
+            ReasoningOutcome {
+                response: deepseek_response,
+                content: critique,
+                cached: false,
+                forwarded_tokens: None,
+            },
+            answer_response,
+            false,
+            StreamTiming::default(),
+        )?;
+        record_usage(&state, new_request_id(), &request, &config, &response.1.combined_usage, started, tally.as_deref());
+        return Ok(response);
+    }
+
+    let pipeline = &config.pipeline;
+
+    // Reuse an identical earlier reasoning result if one is cached
+    let cache_key = state
+        .reasoning_cache
+        .as_ref()
+        .map(|_| ReasoningCache::key(&messages, &request.deepseek_config));
+    let cached = state
+        .reasoning_cache
+        .as_ref()
+        .zip(cache_key.as_deref())
+        .and_then(|(cache, key)| cache.get(key));
+    let reasoning_cached = cached.is_some();
+
+    // Non-reasoning models have no reasoning_content, so their answer is the context
+    let deepseek_kind = config.pricing.deepseek.kind(deepseek_model(&request.deepseek_config));
+
+    // Call DeepSeek API, retrying if the reasoning is too short and retries are enabled
+    // Every attempt is billed, so usage is summed across retries
+    let mut attempts = 0;
+    let mut earlier_usage: Option<deepseek::Usage> = None;
+    let deepseek_started = Instant::now();
+    let (deepseek_response, reasoning_content) = match cached {
+        Some(cached) => {
+            tracing::debug!("Reusing cached DeepSeek reasoning");
+            (cached.response, cached.reasoning)
+        }
+        None => loop {
+            let model = deepseek_model(&request.deepseek_config);
+            let span = telemetry::upstream_span("deepseek", model);
+            let response = within_deadline(
+                deadlines.map(|d| d.reasoning),
+                "DeepSeek",
+                deepseek_client.chat(messages.clone(), &request.deepseek_config),
+            )
+            .instrument(span.clone())
+            .await?;
+            telemetry::record_usage(&span, &reasoning_usage(&response.usage, model, &config));
+            let usage = match earlier_usage.take() {
+                Some(earlier) => earlier + response.usage.clone(),
+                None => response.usage.clone(),
+            };
+
+            // Extract reasoning content, falling back to regular content unless reasoning is required
+            let reasoning = if deepseek_kind == DeepSeekModelKind::Chat {
+                response.choices.first().and_then(|c| c.message.content.clone())
+            } else {
+                extract_reasoning(&response, pipeline.require_reasoning)?
+            };
+
+            match pipeline.min_reasoning_chars {
+                Some(min) if reasoning.as_ref().map_or(0, |r| r.chars().count()) < min => {
+                    if pipeline.short_reasoning_action == ShortReasoningAction::Retry
+                        && attempts < pipeline.short_reasoning_retries
+                    {
+                        attempts += 1;
+                        tracing::warn!("DeepSeek reasoning shorter than {} characters, retrying ({}/{})", min, attempts, pipeline.short_reasoning_retries);
+                        earlier_usage = Some(usage);
+                        continue;
+                    }
+                    return Err(insufficient_reasoning_error(min));
+                }
+                _ => break (DeepSeekResponse { usage, ..response }, reasoning),
+            }
+        },
+    };
+
+    let deepseek_latency = (!reasoning_cached).then(|| deepseek_started.elapsed());
+
+    // Only successful results reach this point, so errors are never cached
+    if let (Some(cache), Some(key)) = (&state.reasoning_cache, cache_key) {
+        if !reasoning_cached {
+            cache.insert(key, CachedReasoning {
+                response: deepseek_response.clone(),
+                reasoning: reasoning_content.clone(),
+            });
+        }
+    }
+    
+    // Add as much of the reasoning as configured to messages for the answer model
+    let reasoning_mode = request
+        .reasoning_to_answer
+        .unwrap_or(pipeline.reasoning_to_answer);
+    let forwarded = reasoning_content
+        .as_deref()
+        .and_then(|reasoning| forwarded_reasoning(reasoning, reasoning_mode, &config));
+
+    // Skip the responder when the reasoning already states a final answer
+    let short_circuit_answer = reasoning_content
+        .as_deref()
+        .filter(|_| pipeline.short_circuit_if_reasoning_answers)
+        .and_then(extract_final_answer);
+    let responder_skipped = request.reasoning_only || short_circuit_answer.is_some();
+
+    // Call the answer provider, unless the reasoner already answered
+    let answer_started = Instant::now();
+    let answer_response = if request.reasoning_only {
+        AnswerResponse {
+            provider: answer_provider,
+            text: String::new(),
+            parts: Vec::new(),
+            alternatives: Vec::new(),
+            citations: Vec::new(),
+            finish_reason: deepseek_response
+                .choices
+                .first()
+                .and_then(|c| c.finish_reason.as_deref())
+                .map(FinishReason::from_deepseek),
+            usage: AnswerUsage::default(),
+            retries: 0,
+            raw: serde_json::Value::Null,
+        }
+    } else if let Some(text) = short_circuit_answer {
+        AnswerResponse {
+            provider: answer_provider,
+            text,
+            parts: Vec::new(),
+            alternatives: Vec::new(),
+            citations: Vec::new(),
+            finish_reason: Some(FinishReason::Stop),
+            usage: AnswerUsage::default(),
+            retries: 0,
+            raw: serde_json::Value::Null,
+        }
+    } else {
+        let (answer_messages, answer_system) = responder_inputs(
+            &request,
+            messages,
+            forwarded.as_ref().map(|(thinking, _)| thinking.as_str()),
+            pipeline.reasoning_injection,
+        );
+        let fallback_client = fallback_answer_client(&headers, &config, &state.http);
+        within_deadline(
+            deadlines.map(|d| d.answer),
+            answer_provider.name(),
+            answer_with_fallback(
+                &answer_client,
+                fallback_client.as_ref(),
+                answer_messages,
+                answer_system,
+                &request,
+                &config,
+            ),
+        )
+        .await?
+    };
+    
+    let answer_latency = (!responder_skipped).then(|| answer_started.elapsed());
+    let timing = if request.include_timing {
+        StreamTiming {
+            deepseek_latency_ms: deepseek_latency.map(|d| d.as_millis() as u64),
+            gemini_latency_ms: answer_latency.map(|d| d.as_millis() as u64),
+            ..Default::default()
+        }
+    } else {
+        StreamTiming::default()
+    };
+
+    // Nothing reached a responder that was skipped
+    let forwarded_reasoning_tokens = (!responder_skipped)
+        .then(|| forwarded.map_or(0, |(_, tokens)| tokens));
+
+    let response = build_chat_response(
+        &config,
+        &request,
+        ReasoningOutcome {
+            response: deepseek_response,
+            content: reasoning_content,
+            cached: reasoning_cached,
+            forwarded_tokens: forwarded_reasoning_tokens,
+        },
+        answer_response,
+        responder_skipped,
+        timing,
+    )?;
+    record_usage(&state, new_request_id(), &request, &config, &response.1.combined_usage, started, tally.as_deref());
+    Ok(response)
+}
+
+/// Calls the answer provider, repeating the call when it returns a blank
+/// answer.
+///
+/// Answers blocked for safety are not retried. Every attempt is billed,
+/// so the returned usage adds up the tokens of all of them.
+///
+/// # Arguments
+///
+/// * `answer_client` - Client for the answer provider
+/// * `messages` - Messages for the answer call
+/// * `system` - Optional system prompt, for providers that take it separately
+/// * `answer_config` - Configuration options for the request
+/// * `config` - Configuration containing the retry limit and pricing
+///
+/// # Returns
+///
+/// * `Result<AnswerResponse>` - The last answer, with the number of retries
+async fn answer_with_retries(
+    answer_client: &AnswerClient,
+    messages: Vec<Message>,
+    system: Option<String>,
+    answer_config: &ApiConfig,
+    config: &Config,
+) -> Result<AnswerResponse> {
+    let provider = answer_client.provider();
+    let max_retries = config.pipeline.retry_on_empty_answer;
+    let mut usage = AnswerUsage::default();
+    let mut retries = 0;
+    loop {
+        let span = telemetry::upstream_span(
+            &provider.name().to_ascii_lowercase(),
+            answer_model(provider, answer_config),
+        );
+        let response = answer_client
+            .chat(messages.clone(), system.clone(), answer_config)
+            .instrument(span.clone())
+            .await?;
+        telemetry::record_usage(&span, &answer_usage(provider, response.usage, answer_config, config));
+        usage.input_tokens = usage.input_tokens.saturating_add(response.usage.input_tokens);
+        usage.output_tokens = usage.output_tokens.saturating_add(response.usage.output_tokens);
+        usage.cached_input_tokens = usage.cached_input_tokens.saturating_add(response.usage.cached_input_tokens);
+        usage.cache_creation_input_tokens = usage
+            .cache_creation_input_tokens
+            .saturating_add(response.usage.cache_creation_input_tokens);
+
+        let blank = response.text.trim().is_empty() && response.finish_reason != Some(FinishReason::Safety);
+        if !blank || retries >= max_retries {
+            return Ok(AnswerResponse { usage, retries, ..response });
+        }
+        retries += 1;
+        tracing::warn!(
+            "{} returned an empty answer, retrying ({}/{})",
+            provider.name(),
+            retries,
+            max_retries
+        );
+    }
+}
+
+/// Creates a client for the configured fallback answer provider.
+///
+/// # Returns
+///
+/// * `Option<AnswerClient>` - The client, or None if no fallback is
+///   configured or the request doesn't carry the fallback's token
+fn fallback_answer_client(
+    headers: &axum::http::HeaderMap,
+    config: &Config,
+    http: &ProviderHttp,
+) -> Option<AnswerClient> {
+    let provider = config
+        .pipeline
+        .fallback_answer_provider
+        .filter(|&provider| provider != config.pipeline.answer_provider)?;
+    let token = match config.pipeline.provider_mode {
+        ProviderMode::Mock => String::new(),
+        ProviderMode::Live => headers.get(provider.token_header())?.to_str().ok()?.to_string(),
+    };
+    Some(AnswerClient::new(provider, token, config, http.answer(provider)))
+}
+
+/// Calls the answer provider, handing the call to the fallback provider
+/// if the primary one fails with a retryable error.
+///
+/// # Arguments
+///
+/// * `answer_client` - Client for the primary answer provider
+/// * `fallback_client` - Client for the fallback provider, if any
+/// * `messages` - Messages for the answer call
+/// * `system` - Optional system prompt, for providers that take it separately
+/// * `request` - The chat request, for each provider's request config
+/// * `config` - Configuration containing the retry limit and pricing
+///
+/// # Returns
+///
+/// * `Result<AnswerResponse>` - The answer of whichever provider served it
+async fn answer_with_fallback(
+    answer_client: &AnswerClient,
+    fallback_client: Option<&AnswerClient>,
+    messages: Vec<Message>,
+    system: Option<String>,
+    request: &ApiRequest,
+    config: &Config,
+) -> Result<AnswerResponse> {
+    let provider = answer_client.provider();
+    let error = match answer_with_retries(
+        answer_client,
+        messages.clone(),
+        system.clone(),
+        request.answer_config(provider),
+        config,
+    )
+    .await
+    {
+        Ok(response) => return Ok(response),
+        Err(e) => e,
+    };
+
+    let Some(fallback) = fallback_client.filter(|_| error.is_retryable()) else {
+        return Err(error);
+    };
+    let fallback_provider = fallback.provider();
+    tracing::warn!(
+        "{} failed, falling back to {}: {}",
+        provider.name(),
+        fallback_provider.name(),
+        error
+    );
+    answer_with_retries(fallback, messages, system, request.answer_config(fallback_provider), config).await
+}
+
+/// Result of the reasoning phase of a non-streaming request.
+struct ReasoningOutcome {
+    response: DeepSeekResponse,
+    /// Reasoning extracted from the response
+    content: Option<String>,
+    /// Whether the reasoning was served from the cache
+    cached: bool,
+    /// Estimated reasoning tokens sent to the responder
+    forwarded_tokens: Option<u32>,
+}
+
+/// Assembles the non-streaming response from the reasoning and answer results.
+///
+/// Computes usage costs, validates JSON mode output, cuts content at the
+/// request's `max_response_chars`, and builds the content blocks,
+/// assistant turn, and optional verbose upstream bodies.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `request` - The original chat request
+/// * `reasoning` - The DeepSeek response and the reasoning taken from it
+/// * `answer_response` - The answer provider's response
+/// * `responder_skipped` - Whether the answer came from the reasoner alone
+/// * `timing` - Upstream latencies to report, when `include_timing` was requested
+///
+/// # Returns
+///
+/// * `Result<(HeaderMap, Json<ApiResponse>)>` - The combined API response and
+///   any extra response headers, or an error
+///
+/// # Errors
+///
+/// Returns `ApiError::UpstreamFormat` if JSON mode output does not parse
+fn build_chat_response(
+    config: &Config,
+    request: &ApiRequest,
+    reasoning: ReasoningOutcome,
+    answer_response: AnswerResponse,
+    responder_skipped: bool,
+    timing: StreamTiming,
+) -> Result<(axum::http::HeaderMap, Json<ApiResponse>)> {
+    let ReasoningOutcome {
+        response: deepseek_response,
+        content: reasoning_content,
+        cached: reasoning_cached,
+        forwarded_tokens: forwarded_reasoning_tokens,
+    } = reasoning;
+    // The serving provider differs from the configured one after a fallback
+    let answer_provider = answer_response.provider;
+    let answer_config = request.answer_config(answer_provider);
+
+    // Cut the returned reasoning at the client's size cap
+    let limits = request.max_response_chars;
+    let mut reasoning_budget = CharBudget::new(limits.reasoning);
+    let reasoning_content = reasoning_content.map(|reasoning| reasoning_budget.take(&reasoning).0.to_string());
+
+    // Wrap in the configured markers; skipped entirely when there is no
+    // reasoning or reasoning is hidden
+    let thinking_content = reasoning_content
+        .as_ref()
+        .zip(config.pipeline.thinking_render_mode.markers())
+        .map(|(reasoning, (open, close))| format!("{}{}{}", open, reasoning, close));
+
+    // Store response metadata
+    let deepseek_status: u16 = 200;
+    let deepseek_headers = HashMap::new(); // Headers not available when using high-level chat method
+    let answer_status: u16 = 200;
+    let answer_headers = HashMap::new(); // Headers not available when using high-level chat method
+
+    // Calculate usage costs
+    let mut reasoning = reasoning_usage(
+        &deepseek_response.usage,
+        deepseek_model(&request.deepseek_config),
+        config,
+    );
+    if reasoning_cached {
+        // No DeepSeek call was made, so nothing was spent on reasoning
+        reasoning.cached = true;
+        reasoning.cost = 0.0;
+        reasoning.cost_breakdown = Some(DeepSeekCost::default());
+    }
+    let mut answer_phase = answer_usage(answer_provider, answer_response.usage, answer_config, config);
+    answer_phase.forwarded_reasoning_tokens = forwarded_reasoning_tokens;
+    answer_phase.retries = answer_response.retries;
+    let combined_usage = CombinedUsage {
+        reasoning,
+        answer: answer_phase,
+        cost_format: config.cost_format.clone(),
+        timing,
+    };
+
+    // In JSON mode the answer must parse on its own, so reasoning is kept out
+    // of content. Without an answer there is nothing to parse.
+    let json_mode = answer_config.json_mode() && !request.reasoning_only;
+    let prepare_answer = |text: &str| -> Result<String> {
+        if !json_mode {
+            return Ok(text.to_string());
+        }
+        let text = if answer_config.strip_json_fences() {
+            strip_json_fences(text)
+        } else {
+            text
+        };
+        validate_json_content(text)?;
+        Ok(text.to_string())
+    };
+    let answer = prepare_answer(&answer_response.text)?;
+    let alternatives = answer_response
+        .alternatives
+        .iter()
+        .map(|text| prepare_answer(text))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Each candidate's answer is cut at the client's size cap on its own
+    let truncate_answer = |text: String| -> (String, bool) {
+        let (kept, cut) = CharBudget::new(limits.answer).take(&text);
+        (kept.to_string(), cut)
+    };
+    let (answer, answer_truncated) = truncate_answer(answer);
+    let mut truncated = reasoning_budget.truncated || answer_truncated;
+    let alternatives: Vec<String> = alternatives
+        .into_iter()
+        .map(|text| {
+            let (text, cut) = truncate_answer(text);
+            truncated |= cut;
+            text
+        })
+        .collect();
+
+    let (answer_word_count, warnings) = check_answer_length(&answer, request.target_answer_words);
+
+    // Build the assistant turn clients can append to their history
+    let assistant_message = Message {
+        role: Role::Assistant,
+        content: match &thinking_content {
+            Some(thinking) if request.history_includes_thinking && !json_mode => {
+                format!("{}\n\n{}", thinking, answer)
+            }
+            _ => answer.clone(),
+        }
+        .into(),
+    };
+
+    // Hash the reasoning exactly as it is returned: the thinking block,
+    // or the separate reasoning field in JSON mode
+    let mut response_headers = axum::http::HeaderMap::new();
+    if config.server.reasoning_hash_header {
+        let returned_reasoning = if json_mode {
+            reasoning_content.as_ref()
+        } else {
+            thinking_content.as_ref()
+        };
+        if let Some(reasoning) = returned_reasoning {
+            let hash = format!("{:x}", Sha256::digest(reasoning.as_bytes()));
+            if let Ok(value) = axum::http::HeaderValue::from_str(&hash) {
+                response_headers.insert(REASONING_HASH_HEADER, value);
+            }
+        }
+    }
+
+    // Combine thinking content with the answer, thinking block first
+    let thinking_block = thinking_content
+        .filter(|_| !json_mode)
+        .map(ContentBlock::text);
+    let content_for = |answer: String| -> Vec<ContentBlock> {
+        thinking_block
+            .iter()
+            .cloned()
+            .chain((!request.reasoning_only).then(|| ContentBlock::text(answer)))
+            .collect()
+    };
+    // Outside JSON mode, each part the provider returned gets its own block,
+    // unless the answer was cut short
+    let content = if !json_mode && !answer_truncated && answer_response.parts.len() > 1 {
+        thinking_block
+            .iter()
+            .cloned()
+            .chain(answer_response.parts.iter().cloned().map(ContentBlock::text))
+            .collect()
+    } else {
+        content_for(answer)
+    };
+
+    // Every candidate shares the reasoning; the first is also `content`
+    let candidates = if alternatives.is_empty() {
+        Vec::new()
+    } else {
+        std::iter::once(content.clone())
+            .chain(alternatives.into_iter().map(content_for))
+            .collect()
+    };
+
+    // Build response with captured headers
+    let response = ApiResponse {
+        created: Utc::now(),
+        content,
+        candidates,
+        citations: answer_response.citations,
+        reasoning: reasoning_content.filter(|_| json_mode),
+        finish_reason: answer_response.finish_reason,
+        assistant_message,
+        answer_word_count,
+        warnings,
+        responder_skipped,
+        answer_provider: (!responder_skipped).then_some(answer_provider),
+        truncated,
+        deepseek_response: request.verbose.then(|| ExternalApiResponse {
+            status: deepseek_status,
+            headers: deepseek_headers,
+            body: serde_json::to_value(&deepseek_response).unwrap_or_default(),
+        }),
+        gemini_response: (request.verbose && answer_provider == AnswerProvider::Gemini).then(|| ExternalApiResponse {
+            status: answer_status,
+            headers: answer_headers.clone(),
+            body: answer_response.raw.clone(),
+        }),
+        anthropic_response: (request.verbose && answer_provider == AnswerProvider::Anthropic).then(|| ExternalApiResponse {
+            status: answer_status,
+            headers: answer_headers,
+            body: answer_response.raw.clone(),
+        }),
+        combined_usage,
+    };
+
+    Ok((response_headers, Json(response)))
+}
+
+/// New reasoning characters between pipelined drafts, unless configured.
+const DEFAULT_PIPELINED_DRAFT_INTERVAL_CHARS: usize = 2000;
+
+/// Instruction for a pipelined draft, whose reasoning is still being written.
+const PARTIAL_REASONING_INSTRUCTION: &str =
+    "The reasoning above is unfinished. Draft the best answer you can from it so far.";
+
+/// How long a draft still running when reasoning ends is awaited before it
+/// is abandoned and charged at an estimate.
+const PIPELINED_DRAFT_WAIT: Duration = Duration::from_secs(5);
+
+/// Completed pipelined drafts, and the usage billed for them.
+#[derive(Debug, Default)]
+struct PipelinedDrafts {
+    /// Drafts that returned an answer.
+    completed: u32,
+    /// Usage of every draft, including estimates for abandoned ones.
+    usage: AnswerUsage,
+    /// Text of the latest non-blank draft.
+    latest: Option<String>,
+}
+
+impl PipelinedDrafts {
+    /// Records the result of a finished draft. Drafts are billed even though
+    /// only the latest is used; a failed one is logged and skipped.
+    fn record(&mut self, result: Result<AnswerResponse>) {
+        match result {
+            Ok(response) => {
+                self.usage = self.usage + response.usage;
+                self.completed += 1;
+                if !response.text.trim().is_empty() {
+                    self.latest = Some(response.text);
+                }
+            }
+            Err(e) => tracing::warn!("Pipelined draft failed: {}", e),
+        }
+    }
+}
+
+/// Builds the instruction handing the latest pipelined draft to the final answer call.
+fn pipelined_draft_instruction(draft: &str) -> String {
+    format!(
+        "A draft answer was written from partial reasoning:\n\n{}\n\nUse the complete reasoning to correct and finish it, and reply with the final answer only.",
+        draft
+    )
+}
+
+/// Handler for streaming chat requests.
+///
+/// Processes the request through both AI models sequentially,
+/// streaming their responses as Server-Sent Events.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+/// * `permit` - Concurrency slot held until the stream finishes
+/// * `tally` - Token counts of the HTTP request, for the abuse log
+///
+/// # Returns
+///
+/// * `Result<SseResponse>` - A stream of Server-Sent Events or an error
+pub(crate) async fn chat_stream(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+    permit: RequestPermit,
+    tally: Option<Arc<RequestTally>>,
+) -> Result<SseResponse> {
+    let config = state.config();
+
+    // Validate system prompt
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+
+    // Reject oversized conversations before any upstream call
+    request.validate_message_count(config.server.max_messages)?;
+    request.validate_message_chars(config.server.max_message_chars)?;
+
+    // Reject models the server doesn't allow clients to select
+    check_allowed_models(&request, &config)?;
+
+    // Validate conversation role ordering
+    request.validate_messages()?;
+
+    // Validate multimodal content
+    request.validate_content()?;
+
+    // Provider-specific parameters are forwarded as an object
+    request.validate_extra_params()?;
+
+    // Options such as the reversed pipeline or extra candidates need the complete answer
+    request.validate_streamable()?;
+
+    // Apply the server-side system prompt and safety defaults
+    let request = with_server_defaults(request, &config);
+
+    // Enforce per-model output token limits
+    let request = with_max_tokens_limits(request, &config)?;
+
+    // Bust provider prompt caches if requested
+    let request = if request.bust_cache {
+        request.with_cache_nonce()
+    } else {
+        request
+    };
+
+    // Extract API tokens
+    let answer_provider = config.pipeline.answer_provider;
+    let (deepseek_token, answer_token) = match config.pipeline.provider_mode {
+        // Mock providers need no tokens
+        ProviderMode::Mock => (String::new(), String::new()),
+        ProviderMode::Live => extract_api_tokens(&headers, answer_provider, !request.reasoning_only)?,
+    };
+
+    // Budget the upstream calls against the caller's deadline, if any
+    let deadlines = phase_deadlines(&headers, &config)?;
+
+    // Initialize clients
+    let deepseek_client = DeepSeekClient::with_http_client(deepseek_token, state.http.deepseek.clone())
+        .with_base_url(config.deepseek.base_url.as_deref())
+        .with_mock(MockProvider::for_config(&config));
+    let answer_client =
+        AnswerClient::new(answer_provider, answer_token, &config, state.http.answer(answer_provider));
+    let mut fallback_client = fallback_answer_client(&headers, &config, &state.http);
+    let answer_config = request.answer_config(answer_provider);
+
+    // Get messages with system prompt
+    let messages = request.get_messages_with_system();
+
+    // Resolve the DeepSeek model, which decides how its output is read
+    let deepseek_model = deepseek_model(&request.deepseek_config).to_string();
+    let deepseek_kind = config.pricing.deepseek.kind(&deepseek_model);
+
+    // Create channel for stream events. Sends await when the buffer is full,
+    // applying backpressure to upstream polling instead of dropping events.
+    let (tx, rx) = tokio::sync::mpsc::channel(config.server.stream_channel_buffer.max(1));
+
+    // Spawn task to handle streaming
+    let request_clone = request.clone();
+    // Without an answer there is nothing for JSON mode to parse
+    let json_mode = answer_config.json_mode() && !request.reasoning_only;
+    let strip_fences = answer_config.strip_json_fences();
+    let thinking_markers = config.pipeline.thinking_render_mode.markers();
+    // Interleaved streams tag each frame with its phase instead of wrapping
+    // the reasoning in thinking markers
+    let interleaved = request.interleave_policy == InterleavePolicy::Interleaved;
+    let reasoning_kind = interleaved.then_some(ContentKind::Reasoning);
+    let answer_kind = interleaved.then_some(ContentKind::Answer);
+    let shutdown = state.shutdown.clone();
+    // Only the credential that started the stream may cancel or resume it
+    let owner = abuse::credential_hash(&headers);
+    let (registration, cancel) = StreamRegistration::new(state.clone(), owner.clone());
+    let span = tracing::info_span!(
+        "chat_stream",
+        request_id = %registration.request_id,
+        status = tracing::field::Empty
+    );
+
+    // Record events so a disconnected client can resume, if enabled
+    let replay = state
+        .replay
+        .as_ref()
+        .and_then(|replay| replay.register(&registration.request_id, owner));
+    let tx = Arc::new(StreamSink {
+        tx,
+        replay,
+        minimal: request.minimal_events.unwrap_or(config.server.minimal_events),
+        event_names: config.sse_event_names.clone(),
+    });
+    let started = Instant::now();
+    tokio::spawn(async move {
+        let tx = tx.clone();
+        let _permit = permit;
+
+        // Start event
+        let start = StreamEvent::Start {
+            created: Utc::now(),
+            request_id: registration.request_id.clone(),
+        };
+        if !send_event(&tx, "start", &start).await {
+            return;
+        }
+
+        // Stream from DeepSeek
+        let mut deepseek_usage = None;
+        let mut complete_reasoning = String::new();
+        let mut thinking_open = false;
+        let mut first_reasoning_at = None;
+        // Set when a reasoner streams plain content instead of reasoning
+        let mut content_as_reasoning = false;
+        let mut coalescer = DeltaCoalescer::new(
+            config.server.stream_flush_interval_ms,
+            config.server.stream_flush_max_chars,
+        );
+        let limits = request_clone.max_response_chars;
+        let mut reasoning_budget = CharBudget::new(limits.reasoning);
+        let mut answer_budget = CharBudget::new(limits.answer);
+        let deepseek_span = telemetry::upstream_span("deepseek", &deepseek_model);
+        let mut deepseek_stream = deepseek_client.chat_stream(messages.clone(), &request_clone.deepseek_config);
+
+        // Periodic running estimates while reasoning, if configured
+        let usage_period = config
+            .pipeline
+            .usage_update_interval_ms
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+        let mut usage_ticker = usage_period.map(|period| {
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            ticker
+        });
+        let estimated_input_tokens: u32 = messages
+            .iter()
+            .map(|msg| estimate_tokens(&msg.content.text_with_placeholders()))
+            .sum();
+
+        // Experimental pipelined drafts from the partial reasoning, one in flight at a time
+        let reasoning_mode = request_clone
+            .reasoning_to_answer
+            .unwrap_or(config.pipeline.reasoning_to_answer);
+        // Drafts would go unused if the responder could be skipped, so they
+        // are only made when it always runs
+        let pipelined = request_clone.pipelined_answer
+            && !request_clone.reasoning_only
+            && reasoning_mode != ReasoningToAnswer::None
+            && !config.pipeline.short_circuit_if_reasoning_answers;
+        let draft_interval = config
+            .pipeline
+            .pipelined_draft_interval_chars
+            .unwrap_or(DEFAULT_PIPELINED_DRAFT_INTERVAL_CHARS);
+        let mut reasoning_chars = 0;
+        let mut next_draft_at = draft_interval;
+        let mut draft: Option<futures::future::BoxFuture<'_, Result<AnswerResponse>>> = None;
+        let mut draft_input_tokens = 0;
+        let mut drafts = PipelinedDrafts::default();
+
+        loop {
+            let chunk = tokio::select! {
+                chunk = deepseek_stream.next() => chunk,
+                result = async { draft.as_mut().unwrap().await }, if draft.is_some() => {
+                    draft = None;
+                    // Interleaved streams get each draft while reasoning continues
+                    if let Ok(response) = result.as_ref() {
+                        if interleaved && !response.text.trim().is_empty() {
+                            let mut ready = coalescer.flush();
+                            ready.push(ContentBlock::delta(ContentType::Draft, response.text.clone()).with_kind(answer_kind));
+                            if !send_content(&tx, ready).await {
+                                return;
+                            }
+                        }
+                    }
+                    drafts.record(result);
+                    continue;
+                }
+                _ = async { usage_ticker.as_mut().unwrap().tick().await }, if usage_ticker.is_some() => {
+                    let usage = CombinedUsage {
+                        reasoning: estimated_reasoning_usage(
+                            estimated_input_tokens,
+                            estimate_tokens(&complete_reasoning),
+                            &deepseek_model,
+                            &config,
+                        ),
+                        answer: answer_usage(
+                            answer_provider,
+                            drafts.usage,
+                            request_clone.answer_config(answer_provider),
+                            &config,
+                        ),
+                        cost_format: config.cost_format.clone(),
+                        timing: StreamTiming::default(),
+                    };
+                    if !send_event(&tx, "usage", &StreamEvent::Usage { usage, estimated: true }).await {
+                        return;
+                    }
+                    continue;
+                }
+                _ = tokio::time::sleep_until(coalescer.flush_at().unwrap_or_else(tokio::time::Instant::now)),
+                    if coalescer.flush_at().is_some() => {
+                    if !send_content(&tx, coalescer.flush()).await {
+                        return;
+                    }
+                    continue;
+                }
+                _ = shutdown.cancelled() => {
+                    send_error_event(&tx, "shutting_down", "Server is shutting down", 503).await;
+                    return;
+                }
+                _ = cancel.cancelled() => {
+                    send_cancelled_event(&tx).await;
+                    return;
+                }
+                _ = tokio::time::sleep_until(deadlines.map_or_else(tokio::time::Instant::now, |d| d.reasoning)),
+                    if deadlines.is_some() => {
+                    send_error_event(&tx, "deadline_exceeded", "DeepSeek did not finish in time", 504).await;
+                    return;
+                }
+            };
+            let Some(chunk) = chunk else { break };
+
+            match chunk {
+                Ok(response) => {
+                    let mut reasoning_done = false;
+                    if let Some(choice) = response.choices.first() {
+                        // Non-reasoning models stream their whole answer as the reasoning
+                        let reasoning_delta = if deepseek_kind == DeepSeekModelKind::Chat {
+                            choice.delta.content.as_ref()
+                        } else {
+                            reasoning_done = choice.finish_reason.is_some();
+                            let reasoning = choice.delta.reasoning_content.as_ref().filter(|r| !r.is_empty());
+                            let content = choice.delta.content.as_ref().filter(|c| !c.is_empty());
+                            if content_as_reasoning {
+                                content
+                            } else if reasoning.is_some() {
+                                // Content alongside the last reasoning starts the answer
+                                reasoning_done |= content.is_some();
+                                reasoning
+                            } else if content.is_some()
+                                && complete_reasoning.is_empty()
+                                && !config.pipeline.require_reasoning
+                            {
+                                tracing::warn!("DeepSeek streamed no reasoning content, falling back to message content");
+                                content_as_reasoning = true;
+                                content
+                            } else {
+                                // Regular content after the reasoning starts the answer;
+                                // keepalive chunks carry neither field and are skipped
+                                reasoning_done |= content.is_some();
+                                None
+                            }
+                        };
+
+                        // Handle delta reasoning_content for streaming
+                        if let Some(reasoning) = reasoning_delta {
+                            if !reasoning.is_empty() {
+                                first_reasoning_at.get_or_insert_with(Instant::now);
+
+                                // Send the opening marker lazily so it is skipped when there is
+                                // no reasoning (and omitted in JSON mode so the answer stays parseable)
+                                if let Some((open, _)) = thinking_markers {
+                                    if !thinking_open && !json_mode && !interleaved {
+                                        let event = StreamEvent::Content {
+                                            content: vec![ContentBlock::text(open)],
+                                        };
+                                        if !send_event(&tx, "content", &event).await {
+                                            return;
+                                        }
+                                        thinking_open = true;
+                                    }
+                                }
+
+                                // Stream the reasoning content as a delta, unless it is hidden,
+                                // up to the client's size cap
+                                if thinking_markers.is_some() {
+                                    let (kept, cut) = reasoning_budget.take(reasoning);
+                                    if !kept.is_empty() {
+                                        let ready = coalescer.push(ContentBlock::delta(ContentType::ThinkingDelta, kept).with_kind(reasoning_kind));
+                                        if !send_content(&tx, ready).await {
+                                            return;
+                                        }
+                                    }
+                                    if cut
+                                        && !send_truncated_event(&tx, &mut coalescer, ContentKind::Reasoning, limits.reasoning).await
+                                    {
+                                        return;
+                                    }
+                                }
+
+                                // Accumulate complete reasoning for later use
+                                complete_reasoning.push_str(reasoning);
+                                reasoning_chars += reasoning.chars().count();
+
+                                // Draft an answer from the reasoning so far, unless a draft is running
+                                if pipelined && draft.is_none() && reasoning_chars >= next_draft_at {
+                                    next_draft_at = reasoning_chars + draft_interval;
+                                    let thinking = forwarded_reasoning(&complete_reasoning, reasoning_mode, &config)
+                                        .map(|(thinking, _)| thinking);
+                                    let (draft_messages, draft_system) = with_responder_instruction(
+                                        responder_inputs(
+                                            &request_clone,
+                                            messages.clone(),
+                                            thinking.as_deref(),
+                                            config.pipeline.reasoning_injection,
+                                        ),
+                                        PARTIAL_REASONING_INSTRUCTION,
+                                    );
+                                    draft_input_tokens = draft_messages
+                                        .iter()
+                                        .map(|msg| estimate_tokens(&msg.content.text_with_placeholders()))
+                                        .chain(draft_system.as_deref().map(estimate_tokens))
+                                        .sum();
+                                    draft = Some(Box::pin(answer_client.chat(
+                                        draft_messages,
+                                        draft_system,
+                                        request_clone.answer_config(answer_provider),
+                                    )));
+                                }
+                            }
+                        }
+                    }
+                    
+                    // Store usage information if present
+                    if let Some(usage) = response.usage {
+                        deepseek_usage = Some(usage);
+                    }
+
+                    if reasoning_done {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    send_upstream_error_event(&tx, &e, 500, None, &config).await;
+                    return;
+                }
+            }
+        }
+
+        // A draft still running is billed whether or not it is used, so give
+        // it a moment to finish and charge its estimated input if it doesn't
+        if let Some(pending) = draft.take() {
+            match tokio::time::timeout(PIPELINED_DRAFT_WAIT, pending).await {
+                Ok(result) => drafts.record(result),
+                Err(_) => {
+                    tracing::warn!("Abandoning a pipelined draft still running after the reasoning ended");
+                    drafts.usage.input_tokens += draft_input_tokens;
+                }
+            }
+        }
+
+        // Batched reasoning goes out before anything that follows it
+        if !send_content(&tx, coalescer.flush()).await {
+            return;
+        }
+
+        // Report the reasoning cost before the answer phase can fail
+        let (reasoning, estimated) = match &deepseek_usage {
+            Some(usage) => (reasoning_usage(usage, &deepseek_model, &config), false),
+            None => (
+                estimated_reasoning_usage(
+                    estimated_input_tokens,
+                    estimate_tokens(&complete_reasoning),
+                    &deepseek_model,
+                    &config,
+                ),
+                true,
+            ),
+        };
+        telemetry::record_usage(&deepseek_span, &reasoning);
+        drop(deepseek_span);
+        let event = StreamEvent::ReasoningUsage {
+            deepseek_usage: DeepSeekUsage::from_provider(&reasoning, &config.cost_format),
+            estimated,
+        };
+        if !send_event(&tx, "reasoning_usage", &event).await {
+            return;
+        }
+
+        // Fail if reasoning is required but DeepSeek produced none
+        if complete_reasoning.is_empty() && config.pipeline.require_reasoning {
+            send_error_event(&tx, "missing_content", "No reasoning content in response", 502).await;
+            return;
+        }
+
+        // Reasoning has already been streamed, so a short reasoning can't be retried here
+        if let Some(min) = config.pipeline.min_reasoning_chars {
+            if complete_reasoning.chars().count() < min {
+                send_upstream_error_event(&tx, &insufficient_reasoning_error(min), 502, None, &config).await;
+                return;
+            }
+        }
+
+        // Send the closing marker
+        if let Some((_, close)) = thinking_markers.filter(|_| thinking_open) {
+            let event = StreamEvent::Content {
+                content: vec![ContentBlock::text(close)],
+            };
+            if !send_event(&tx, "content", &event).await {
+                return;
+            }
+        }
+
+        // Add as much of the reasoning as configured to messages for the answer model
+        let forwarded = Some(complete_reasoning.as_str())
+            .filter(|reasoning| !reasoning.is_empty())
+            .and_then(|reasoning| forwarded_reasoning(reasoning, reasoning_mode, &config));
+        let forwarded_reasoning_tokens = forwarded.as_ref().map_or(0, |(_, tokens)| *tokens);
+
+        // Stream from the answer provider
+        let mut answer_provider = answer_provider;
+        let mut answer_config = request_clone.answer_config(answer_provider);
+        let answer_inputs = responder_inputs(
+            &request_clone,
+            messages,
+            forwarded.as_ref().map(|(thinking, _)| thinking.as_str()),
+            config.pipeline.reasoning_injection,
+        );
+        // The final call revises the latest pipelined draft rather than starting over
+        let (answer_messages, answer_system) = match &drafts.latest {
+            Some(draft) => with_responder_instruction(answer_inputs, &pipelined_draft_instruction(draft)),
+            None => answer_inputs,
+        };
+
+        // Skip the responder when the reasoning already states a final answer
+        let short_circuit_answer = Some(complete_reasoning.as_str())
+            .filter(|_| config.pipeline.short_circuit_if_reasoning_answers && !request_clone.reasoning_only)
+            .and_then(extract_final_answer);
+        let responder_skipped = request_clone.reasoning_only || short_circuit_answer.is_some();
+        let mut answer_span = (!responder_skipped).then(|| {
+            telemetry::upstream_span(
+                &answer_provider.name().to_ascii_lowercase(),
+                answer_model(answer_provider, answer_config),
+            )
+        });
+        let mut answer_stream = match short_circuit_answer {
+            // Reasoning-only requests go straight to usage and done
+            None if request_clone.reasoning_only => futures::stream::empty::<Result<AnswerChunk>>().boxed(),
+            Some(answer) => futures::stream::once(async move {
+                Result::Ok(AnswerChunk {
+                    text: Some(answer),
+                    finish_reason: Some(FinishReason::Stop),
+                    ..Default::default()
+                })
+            })
+            .boxed(),
+            None => answer_client.chat_stream(answer_messages.clone(), answer_system.clone(), answer_config),
+        };
+
+        let mut complete_answer = String::new();
+        let mut first_answer_at = None;
+        let mut answer_usage_total = None;
+        let mut finish_reason = None;
+        let first_token_timeout = config.pipeline.gemini_first_token_timeout_secs;
+        let first_token_deadline = first_token_timeout
+            .map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs));
+        // The system prompt is among the messages as well, so count it once
+        let estimated_answer_input_tokens: u32 = answer_messages
+            .iter()
+            .map(|msg| estimate_tokens(&msg.content.text_with_placeholders()))
+            .sum();
+        loop {
+            let chunk = tokio::select! {
+                chunk = answer_stream.next() => chunk,
+                // Running estimates continue through the answer, on top of the reasoning usage
+                _ = async { usage_ticker.as_mut().unwrap().tick().await },
+                    if usage_ticker.is_some() && !responder_skipped => {
+                    let mut answer_phase = answer_usage(
+                        answer_provider,
+                        AnswerUsage {
+                            input_tokens: drafts.usage.input_tokens + estimated_answer_input_tokens,
+                            output_tokens: drafts.usage.output_tokens + estimate_tokens(&complete_answer),
+                            cached_input_tokens: drafts.usage.cached_input_tokens,
+                            cache_creation_input_tokens: drafts.usage.cache_creation_input_tokens,
+                        },
+                        answer_config,
+                        &config,
+                    );
+                    answer_phase.forwarded_reasoning_tokens = Some(forwarded_reasoning_tokens);
+                    let usage = CombinedUsage {
+                        reasoning: reasoning.clone(),
+                        answer: answer_phase,
+                        cost_format: config.cost_format.clone(),
+                        timing: StreamTiming::default(),
+                    };
+                    if !send_event(&tx, "usage", &StreamEvent::Usage { usage, estimated: true }).await {
+                        return;
+                    }
+                    continue;
+                }
+                _ = tokio::time::sleep_until(coalescer.flush_at().unwrap_or_else(tokio::time::Instant::now)),
+                    if coalescer.flush_at().is_some() => {
+                    if !send_content(&tx, coalescer.flush()).await {
+                        return;
+                    }
+                    continue;
+                }
+                _ = shutdown.cancelled() => {
+                    send_error_event(&tx, "shutting_down", "Server is shutting down", 503).await;
+                    return;
+                }
+                _ = cancel.cancelled() => {
+                    send_cancelled_event(&tx).await;
+                    return;
+                }
+                _ = tokio::time::sleep_until(first_token_deadline.unwrap_or_else(tokio::time::Instant::now)),
+                    if first_token_deadline.is_some() && complete_answer.is_empty() => {
+                    send_error_event(
+                        &tx,
+                        "timeout",
+                        format!(
+                            "{} first token timeout: no content within {} seconds of reasoning completing",
+                            answer_provider.name(),
+                            first_token_timeout.unwrap_or_default()
+                        ),
+                        504,
+                    ).await;
+                    return;
+                }
+                _ = tokio::time::sleep_until(deadlines.map_or_else(tokio::time::Instant::now, |d| d.answer)),
+                    if deadlines.is_some() => {
+                    send_error_event(
+                        &tx,
+                        "deadline_exceeded",
+                        format!("{} did not finish in time", answer_provider.name()),
+                        504,
+                    ).await;
+                    return;
+                }
+            };
+            let Some(chunk) = chunk else { break };
+
+            match chunk {
+                Ok(chunk) => {
+                    if let Some(text) = chunk.text.filter(|t| !t.is_empty()) {
+                        first_answer_at.get_or_insert_with(Instant::now);
+                        complete_answer.push_str(&text);
+
+                        // Send content update, up to the client's size cap
+                        let (kept, cut) = answer_budget.take(&text);
+                        if !kept.is_empty() {
+                            let ready = coalescer.push(ContentBlock::delta(ContentType::TextDelta, kept).with_kind(answer_kind));
+                            if !send_content(&tx, ready).await {
+                                return;
+                            }
+                        }
+                        if cut && !send_truncated_event(&tx, &mut coalescer, ContentKind::Answer, limits.answer).await {
+                            return;
+                        }
+                    }
+
+                    if !chunk.citations.is_empty() {
+                        // Keep citations after the text they refer to
+                        if !send_content(&tx, coalescer.flush()).await {
+                            return;
+                        }
+                        let event = StreamEvent::Citations {
+                            citations: chunk.citations,
+                        };
+                        if !send_event(&tx, "citations", &event).await {
+                            return;
+                        }
+                    }
+
+                    if chunk.finish_reason.is_some() {
+                        finish_reason = chunk.finish_reason;
+                    }
+
+                    // Store usage information if present
+                    if let Some(usage) = chunk.usage {
+                        answer_usage_total = Some(usage);
+                    }
+                }
+                Err(e) => {
+                    // Hand the answer to the fallback provider if none of it was sent yet
+                    if complete_answer.is_empty() && !responder_skipped && e.is_retryable() {
+                        if let Some(fallback) = fallback_client.take() {
+                            tracing::warn!(
+                                "{} failed, falling back to {}: {}",
+                                answer_provider.name(),
+                                fallback.provider().name(),
+                                e
+                            );
+                            answer_provider = fallback.provider();
+                            answer_config = request_clone.answer_config(answer_provider);
+                            answer_span = Some(telemetry::upstream_span(
+                                &answer_provider.name().to_ascii_lowercase(),
+                                answer_model(answer_provider, answer_config),
+                            ));
+                            answer_stream =
+                                fallback.chat_stream(answer_messages.clone(), answer_system.clone(), answer_config);
+                            answer_usage_total = None;
+                            finish_reason = None;
+                            continue;
+                        }
+                    }
+
+                    // Tell the client how much of the answer it already has
+                    if !send_content(&tx, coalescer.flush()).await {
+                        return;
+                    }
+                    let delivered_chars = (!complete_answer.is_empty())
+                        .then(|| complete_answer.chars().count().min(limits.answer.unwrap_or(usize::MAX)));
+                    send_upstream_error_event(&tx, &e, 500, delivered_chars, &config).await;
+                    return;
+                }
+            }
+        }
+
+        let completed_at = Instant::now();
+        if !send_content(&tx, coalescer.flush()).await {
+            return;
+        }
+
+        // Send final usage stats
+        let answer_usage_total = answer_usage_total.unwrap_or_default();
+        let answer_tokens = match answer_usage_total.output_tokens {
+            0 => estimate_tokens(&complete_answer),
+            tokens => tokens,
+        };
+        // Pipelined drafts are billed as part of the answer phase
+        let billed_answer_usage = answer_usage_total + drafts.usage;
+        let mut answer_phase = answer_usage(answer_provider, billed_answer_usage, answer_config, &config);
+        answer_phase.forwarded_reasoning_tokens = (!responder_skipped).then_some(forwarded_reasoning_tokens);
+        if let Some(span) = answer_span {
+            telemetry::record_usage(&span, &answer_phase);
+        }
+        let usage = CombinedUsage {
+            // DeepSeek only reports usage on the final chunk, if at all, so
+            // this may be the estimate from the streamed reasoning
+            reasoning,
+            answer: answer_phase,
+            cost_format: config.cost_format.clone(),
+            timing: stream_timing(started, first_reasoning_at, first_answer_at, completed_at, answer_tokens),
+        };
+
+        // Upstream usage is billed whether or not the client is still connected
+        record_usage(
+            &registration.state,
+            registration.request_id.clone(),
+            &request_clone,
+            &config,
+            &usage,
+            started,
+            tally.as_deref(),
+        );
+
+        if !send_event(&tx, "usage", &StreamEvent::Usage { usage, estimated }).await {
+            return;
+        }
+
+        // Validate the assembled answer in JSON mode
+        if json_mode {
+            let answer = if strip_fences {
+                strip_json_fences(&complete_answer)
+            } else {
+                &complete_answer
+            };
+            if let Err(e) = validate_json_content(answer) {
+                send_upstream_error_event(&tx, &e, 502, None, &config).await;
+                return;
+            }
+        }
+
+        // Send the assembled answer for clients that don't want to rebuild it
+        if request_clone.stream_final_message {
+            let (answer, _) = CharBudget::new(limits.answer).take(&complete_answer);
+            let event = StreamEvent::FinalMessage {
+                content: vec![ContentBlock::text(answer)],
+            };
+            if !send_event(&tx, "final_message", &event).await {
+                return;
+            }
+        }
+
+        // Send done event
+        let (answer_word_count, warnings) =
+            check_answer_length(&complete_answer, request_clone.target_answer_words);
+        let done = StreamEvent::Done {
+            finish_reason,
+            answer_word_count,
+            warnings,
+            responder_skipped,
+            answer_provider: (!responder_skipped).then_some(answer_provider),
+            pipelined_drafts: drafts.completed,
+        };
+        if send_event(&tx, "done", &done).await {
+            record_stream_outcome(200);
+        }
+    }.instrument(span));
+
+    // Convert receiver into stream
     let stream = ReceiverStream::new(rx);
     Ok(SseResponse::new(stream))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// Asserts two dollar amounts are equal to within floating point error.
+    fn assert_cost(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected ${}, got ${}", expected, actual);
+    }
+
+    #[test]
+    fn anthropic_cost_bills_input_cache_reads_and_cache_writes_separately() {
+        let config = Config::default();
+
+        // Sonnet: $3 input, $15 output, $0.30 cache read, $3.75 cache write per million
+        let cost = calculate_anthropic_cost(1_000_000, 1_000_000, 1_000_000, 1_000_000, "claude-3-5-sonnet", &config);
+
+        assert_cost(cost, 3.0 + 15.0 + 0.30 + 3.75);
+    }
+
+    #[test]
+    fn deepseek_cost_counts_reasoning_within_output_by_default() {
+        let config = Config::default();
+
+        // $0.14 cache hit, $0.55 cache miss, $2.19 output per million
+        let cost = calculate_deepseek_cost(1_000_000, 1_000_000, 600_000, 200_000, "deepseek-reasoner", &config);
+
+        assert_cost(cost.cache_hit_cost, 0.028);
+        assert_cost(cost.cache_miss_cost, 0.44);
+        assert_cost(cost.output_cost, 0.876);
+        assert_cost(cost.reasoning_cost, 1.314);
+        assert_cost(cost.total(), 2.658);
+    }
+
+    #[test]
+    fn deepseek_cost_adds_reasoning_to_output_when_billed_separately() {
+        let mut config = Config::default();
+        config.pricing.deepseek.reasoning_billed_separately = true;
+
+        let cost = calculate_deepseek_cost(1_000_000, 1_000_000, 600_000, 200_000, "deepseek-reasoner", &config);
+
+        assert_cost(cost.output_cost, 2.19);
+        assert_cost(cost.reasoning_cost, 1.314);
+        assert_cost(cost.total(), 3.972);
+    }
+
+    #[test]
+    fn anthropic_cost_does_not_subtract_cache_reads_from_input() {
+        let config = Config::default();
+
+        let cost = calculate_anthropic_cost(1_000, 0, 5_000, 0, "claude-3-5-sonnet", &config);
+
+        assert_cost(cost, 1_000.0 / 1e6 * 3.0 + 5_000.0 / 1e6 * 0.30);
+    }
+
+    /// Collects the `status` values recorded on spans.
+    #[derive(Clone, Default)]
+    struct StatusRecorder(Arc<Mutex<Vec<u64>>>);
+
+    impl tracing::field::Visit for StatusRecorder {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            if field.name() == "status" {
+                self.0.lock().unwrap().push(value);
+            }
+        }
+
+        fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for StatusRecorder {
+        fn on_record(
+            &self,
+            _span: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_slow_stream_consumer_receives_every_event_in_order() {
+        let mut config = test_support::mock_config();
+        config.server.stream_channel_buffer = 1;
+        let body = serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true});
+        let names = |events: &[(String, serde_json::Value)]| events.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        let expected = test_support::sse_events(chat(&test_support::state(config.clone()), body.clone()).await).await;
+
+        // Read the body a frame at a time, pausing so the producer is always ahead
+        let response = chat(&test_support::state(config.clone()), body).await;
+        let mut frames = response.into_body().into_data_stream();
+        let mut received = Vec::new();
+        while let Some(frame) = frames.next().await {
+            received.extend_from_slice(&frame.unwrap());
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        let events = test_support::sse_events(axum::response::Response::new(axum::body::Body::from(received))).await;
+
+        assert_eq!(names(&events), names(&expected));
+        let text = |kind: &str| -> String {
+            events
+                .iter()
+                .filter(|(name, _)| name == "content")
+                .flat_map(|(_, event)| event["content"].as_array().unwrap().clone())
+                .filter(|block| block["type"] == kind)
+                .map(|block| block["text"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(text("thinking_delta"), config.mock.reasoning);
+        assert_eq!(text("text_delta"), config.mock.answer);
+        assert_eq!(events.last().unwrap().0, "done");
+    }
+
+    #[tokio::test]
+    async fn send_event_records_client_closed_request_once_the_client_is_gone() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorder = StatusRecorder::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        drop(rx);
+        let sink = StreamSink {
+            tx,
+            replay: None,
+            minimal: false,
+            event_names: HashMap::new(),
+        };
+
+        let span = tracing::info_span!("request", status = tracing::field::Empty);
+        let sent = send_event(&sink, "content", &StreamEvent::Content { content: Vec::new() })
+            .instrument(span)
+            .await;
+
+        assert!(!sent);
+        assert_eq!(*recorder.0.lock().unwrap(), vec![u64::from(CLIENT_CLOSED_REQUEST)]);
+    }
+
+    /// Sends a chat request with no extra headers.
+    async fn chat(state: &Arc<AppState>, body: serde_json::Value) -> axum::response::Response {
+        chat_with_headers(state, axum::http::HeaderMap::new(), body).await
+    }
+
+    /// Sends a chat request with the given headers.
+    async fn chat_with_headers(
+        state: &Arc<AppState>,
+        headers: axum::http::HeaderMap,
+        body: serde_json::Value,
+    ) -> axum::response::Response {
+        let request = serde_json::from_value(body).unwrap();
+        handle_chat(State(state.clone()), Query(ChatQuery::default()), headers, ClientTally(None), Json(request))
+            .await
+            .into_response()
+    }
+
+    #[tokio::test]
+    async fn reasoning_hash_header_is_the_sha256_of_the_returned_reasoning() {
+        let mut config = test_support::mock_config();
+        config.server.reasoning_hash_header = true;
+        let state = test_support::state(config);
+
+        let response = chat(&state, serde_json::json!({"messages": [{"role": "user", "content": "hi"}]})).await;
+        let header = response.headers()[REASONING_HASH_HEADER].to_str().unwrap().to_string();
+        let body = test_support::json_body(response).await;
+
+        let thinking = body["content"][0]["text"].as_str().unwrap();
+        assert!(thinking.starts_with("<thinking>"));
+        assert_eq!(header, format!("{:x}", Sha256::digest(thinking.as_bytes())));
+    }
+
+    #[tokio::test]
+    async fn answer_then_reason_drafts_with_gemini_then_has_deepseek_critique_the_draft() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("The draft is fine.", None),
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("Draft answer"), log.clone()).await;
+        let state = test_support::state(test_support::live_config(&deepseek, &gemini));
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "What is 2 + 2?"}],
+                "pipeline_order": "answer_then_reason"
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let calls = log.lock().unwrap().clone();
+        assert_eq!(calls.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["gemini", "deepseek"]);
+        let draft_contents = &calls[0].1["contents"];
+        assert_eq!(draft_contents.as_array().unwrap().len(), 1);
+        assert_eq!(draft_contents[0]["parts"][0]["text"], "What is 2 + 2?");
+        let critique_messages = calls[1].1["messages"].as_array().unwrap();
+        assert_eq!(critique_messages[0]["content"], "What is 2 + 2?");
+        assert_eq!(critique_messages[1]["role"], "assistant");
+        assert_eq!(critique_messages[1]["content"], "Draft answer");
+    }
+
+    /// Serves a DeepSeek stand-in that rejects every request, tagging the
+    /// rejection with an upstream request ID.
+    async fn rejecting_deepseek() -> String {
+        test_support::serve(axum::Router::new().fallback(|| async {
+            (
+                axum::http::StatusCode::BAD_REQUEST,
+                [("x-request-id", "ds-req-123")],
+                Json(serde_json::json!({"error": {"message": "bad", "type": "invalid_request_error"}})),
+            )
+        }))
+        .await
+    }
+
+    #[tokio::test]
+    async fn provider_request_id_is_returned_in_the_error_body() {
+        let deepseek = rejecting_deepseek().await;
+        let state = test_support::state(test_support::live_config(&deepseek, "http://127.0.0.1:9"));
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}]}),
+        )
+        .await;
+        let body = test_support::json_body(response).await;
+
+        assert_eq!(body["error"]["provider"], "deepseek");
+        assert_eq!(body["error"]["provider_request_id"], "ds-req-123");
+    }
+
+    #[tokio::test]
+    async fn provider_request_id_is_returned_in_the_stream_error_event() {
+        let deepseek = rejecting_deepseek().await;
+        let state = test_support::state(test_support::live_config(&deepseek, "http://127.0.0.1:9"));
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+        let events = test_support::sse_events(response).await;
+
+        let (_, error) = events.iter().find(|(name, _)| name == "error").unwrap();
+        assert_eq!(error["error"]["provider_request_id"], "ds-req-123");
+    }
+
+    #[tokio::test]
+    async fn target_answer_words_instructs_the_responder_and_reports_the_word_count() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("", Some("Some reasoning.")),
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider(
+            "gemini",
+            test_support::gemini_body("one two three four five six seven eight"),
+            log.clone(),
+        )
+        .await;
+        let state = test_support::state(test_support::live_config(&deepseek, &gemini));
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "Count"}],
+                "target_answer_words": 4
+            }),
+        )
+        .await;
+        let body = test_support::json_body(response).await;
+
+        let calls = log.lock().unwrap().clone();
+        let (_, gemini_request) = calls.iter().find(|(name, _)| *name == "gemini").unwrap();
+        let system = gemini_request["systemInstruction"]["parts"][0]["text"].as_str().unwrap();
+        assert!(system.contains("Limit your answer to about 4 words."));
+        assert_eq!(body["answer_word_count"], 8);
+        assert_eq!(body["warnings"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reasoning_with_a_final_answer_skips_the_responder() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("", Some("2 + 2 makes 4.\nFinal answer: 4")),
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("unused"), log.clone()).await;
+        let mut config = test_support::live_config(&deepseek, &gemini);
+        config.pipeline.short_circuit_if_reasoning_answers = true;
+        let state = test_support::state(config);
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "What is 2 + 2?"}]}),
+        )
+        .await;
+        let body = test_support::json_body(response).await;
+
+        assert!(log.lock().unwrap().iter().all(|(name, _)| *name != "gemini"));
+        assert_eq!(body["responder_skipped"], true);
+        let content = body["content"].as_array().unwrap();
+        assert_eq!(content.last().unwrap()["text"], "4");
+    }
+
+    #[test]
+    fn extract_final_answer_takes_the_last_marked_line() {
+        let reasoning = "Final answer: 3\nWait, recount.\nfinal ANSWER:  4 ";
+
+        assert_eq!(extract_final_answer(reasoning).as_deref(), Some("4"));
+        assert_eq!(extract_final_answer("No conclusion yet."), None);
+    }
+
+    #[tokio::test]
+    async fn slow_reasoning_sends_running_usage_estimates() {
+        let mut config = test_support::mock_config();
+        config.mock.reasoning = "Thinking it through step by step. ".repeat(20);
+        config.mock.chunk_delay_ms = 10;
+        config.pipeline.usage_update_interval_ms = Some(25);
+        let state = test_support::state(config);
+
+        let response = chat(
+            &state,
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+        let events = test_support::sse_events(response).await;
+
+        // Estimates sent while DeepSeek is still reasoning
+        let reasoning_end = events.iter().position(|(name, _)| name == "reasoning_usage").unwrap();
+        let estimates: Vec<&serde_json::Value> = events[..reasoning_end]
+            .iter()
+            .filter(|(name, data)| name == "usage" && data["estimated"] == true)
+            .map(|(_, data)| &data["usage"])
+            .collect();
+        let reasoning_tokens: Vec<u64> = estimates
+            .iter()
+            .map(|usage| usage["deepseek_usage"]["output_tokens"].as_u64().unwrap())
+            .collect();
+        assert!(reasoning_tokens.len() >= 2, "expected several estimates, got {:?}", reasoning_tokens);
+        assert!(reasoning_tokens.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(reasoning_tokens.first() < reasoning_tokens.last());
+        assert!(estimates.iter().all(|usage| usage["gemini_usage"]["output_tokens"] == 0));
+    }
+
+    #[tokio::test]
+    async fn system_prompt_reaches_both_providers() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("", Some("Some reasoning.")),
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("Hi."), log.clone()).await;
+        let state = test_support::state(test_support::live_config(&deepseek, &gemini));
+
+        chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"system": "Be terse.", "messages": [{"role": "user", "content": "hi"}]}),
+        )
+        .await;
+
+        let calls = log.lock().unwrap().clone();
+        let deepseek_messages = &calls[0].1["messages"];
+        assert_eq!(deepseek_messages[0]["role"], "system");
+        assert_eq!(deepseek_messages[0]["content"], "Be terse.");
+        let gemini_request = &calls[1].1;
+        let system = gemini_request["systemInstruction"]["parts"][0]["text"].as_str().unwrap();
+        assert!(system.starts_with("Be terse."));
+        let contents = gemini_request["contents"].as_array().unwrap();
+        assert!(contents.iter().all(|content| content["role"] != "system"));
+        assert!(contents.iter().all(|content| !content.to_string().contains("Be terse.")));
+    }
+
+    #[tokio::test]
+    async fn blocked_gemini_prompt_is_reported_as_prompt_blocked() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("", Some("Some reasoning.")),
+            log.clone(),
+        )
+        .await;
+        let blocked = serde_json::json!({
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "safetyRatings": [{"category": "HARM_CATEGORY_HARASSMENT", "probability": "HIGH"}]
+            }
+        });
+        let gemini = test_support::fake_provider("gemini", blocked, log.clone()).await;
+        let mut config = test_support::live_config(&deepseek, &gemini);
+        config.gemini.safety_settings = HashMap::from([
+            ("HARM_CATEGORY_HARASSMENT".to_string(), "BLOCK_LOW_AND_ABOVE".to_string()),
+            ("HARM_CATEGORY_HATE_SPEECH".to_string(), "BLOCK_ONLY_HIGH".to_string()),
+        ]);
+        let state = test_support::state(config);
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "gemini_config": {"safety_settings": {"HARM_CATEGORY_HATE_SPEECH": "BLOCK_NONE"}}
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let body = test_support::json_body(response).await;
+        assert_eq!(body["error"]["type"], "prompt_blocked");
+        assert_eq!(body["error"]["param"], "SAFETY");
+
+        // Server thresholds apply unless the request overrides them
+        let calls = log.lock().unwrap().clone();
+        let (_, gemini_request) = calls.iter().find(|(name, _)| *name == "gemini").unwrap();
+        let mut settings: Vec<(String, String)> = gemini_request["safetySettings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|setting| {
+                (
+                    setting["category"].as_str().unwrap().to_string(),
+                    setting["threshold"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        settings.sort();
+        assert_eq!(
+            settings,
+            [
+                ("HARM_CATEGORY_HARASSMENT".to_string(), "BLOCK_LOW_AND_ABOVE".to_string()),
+                ("HARM_CATEGORY_HATE_SPEECH".to_string(), "BLOCK_NONE".to_string()),
+            ]
+        );
+    }
+
+    /// Returns headers carrying only the given DeepSeek API token.
+    fn deepseek_token(token: &'static str) -> axum::http::HeaderMap {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("X-DeepSeek-API-Token", axum::http::HeaderValue::from_static(token));
+        headers
+    }
+
+    #[tokio::test]
+    async fn only_the_credential_that_started_a_stream_can_cancel_it() {
+        let mut config = test_support::mock_config();
+        config.mock.chunk_delay_ms = 20;
+        let state = test_support::state(config);
+
+        let response = chat_with_headers(
+            &state,
+            deepseek_token("owner-token"),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+        let request_id = state.active_streams.lock().unwrap().keys().next().unwrap().clone();
+
+        let other = cancel_stream(State(state.clone()), Path(request_id.clone()), deepseek_token("other-token")).await;
+        assert_eq!(other.unwrap_err().status_and_body(&HashMap::new()).0, axum::http::StatusCode::NOT_FOUND);
+        let anonymous = cancel_stream(State(state.clone()), Path(request_id.clone()), axum::http::HeaderMap::new()).await;
+        assert!(anonymous.is_err());
+
+        let owner = cancel_stream(State(state.clone()), Path(request_id), deepseek_token("owner-token")).await;
+        assert!(owner.unwrap().cancelled);
+        let events = test_support::sse_events(response).await;
+        assert!(events.iter().any(|(name, _)| name == "cancelled"));
+    }
+
+    /// Returns a config limiting `gemini-small` to 1000 output tokens.
+    fn small_gemini_config(clamp: bool) -> Config {
+        let mut config = Config::default();
+        config.pipeline.clamp_max_tokens = clamp;
+        let small = ModelPricing {
+            max_output_tokens: Some(1000),
+            ..config.pricing.gemini.gemini_pro.clone()
+        };
+        config.pricing.gemini.models.insert("gemini-small".to_string(), small);
+        config
+    }
+
+    /// Returns a request selecting `model` for Gemini with the given `max_tokens`.
+    fn gemini_request(model: Option<&str>, max_tokens: u32) -> ApiRequest {
+        let mut body = serde_json::json!({"max_tokens": max_tokens});
+        if let Some(model) = model {
+            body["model"] = serde_json::json!(model);
+        }
+        serde_json::from_value(serde_json::json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "gemini_config": {"body": body}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn max_tokens_over_the_selected_gemini_models_limit_is_rejected() {
+        let config = small_gemini_config(false);
+
+        let error = with_max_tokens_limits(gemini_request(Some("gemini-small"), 2000), &config).unwrap_err();
+
+        assert!(matches!(&error, ApiError::BadRequest { message } if message.contains("gemini-small")));
+    }
+
+    #[test]
+    fn max_tokens_over_the_selected_gemini_models_limit_is_clamped() {
+        let config = small_gemini_config(true);
+
+        let request = with_max_tokens_limits(gemini_request(Some("gemini-small"), 2000), &config).unwrap();
+
+        assert_eq!(request.gemini_config.max_tokens(), Some(1000));
+    }
+
+    #[test]
+    fn unlisted_gemini_models_use_the_default_limit() {
+        let config = small_gemini_config(false);
+
+        let request = with_max_tokens_limits(gemini_request(Some("gemini-other"), 2000), &config).unwrap();
+        assert_eq!(request.gemini_config.max_tokens(), Some(2000));
+        assert!(with_max_tokens_limits(gemini_request(None, 9000), &config).is_err());
+    }
+
+    /// Runs a chat request against fake providers with the given DeepSeek
+    /// model and response, returning the response body and the Gemini request.
+    async fn chat_with_deepseek_model(
+        model: &str,
+        deepseek_body: serde_json::Value,
+    ) -> (serde_json::Value, serde_json::Value) {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider("deepseek", deepseek_body, log.clone()).await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("Answer."), log.clone()).await;
+        let state = test_support::state(test_support::live_config(&deepseek, &gemini));
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "deepseek_config": {"body": {"model": model}}
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = test_support::json_body(response).await;
+        let calls = log.lock().unwrap().clone();
+        let (_, gemini_request) = calls.into_iter().find(|(name, _)| *name == "gemini").unwrap();
+        (body, gemini_request)
+    }
+
+    #[tokio::test]
+    async fn assistant_message_carries_the_answer() {
+        let config = test_support::mock_config();
+        let answer = config.mock.answer.clone();
+        let state = test_support::state(config);
+
+        let response = chat(&state, serde_json::json!({"messages": [{"role": "user", "content": "hi"}]})).await;
+
+        let body = test_support::json_body(response).await;
+        let message: Message = serde_json::from_value(body["assistant_message"].clone()).unwrap();
+        assert!(matches!(message.role, Role::Assistant));
+        assert_eq!(message.content.text(), answer);
+    }
+
+    #[test]
+    fn json_fences_are_stripped_with_or_without_a_language_tag() {
+        for fenced in ["```json\n{\"answer\": 4}\n```", "```\n{\"answer\": 4}\n```", "  ```\n[1, 2]```\n"] {
+            let stripped = strip_json_fences(fenced);
+
+            assert!(validate_json_content(fenced).is_err());
+            assert!(!stripped.contains("```"), "{:?}", stripped);
+            assert!(validate_json_content(stripped).is_ok(), "{:?}", stripped);
+        }
+        assert_eq!(strip_json_fences("```json\n{\"answer\": 4}\n```"), "{\"answer\": 4}");
+    }
+
+    #[tokio::test]
+    async fn busted_requests_send_distinct_nonces_that_never_reach_the_content() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("Ignored answer.", Some("Reasoning.")),
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("Answer."), log.clone()).await;
+        let state = test_support::state(test_support::live_config(&deepseek, &gemini));
+
+        let mut contents = Vec::new();
+        for _ in 0..2 {
+            let response = chat_with_headers(
+                &state,
+                test_support::token_headers(),
+                serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "bust_cache": true}),
+            )
+            .await;
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+            contents.push(test_support::json_body(response).await["content"].to_string());
+        }
+
+        let nonces: Vec<String> = log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| *name == "deepseek")
+            .map(|(_, body)| {
+                let system = body["messages"][0]["content"].as_str().unwrap();
+                system[system.find("<!-- nonce: ").unwrap()..].to_string()
+            })
+            .collect();
+        assert_eq!(nonces.len(), 2);
+        assert_ne!(nonces[0], nonces[1]);
+        for content in &contents {
+            assert!(!content.contains("nonce"), "{}", content);
+        }
+    }
+
+    #[test]
+    fn an_invalid_reloaded_config_leaves_the_pricing_unchanged() {
+        let state = test_support::state(test_support::mock_config());
+        let mut loaded = test_support::mock_config();
+        loaded.pricing.deepseek.output_price = 99.0;
+        loaded.pipeline.pipelined_draft_interval_chars = Some(0);
+
+        let result = swap_pricing(&state, loaded.clone());
+
+        assert!(matches!(result, Err(ApiError::Internal { .. })));
+        assert_ne!(state.config().pricing.deepseek.output_price, 99.0);
+
+        loaded.pipeline.pipelined_draft_interval_chars = None;
+        let pricing = swap_pricing(&state, loaded).unwrap();
+        assert_eq!(pricing.deepseek.output_price, 99.0);
+        assert_eq!(state.config().pricing.deepseek.output_price, 99.0);
+    }
+
+    #[tokio::test]
+    async fn models_are_listed_from_the_pricing_configuration() {
+        let mut config = test_support::mock_config();
+        config.pricing.deepseek.models.insert(
+            "deepseek-chat".to_string(),
+            crate::config::DeepSeekModelPricing {
+                kind: DeepSeekModelKind::Chat,
+                input_cache_hit_price: 0.07,
+                input_cache_miss_price: 0.27,
+                output_price: 1.10,
+                max_output_tokens: None,
+            },
+        );
+        config.pricing.gemini.models.insert(
+            "gemini-1.5-flash".to_string(),
+            ModelPricing {
+                input_price: 0.075,
+                output_price: 0.30,
+                cache_write_price: 0.0,
+                cache_read_price: 0.01875,
+                max_output_tokens: Some(8192),
+            },
+        );
+        let state = test_support::state(config.clone());
+
+        let Json(response) = list_models(State(state)).await;
+
+        let model = |id: &str| response.models.iter().find(|model| model.id == id).unwrap();
+        let chat = model("deepseek-chat");
+        assert_eq!(chat.kind, Some(DeepSeekModelKind::Chat));
+        assert_eq!((chat.input_price, chat.output_price, chat.cache_read_price), (0.27, 1.10, 0.07));
+        let reasoner = model(crate::clients::deepseek::DEFAULT_MODEL);
+        assert_eq!(reasoner.kind, Some(DeepSeekModelKind::Reasoner));
+        assert_eq!(reasoner.input_price, config.pricing.deepseek.input_cache_miss_price);
+        let flash = model("gemini-1.5-flash");
+        assert_eq!((flash.provider.as_str(), flash.role), ("gemini", ModelRole::Answer));
+        assert_eq!((flash.input_price, flash.output_price), (0.075, 0.30));
+        assert_eq!(model(crate::clients::gemini::DEFAULT_MODEL).input_price, config.pricing.gemini.gemini_pro.input_price);
+        assert!(response.models.iter().all(|model| model.id != "deepseek" && model.id != "gemini_pro"));
+    }
+
+    #[tokio::test]
+    async fn short_reasoning_is_an_error_by_default() {
+        let mut config = test_support::mock_config();
+        config.mock.reasoning = "ok".to_string();
+        config.pipeline.min_reasoning_chars = Some(10);
+        let state = test_support::state(config);
+
+        let response = chat(&state, serde_json::json!({"messages": [{"role": "user", "content": "hi"}]})).await;
+
+        assert!(!response.status().is_success());
+        let body = test_support::json_body(response).await;
+        assert_eq!(body["error"]["type"], "deepseek_insufficient_reasoning", "{}", body);
+    }
+
+    #[tokio::test]
+    async fn short_reasoning_is_retried_and_every_attempt_is_billed() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::sequenced_provider(
+            "deepseek",
+            vec![
+                test_support::deepseek_body("", Some("ok")),
+                test_support::deepseek_body("", Some("Long enough reasoning.")),
+            ],
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("Answer."), log.clone()).await;
+        let mut config = test_support::live_config(&deepseek, &gemini);
+        config.pipeline.min_reasoning_chars = Some(10);
+        config.pipeline.short_reasoning_action = ShortReasoningAction::Retry;
+        config.pipeline.short_reasoning_retries = 1;
+        let state = test_support::state(config);
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}]}),
+        )
+        .await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = test_support::json_body(response).await;
+        let deepseek_calls = log.lock().unwrap().iter().filter(|(name, _)| *name == "deepseek").count();
+        assert_eq!(deepseek_calls, 2);
+        let usage = &body["combined_usage"]["deepseek_usage"];
+        assert_eq!(usage["input_tokens"], 20, "{}", body);
+        assert_eq!(usage["output_tokens"], 40, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn reasoner_model_forwards_its_reasoning_content() {
+        let (body, gemini_request) = chat_with_deepseek_model(
+            "deepseek-reasoner",
+            test_support::deepseek_body("Ignored answer.", Some("Reasoner thoughts.")),
+        )
+        .await;
+
+        assert!(gemini_request.to_string().contains("Reasoner thoughts."));
+        assert!(!gemini_request.to_string().contains("Ignored answer."));
+        assert_eq!(body["combined_usage"]["deepseek_usage"]["reasoning_tokens"], 15);
+    }
+
+    #[test]
+    fn missing_reasoning_is_an_error_when_required() {
+        let response: DeepSeekResponse =
+            serde_json::from_value(test_support::deepseek_body("Plain answer.", None)).unwrap();
+
+        let result = extract_reasoning(&response, true);
+
+        assert!(
+            matches!(&result, Err(ApiError::DeepSeekError { type_, .. }) if type_ == "missing_content"),
+            "{:?}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn reasoner_without_reasoning_content_forwards_its_content_by_default() {
+        let (body, gemini_request) = chat_with_deepseek_model(
+            "deepseek-reasoner",
+            test_support::deepseek_body("Fallback thoughts.", None),
+        )
+        .await;
+
+        assert!(gemini_request.to_string().contains("Fallback thoughts."));
+        assert!(body["content"].to_string().contains("Fallback thoughts."), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn chat_model_forwards_its_content_as_the_reasoning() {
+        let (body, gemini_request) =
+            chat_with_deepseek_model("deepseek-chat", test_support::deepseek_body("Chat thoughts.", None)).await;
+
+        assert!(gemini_request.to_string().contains("Chat thoughts."));
+        let thinking = body["content"][0]["text"].as_str().unwrap();
+        assert!(thinking.starts_with("<thinking>") && thinking.contains("Chat thoughts."));
+    }
+
+    #[test]
+    fn chat_model_is_priced_at_its_own_rates() {
+        let config = Config::default();
+
+        // deepseek-chat: $0.27 cache miss and $1.10 output per million
+        let cost = calculate_deepseek_cost(1_000_000, 1_000_000, 0, 0, "deepseek-chat", &config);
+
+        assert_cost(cost.total(), 0.27 + 1.10);
+    }
+
+    #[test]
+    fn request_ids_are_128_random_bits() {
+        let first = new_request_id();
+        let second = new_request_id();
+
+        let hex = first.strip_prefix("req_").unwrap();
+        assert_eq!(hex.len(), 32);
+        assert!(hex.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+
+    /// Returns a request selecting `model` for Anthropic.
+    fn anthropic_request(model: Option<&str>) -> ApiRequest {
+        let body = model.map_or(serde_json::json!({}), |model| serde_json::json!({"model": model}));
+        serde_json::from_value(serde_json::json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "anthropic_config": {"body": body}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn anthropic_models_outside_the_allow_list_are_rejected() {
+        let mut config = Config::default();
+        config.anthropic.models.allowed_models = vec!["claude-3-5-haiku".to_string()];
+
+        assert!(check_allowed_models(&anthropic_request(Some("claude-3-5-haiku")), &config).is_ok());
+        let error = check_allowed_models(&anthropic_request(Some("claude-3-opus")), &config).unwrap_err();
+        assert!(matches!(error, ApiError::ModelNotAllowed { model } if model == "claude-3-opus"));
+    }
+
+    #[test]
+    fn denied_anthropic_models_are_rejected_including_the_default() {
+        let mut config = Config::default();
+        config.anthropic.models.denied_models = vec![crate::clients::anthropic::DEFAULT_MODEL.to_string()];
+
+        assert!(check_allowed_models(&anthropic_request(None), &config).is_err());
+        assert!(check_allowed_models(&anthropic_request(Some("claude-3-5-haiku")), &config).is_ok());
+    }
+
+    /// Sends a batch with `count` copies of a simple request.
+    async fn batch(state: &Arc<AppState>, count: usize) -> Result<Json<BatchResponse>> {
+        let request = serde_json::json!({"messages": [{"role": "user", "content": "hi"}]});
+        let batch = serde_json::from_value(serde_json::json!({"requests": vec![request; count]})).unwrap();
+        handle_batch(State(state.clone()), axum::http::HeaderMap::new(), ClientTally(None), Json(batch)).await
+    }
+
+    #[tokio::test]
+    async fn batch_requests_each_take_a_request_slot() {
+        let mut config = test_support::mock_config();
+        config.server.max_concurrent_requests = Some(1);
+        let state = test_support::state(config);
+
+        let Json(response) = batch(&state, 2).await.unwrap();
+        assert_eq!(response.succeeded, 2);
+
+        let _held = state.request_limiter.acquire().await.unwrap();
+        let Json(response) = batch(&state, 2).await.unwrap();
+        assert_eq!(response.failed, 2);
+        assert!(response.results.iter().all(|result| result.status == Some(503)));
+    }
+
+    #[tokio::test]
+    async fn batch_size_is_capped() {
+        let mut config = test_support::mock_config();
+        config.server.batch_max_requests = 2;
+        let state = test_support::state(config);
+
+        assert!(batch(&state, 2).await.is_ok());
+        assert!(matches!(batch(&state, 3).await, Err(ApiError::BadRequest { .. })));
+    }
+
+    #[tokio::test]
+    async fn newlines_in_messages_and_answers_keep_the_stream_well_formed() {
+        let mut config = test_support::mock_config();
+        config.mock.answer = "first line\nsecond\r\nthird".to_string();
+        config.mock.chunk_delay_ms = 0;
+        let state = test_support::state(config);
+
+        let response = chat(
+            &state,
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "line one\nline two\r\n\ndata: injected"}],
+                "stream": true
+            }),
+        )
+        .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let mut answer = String::new();
+        for frame in body.split("\n\n").filter(|frame| !frame.is_empty()) {
+            for line in frame.lines() {
+                let (field, value) = line.split_once(':').unwrap();
+                assert!(["event", "data", "id", "retry", ""].contains(&field), "bad line {:?}", line);
+                if field == "data" {
+                    let data: serde_json::Value = serde_json::from_str(value.trim()).unwrap();
+                    for block in data["content"].as_array().into_iter().flatten() {
+                        if block["type"] == "text_delta" {
+                            answer.push_str(block["text"].as_str().unwrap());
+                        }
+                    }
+                }
+            }
+        }
+        assert!(answer.ends_with("first line\nsecond\r\nthird"), "{:?}", answer);
+    }
+
+    #[tokio::test]
+    async fn null_characters_are_rejected_before_the_stream_starts() {
+        let state = test_support::state(test_support::mock_config());
+
+        let response = chat(
+            &state,
+            serde_json::json!({"messages": [{"role": "user", "content": "a\0b"}], "stream": true}),
+        )
+        .await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = test_support::json_body(response).await;
+        assert!(body["error"]["message"].as_str().unwrap().contains("messages[0]"));
+    }
+
+    /// Streams a chat request against a DeepSeek fake sending `chunks`, and
+    /// returns the reasoning streamed as thinking deltas.
+    async fn streamed_reasoning(chunks: Vec<serde_json::Value>) -> String {
+        let deepseek = test_support::streaming_provider(chunks).await;
+        let state = test_support::state(test_support::live_config(&deepseek, "http://127.0.0.1:9"));
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+
+        test_support::sse_events(response)
+            .await
+            .iter()
+            .filter(|(name, _)| name == "content")
+            .flat_map(|(_, data)| data["content"].as_array().cloned().unwrap_or_default())
+            .filter(|block| block["type"] == "thinking_delta")
+            .map(|block| block["text"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn streams_without_a_deepseek_usage_chunk_report_the_estimated_cost() {
+        let deepseek = test_support::streaming_provider(vec![
+            test_support::deepseek_chunk(Some("Counting the letters one by one."), None, None),
+            test_support::deepseek_chunk(None, Some("Three"), Some("stop")),
+        ])
+        .await;
+        let gemini = test_support::streaming_provider(vec![test_support::gemini_body("Three.")]).await;
+        let mut config = test_support::live_config(&deepseek, &gemini);
+        config.cost_format.decimal_places = 9;
+        let state = test_support::state(config);
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+
+        let events = test_support::sse_events(response).await;
+        let (_, usage) = events.iter().rfind(|(name, _)| name == "usage").unwrap();
+        assert_eq!(usage["estimated"], true);
+        let deepseek_usage = &usage["usage"]["deepseek_usage"];
+        assert!(deepseek_usage["output_tokens"].as_u64().unwrap() > 0, "{}", usage);
+        let cost: f64 = deepseek_usage["total_cost"].as_str().unwrap().trim_start_matches('$').parse().unwrap();
+        assert!(cost > 0.0, "{}", usage);
+    }
+
+    #[tokio::test]
+    async fn keepalive_chunks_do_not_end_the_streamed_reasoning() {
+        let reasoning = streamed_reasoning(vec![
+            test_support::deepseek_chunk(Some("First "), None, None),
+            test_support::deepseek_chunk(None, None, None),
+            test_support::deepseek_chunk(Some(""), Some(""), None),
+            test_support::deepseek_chunk(Some("second."), None, None),
+            test_support::deepseek_chunk(None, Some("Answer"), Some("stop")),
+        ])
+        .await;
+
+        assert_eq!(reasoning, "First second.");
+    }
+
+    #[tokio::test]
+    async fn answer_content_ends_the_streamed_reasoning() {
+        let reasoning = streamed_reasoning(vec![
+            test_support::deepseek_chunk(Some("Thinking."), None, None),
+            test_support::deepseek_chunk(None, Some("Answer"), None),
+            test_support::deepseek_chunk(Some("Ignored."), None, None),
+        ])
+        .await;
+
+        assert_eq!(reasoning, "Thinking.");
+    }
+
+    #[tokio::test]
+    async fn finish_reason_ends_the_streamed_reasoning() {
+        let reasoning = streamed_reasoning(vec![
+            test_support::deepseek_chunk(Some("Done."), None, Some("stop")),
+            test_support::deepseek_chunk(Some("Ignored."), None, None),
+        ])
+        .await;
+
+        assert_eq!(reasoning, "Done.");
+    }
+
+    #[tokio::test]
+    async fn streamed_content_without_reasoning_is_used_as_the_reasoning() {
+        let reasoning = streamed_reasoning(vec![
+            test_support::deepseek_chunk(None, Some("Plain "), None),
+            test_support::deepseek_chunk(None, Some("content."), Some("stop")),
+        ])
+        .await;
+
+        assert_eq!(reasoning, "Plain content.");
+    }
+
+    /// An event whose serialization always fails.
+    struct Unserializable;
+
+    impl serde::Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unserializable"))
+        }
+    }
+
+    #[test]
+    fn unserializable_events_become_internal_error_events() {
+        let (name, data) = serialize_event("content", &Unserializable);
+        let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+
+        assert_eq!(name, "error");
+        assert_eq!(data["type"], "error");
+        assert_eq!(data["status"], 500);
+        assert_eq!(data["error"]["message"], "Failed to serialize content event");
+    }
+
+    #[test]
+    fn serializable_events_keep_their_name() {
+        let (name, data) = serialize_event("cancelled", &StreamEvent::Cancelled {});
+
+        assert_eq!(name, "cancelled");
+        assert_eq!(data, r#"{"type":"cancelled"}"#);
+    }
+
+    /// Returns DeepSeek usage with the given cache-hit figures.
+    fn deepseek_usage(cache_hit_tokens: Option<u32>, cached_tokens: u32) -> deepseek::Usage {
+        let mut usage = serde_json::json!({
+            "prompt_tokens": 100,
+            "completion_tokens": 20,
+            "total_tokens": 120,
+            "prompt_tokens_details": {"cached_tokens": cached_tokens},
+            "completion_tokens_details": {"reasoning_tokens": 15}
+        });
+        if let Some(hits) = cache_hit_tokens {
+            usage["prompt_cache_hit_tokens"] = hits.into();
+            usage["prompt_cache_miss_tokens"] = (100 - hits).into();
+        }
+        serde_json::from_value(usage).unwrap()
+    }
+
+    #[test]
+    fn prompt_cache_hit_tokens_are_reported_as_cached_input_tokens() {
+        let config = Config::default();
+
+        let usage = reasoning_usage(&deepseek_usage(Some(60), 0), "deepseek-reasoner", &config);
+        assert_eq!(usage.cached_input_tokens, 60);
+
+        let fallback = reasoning_usage(&deepseek_usage(None, 25), "deepseek-reasoner", &config);
+        assert_eq!(fallback.cached_input_tokens, 25);
+    }
+
+    #[tokio::test]
+    async fn usage_totals_sum_both_providers() {
+        let (body, _) = chat_with_deepseek_model(
+            "deepseek-reasoner",
+            test_support::deepseek_body("", Some("Reasoning.")),
+        )
+        .await;
+
+        let usage = &body["combined_usage"];
+        assert_eq!(usage["deepseek_usage"]["input_tokens"], 10);
+        assert_eq!(usage["gemini_usage"]["input_tokens"], 5);
+        assert_eq!(usage["total_input_tokens"], 15);
+        assert_eq!(usage["total_output_tokens"], 23);
+    }
+
+    /// Sends a chat request whose Gemini answer call fails to connect, with
+    /// Anthropic configured as the fallback answer provider.
+    async fn chat_with_gemini_down(headers: axum::http::HeaderMap) -> axum::response::Response {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("", Some("Reasoning.")),
+            log.clone(),
+        )
+        .await;
+        let anthropic = test_support::fake_provider(
+            "anthropic",
+            serde_json::json!({
+                "id": "msg",
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type": "text", "text": "Fallback answer."}],
+                "model": "claude-3-5-sonnet-20241022",
+                "stop_reason": "end_turn",
+                "stop_sequence": null,
+                "usage": {"input_tokens": 7, "output_tokens": 4}
+            }),
+            log,
+        )
+        .await;
+        let mut config = test_support::live_config(&deepseek, "http://127.0.0.1:9");
+        config.anthropic.base_url = Some(anthropic);
+        config.pipeline.fallback_answer_provider = Some(AnswerProvider::Anthropic);
+        let state = test_support::state(config);
+
+        chat_with_headers(&state, headers, serde_json::json!({"messages": [{"role": "user", "content": "hi"}]})).await
+    }
+
+    #[tokio::test]
+    async fn failed_gemini_answer_falls_back_to_the_configured_provider() {
+        let mut headers = test_support::token_headers();
+        headers.insert("X-Anthropic-API-Token", axum::http::HeaderValue::from_static("anthropic-token"));
+
+        let response = chat_with_gemini_down(headers).await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = test_support::json_body(response).await;
+        assert_eq!(body["answer_provider"], "anthropic");
+        assert!(body.to_string().contains("Fallback answer."));
+        assert_eq!(body["combined_usage"]["anthropic_usage"]["input_tokens"], 7);
+        assert!(body["combined_usage"].get("gemini_usage").is_none());
+    }
+
+    #[tokio::test]
+    async fn fallback_needs_the_fallback_providers_token() {
+        let response = chat_with_gemini_down(test_support::token_headers()).await;
+
+        assert!(!response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_rejected() {
+        let mut config = test_support::mock_config();
+        config.server.max_message_chars = Some(5);
+        let state = test_support::state(config);
+
+        let response = chat(&state, serde_json::json!({"messages": [{"role": "user", "content": "too long"}]})).await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let response = chat(&state, serde_json::json!({"messages": [{"role": "user", "content": "short"}]})).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    fn accept(value: &'static str) -> axum::http::HeaderMap {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(axum::http::header::ACCEPT, axum::http::HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn stream_query_parameter_overrides_body_and_accept() {
+        for body in [None, Some(true), Some(false)] {
+            for headers in [accept("text/event-stream"), accept("application/json"), axum::http::HeaderMap::new()] {
+                assert!(stream_mode(Some(true), &headers, body));
+                assert!(!stream_mode(Some(false), &headers, body));
+            }
+        }
+    }
+
+    #[test]
+    fn body_flag_overrides_accept() {
+        assert!(stream_mode(None, &accept("application/json"), Some(true)));
+        assert!(!stream_mode(None, &accept("text/event-stream"), Some(false)));
+    }
+
+    #[test]
+    fn accept_decides_when_the_body_flag_is_absent() {
+        assert!(stream_mode(None, &accept("text/event-stream"), None));
+        assert!(stream_mode(None, &accept("Text/Event-Stream; charset=utf-8"), None));
+        assert!(!stream_mode(None, &accept("application/json"), None));
+    }
+
+    #[test]
+    fn ambiguous_or_missing_accept_does_not_stream() {
+        assert!(!stream_mode(None, &axum::http::HeaderMap::new(), None));
+        assert!(!stream_mode(None, &accept("*/*"), None));
+        assert!(!stream_mode(None, &accept("text/event-stream, application/json"), None));
+    }
+
+    #[tokio::test]
+    async fn accept_header_streams_a_request_without_a_stream_flag() {
+        let state = test_support::state(test_support::mock_config());
+
+        let response = chat_with_headers(
+            &state,
+            accept("text/event-stream"),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}]}),
+        )
+        .await;
+
+        let content_type = response.headers().get(axum::http::header::CONTENT_TYPE).unwrap();
+        assert!(content_type.to_str().unwrap().starts_with("text/event-stream"));
+    }
+
+    #[test]
+    fn reasoning_usage_cost_is_the_sum_of_its_breakdown() {
+        let config = Config::default();
+        let usage: deepseek::Usage = serde_json::from_value(serde_json::json!({
+            "prompt_tokens": 1_000_000,
+            "completion_tokens": 1_000_000,
+            "total_tokens": 2_000_000,
+            "prompt_tokens_details": {"cached_tokens": 0},
+            "completion_tokens_details": {"reasoning_tokens": 600_000},
+            "prompt_cache_hit_tokens": 200_000,
+            "prompt_cache_miss_tokens": 800_000
+        }))
+        .unwrap();
+
+        let usage = reasoning_usage(&usage, "deepseek-reasoner", &config);
+
+        let breakdown = usage.cost_breakdown.unwrap();
+        assert_cost(breakdown.cache_hit_cost, 0.028);
+        assert_cost(breakdown.cache_miss_cost, 0.44);
+        assert_cost(breakdown.output_cost, 0.876);
+        assert_cost(breakdown.reasoning_cost, 1.314);
+        assert_cost(usage.cost, 2.658);
+    }
+
+    #[tokio::test]
+    async fn few_shot_examples_reach_both_providers_between_system_and_conversation() {
+        let log = test_support::CallLog::default();
+        let deepseek = test_support::fake_provider(
+            "deepseek",
+            test_support::deepseek_body("", Some("Some reasoning.")),
+            log.clone(),
+        )
+        .await;
+        let gemini = test_support::fake_provider("gemini", test_support::gemini_body("Hi."), log.clone()).await;
+        let mut config = test_support::live_config(&deepseek, &gemini);
+        config.pipeline.few_shot_examples = serde_json::from_value(serde_json::json!([
+            {"role": "user", "content": "Example question"},
+            {"role": "assistant", "content": "Example answer"}
+        ]))
+        .unwrap();
+        let state = test_support::state(config);
+
+        chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"system": "Be terse.", "messages": [{"role": "user", "content": "Real question"}]}),
+        )
+        .await;
+
+        let calls = log.lock().unwrap().clone();
+        let deepseek_contents: Vec<_> = calls[0].1["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|msg| msg["content"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(deepseek_contents, ["Be terse.", "Example question", "Example answer", "Real question"]);
+        let contents = calls[1].1["contents"].as_array().unwrap();
+        assert_eq!(contents[0]["parts"][0]["text"], "Example question");
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[1]["parts"][0]["text"], "Example answer");
+        assert!(contents[2..].iter().any(|content| content.to_string().contains("Real question")));
+    }
+
+    #[tokio::test]
+    async fn stream_error_events_use_the_configured_provider_error_mappings() {
+        let deepseek = rejecting_deepseek().await;
+        let mut config = test_support::live_config(&deepseek, "http://127.0.0.1:9");
+        config.errors.provider_errors.insert(
+            "invalid_request_error".to_string(),
+            crate::config::ProviderErrorMapping {
+                status: 422,
+                type_: "rejected_upstream".to_string(),
+            },
+        );
+        let state = test_support::state(config);
+
+        let response = chat_with_headers(
+            &state,
+            test_support::token_headers(),
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+        let events = test_support::sse_events(response).await;
+
+        let (_, error) = events.iter().find(|(name, _)| name == "error").unwrap();
+        assert_eq!(error["status"], 422);
+        assert_eq!(error["error"]["type"], "rejected_upstream");
+    }
+
+    fn draft(text: &str, input_tokens: u32) -> Result<AnswerResponse> {
+        Ok(AnswerResponse {
+            text: text.to_string(),
+            usage: AnswerUsage {
+                input_tokens,
+                output_tokens: 2,
+                ..Default::default()
+            },
+            provider: AnswerProvider::Gemini,
+            parts: Vec::new(),
+            alternatives: Vec::new(),
+            citations: Vec::new(),
+            finish_reason: None,
+            retries: 0,
+            raw: serde_json::Value::Null,
+        })
+    }
+
+    #[test]
+    fn every_finished_draft_is_billed_but_blank_ones_are_not_kept() {
+        let mut drafts = PipelinedDrafts::default();
+
+        drafts.record(draft("First draft.", 10));
+        drafts.record(draft("  ", 20));
+        drafts.record(Err(ApiError::Internal { message: "boom".to_string() }));
+
+        assert_eq!(drafts.completed, 2);
+        assert_eq!(drafts.usage.input_tokens, 30);
+        assert_eq!(drafts.usage.output_tokens, 4);
+        assert_eq!(drafts.latest.as_deref(), Some("First draft."));
+    }
+
+    /// Streams a pipelined request in mock mode and returns its `done` event.
+    async fn pipelined_done_event(config: Config) -> serde_json::Value {
+        let state = test_support::state(config);
+        let response = chat(
+            &state,
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+                "pipelined_answer": true
+            }),
+        )
+        .await;
+        let events = test_support::sse_events(response).await;
+        events.into_iter().find(|(name, _)| name == "done").unwrap().1
+    }
+
+    #[tokio::test]
+    async fn pipelined_drafts_are_counted_in_the_done_event() {
+        let mut config = test_support::mock_config();
+        config.pipeline.pipelined_draft_interval_chars = Some(10);
+
+        let done = pipelined_done_event(config).await;
+
+        assert!(done["pipelined_drafts"].as_u64().unwrap() >= 1, "{}", done);
+    }
+
+    #[tokio::test]
+    async fn no_drafts_are_made_when_the_responder_may_be_skipped() {
+        let mut config = test_support::mock_config();
+        config.pipeline.pipelined_draft_interval_chars = Some(10);
+        config.pipeline.short_circuit_if_reasoning_answers = true;
+
+        let done = pipelined_done_event(config).await;
+
+        assert!(done.get("pipelined_drafts").is_none(), "{}", done);
+    }
+
+    /// Streams a mock request and returns the blocks of its `content` events.
+    async fn streamed_blocks(config: Config, body: serde_json::Value) -> Vec<serde_json::Value> {
+        let state = test_support::state(config);
+        let events = test_support::sse_events(chat(&state, body).await).await;
+        events
+            .into_iter()
+            .filter(|(name, _)| name == "content")
+            .flat_map(|(_, event)| event["content"].as_array().unwrap().clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn interleaved_streams_send_drafts_before_reasoning_ends() {
+        let mut config = test_support::mock_config();
+        config.mock.chunk_delay_ms = 5;
+        config.pipeline.pipelined_draft_interval_chars = Some(10);
+
+        let blocks = streamed_blocks(
+            config,
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+                "pipelined_answer": true,
+                "interleave_policy": "interleaved"
+            }),
+        )
+        .await;
+
+        let first_answer = blocks.iter().position(|block| block["kind"] == "answer").unwrap();
+        let last_reasoning = blocks.iter().rposition(|block| block["kind"] == "reasoning").unwrap();
+        assert!(first_answer < last_reasoning, "{:?}", blocks);
+        assert_eq!(blocks[first_answer]["type"], "draft");
+        assert!(blocks.iter().all(|block| block.get("kind").is_some()), "{:?}", blocks);
+    }
+
+    #[tokio::test]
+    async fn reasoning_first_streams_are_untagged() {
+        let blocks = streamed_blocks(
+            test_support::mock_config(),
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true
+            }),
+        )
+        .await;
+
+        assert!(!blocks.is_empty());
+        assert!(blocks.iter().all(|block| block.get("kind").is_none()), "{:?}", blocks);
+    }
+
+    #[tokio::test]
+    async fn stream_events_use_the_configured_names() {
+        let mut config = test_support::mock_config();
+        config.sse_event_names = HashMap::from([
+            ("start".to_string(), "begin".to_string()),
+            ("content".to_string(), "message".to_string()),
+            ("done".to_string(), "end".to_string()),
+        ]);
+        let state = test_support::state(config);
+
+        let response = chat(
+            &state,
+            serde_json::json!({"messages": [{"role": "user", "content": "hi"}], "stream": true}),
+        )
+        .await;
+        let events = test_support::sse_events(response).await;
+
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names.first(), Some(&"begin"));
+        assert_eq!(names.last(), Some(&"end"));
+        assert!(names.contains(&"message") && names.contains(&"usage"));
+        assert!(!names.iter().any(|name| ["start", "content", "done"].contains(name)));
+        let (_, content) = events.iter().find(|(name, _)| name == "message").unwrap();
+        assert_eq!(content["type"], "content");
+    }
+
+    #[tokio::test]
+    async fn streaming_requests_with_incompatible_options_are_rejected() {
+        let state = test_support::state(test_support::mock_config());
+
+        let response = chat(
+            &state,
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+                "gemini_config": {"n": 2}
+            }),
+        )
+        .await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = test_support::json_body(response).await;
+        assert!(body["error"]["message"].as_str().unwrap().contains("gemini_config.n"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Server-wide limit on concurrent chat requests.
+//!
+//! Requests beyond `max_concurrent_requests` wait in a queue of at most
+//! `max_queue_depth` for up to `queue_timeout_ms`, and are rejected with
+//! 503 and `Retry-After` if no slot frees up in time. A queue depth of 0
+//! rejects them immediately.
+
+use crate::error::{ApiError, Result};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Seconds clients are asked to wait when the server is at capacity.
+const OVERLOADED_RETRY_AFTER_SECS: u64 = 1;
+
+/// Concurrency limiter and request queue.
+#[derive(Debug)]
+pub struct RequestLimiter {
+    /// Request slots, or None when concurrency is unlimited.
+    slots: Option<Arc<Semaphore>>,
+    max_queue_depth: usize,
+    queue_timeout: Duration,
+    queued: Arc<AtomicUsize>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// A held request slot, released when dropped.
+///
+/// Streaming requests move the permit into their streaming task, so the
+/// slot is held for the stream's duration.
+#[derive(Debug)]
+pub struct RequestPermit {
+    _slot: Option<OwnedSemaphorePermit>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for RequestPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A claimed queue position, released when dropped, including when the
+/// waiting request is abandoned by a disconnecting client.
+struct QueuePosition(Arc<AtomicUsize>);
+
+impl Drop for QueuePosition {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RequestLimiter {
+    /// Creates a limiter.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_concurrent` - Maximum requests processed at once, or None for no limit
+    /// * `max_queue_depth` - Maximum requests waiting for a slot
+    /// * `queue_timeout` - How long a queued request waits before it is rejected
+    pub fn new(max_concurrent: Option<usize>, max_queue_depth: usize, queue_timeout: Duration) -> Self {
+        Self {
+            slots: max_concurrent.map(|limit| Arc::new(Semaphore::new(limit))),
+            max_queue_depth,
+            queue_timeout,
+            queued: Arc::new(AtomicUsize::new(0)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Reserves a slot, waiting in the queue if the server is at capacity.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<RequestPermit>` - The permit to hold for the request's lifetime
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::Overloaded` if the queue is full or the wait times out
+    pub async fn acquire(&self) -> Result<RequestPermit> {
+        let slot = match &self.slots {
+            Some(slots) => Some(match slots.clone().try_acquire_owned() {
+                Ok(slot) => slot,
+                Err(_) => self.wait(slots.clone()).await?,
+            }),
+            None => None,
+        };
+
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(RequestPermit {
+            _slot: slot,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Waits in the queue for a free slot.
+    async fn wait(&self, slots: Arc<Semaphore>) -> Result<OwnedSemaphorePermit> {
+        let claimed = self
+            .queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                (queued < self.max_queue_depth).then_some(queued + 1)
+            });
+        if claimed.is_err() {
+            return Err(overloaded());
+        }
+        let _position = QueuePosition(self.queued.clone());
+
+        match tokio::time::timeout(self.queue_timeout, slots.acquire_owned()).await {
+            Ok(Ok(slot)) => Ok(slot),
+            _ => Err(overloaded()),
+        }
+    }
+
+    /// Returns the number of requests currently being processed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+fn overloaded() -> ApiError {
+    ApiError::Overloaded {
+        retry_after_secs: OVERLOADED_RETRY_AFTER_SECS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    #[tokio::test]
+    async fn second_request_over_the_limit_gets_503_with_retry_after() {
+        let limiter = RequestLimiter::new(Some(1), 0, Duration::from_millis(10));
+
+        let _first = limiter.acquire().await.unwrap();
+        let second = limiter.acquire().await.unwrap_err().into_response();
+
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(second.headers()["retry-after"], "1");
+        assert_eq!(limiter.in_flight(), 1);
+    }
+
+    #[tokio::test]
+    async fn queued_request_gets_503_when_no_slot_frees_in_time() {
+        let limiter = RequestLimiter::new(Some(1), 1, Duration::from_millis(10));
+
+        let _first = limiter.acquire().await.unwrap();
+        let second = limiter.acquire().await.unwrap_err();
+
+        assert_eq!(second.status_and_body(&Default::default()).0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(limiter.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn queued_request_proceeds_once_the_slot_is_released() {
+        let limiter = RequestLimiter::new(Some(1), 1, Duration::from_secs(5));
+
+        let first = limiter.acquire().await.unwrap();
+        let (second, ()) = tokio::join!(limiter.acquire(), async move { drop(first) });
+
+        assert!(second.is_ok());
+    }
+}
@@ -10,20 +10,54 @@
 //! The API requires authentication tokens for both services and
 //! supports custom configuration through a TOML config file.
 
+mod abuse;
+mod cache;
 mod clients;
+mod coalesce;
 mod config;
 mod error;
 mod handlers;
+mod limiter;
 mod models;
+mod moderation;
+mod replay;
+mod self_test;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod usage;
 
-use crate::{config::Config, handlers::AppState};
-use axum::routing::{post, Router};
-use std::{net::SocketAddr, sync::Arc};
+use crate::{
+    abuse::AbuseMonitor,
+    cache::ReasoningCache,
+    config::{Config, CorsConfig},
+    error::ApiError,
+    handlers::AppState,
+    limiter::RequestLimiter,
+    moderation::ModerationFilter,
+    replay::ReplayRegistry,
+    usage::UsageStats,
+};
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post, Router},
+};
+use std::{
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
 use tower_http::{
-    cors::{Any, CorsLayer},
+    compression::CompressionLayer,
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer, ExposeHeaders},
+    limit::RequestBodyLimitLayer,
     trace::TraceLayer,
 };
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 /// Application entry point.
 ///
@@ -37,43 +71,78 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 /// # Errors
 ///
 /// Returns an error if:
-/// - Logging setup fails
+/// - Logging or OTLP export setup fails
+/// - A pinned certificate fingerprint is invalid
+/// - The self-test is enabled and fails
+/// - A moderation pattern is not a valid regex
 /// - Server address binding fails
 /// - Server encounters a fatal error while running
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "deepclaude=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    // Load configuration
-    let config = Config::load().unwrap_or_else(|_| {
+    // Load configuration, which decides where spans are exported
+    let loaded = Config::load();
+    let config = loaded.as_ref().cloned().unwrap_or_default();
+
+    // Initialize logging and tracing
+    telemetry::init(&config.telemetry)?;
+    if loaded.is_err() {
         tracing::warn!("Failed to load config.toml, using default configuration");
-        Config::default()
-    });
+    }
+
+    // Reject malformed settings before anything connects upstream
+    config.validate()?;
+
+    // Build provider HTTP clients, pinning certificates if configured
+    let http = clients::pinning::ProviderHttp::from_config(&config)?;
+    // Fail fast on a misconfigured instance if requested
+    let self_test_requested = std::env::args().any(|arg| arg == self_test::SELF_TEST_FLAG);
+    if self_test_requested || config.server.self_test_on_start {
+        self_test::run(&config, http.clone()).await?;
+    }
+
+    // Compile moderation rules up front so a bad pattern fails at startup
+    let moderation = ModerationFilter::new(&config.moderation)?;
+
+    // Open the usage sink up front so an unwritable path fails at startup
+    let usage_sink = usage::from_config(&config.usage_log)?;
 
     // Create application state
     // Clone config for AppState
     let config_clone = config.clone();
-    let state = Arc::new(AppState { config: config_clone });
-
-    // Set up CORS
-    let cors = CorsLayer::new()
-        .allow_methods(Any)
-        .allow_headers(Any)
-        .allow_origin(Any);
+    let shutdown = CancellationToken::new();
+    let state = Arc::new(AppState {
+        config: RwLock::new(Arc::new(config_clone)),
+        shutdown: shutdown.clone(),
+        request_limiter: RequestLimiter::new(
+            config.server.max_concurrent_requests,
+            config.server.max_queue_depth,
+            Duration::from_millis(config.server.queue_timeout_ms),
+        ),
+        reasoning_cache: NonZeroUsize::new(config.pipeline.reasoning_cache_size).map(|size| {
+            ReasoningCache::new(
+                size,
+                config.pipeline.reasoning_cache_ttl_secs.map(Duration::from_secs),
+            )
+        }),
+        http,
+        active_streams: Default::default(),
+        moderation,
+        replay: config.resume.enabled.then(|| {
+            ReplayRegistry::new(
+                Duration::from_secs(config.resume.ttl_secs),
+                config.resume.max_streams,
+                config.resume.max_events_per_stream,
+            )
+        }),
+        usage_sink,
+        usage_stats: UsageStats::default(),
+        abuse_monitor: config
+            .abuse
+            .enabled
+            .then(|| AbuseMonitor::new(Duration::from_secs(config.abuse.window_secs))),
+    });
 
-    // Build router
-    let app = Router::new()
-        .route("/", post(handlers::handle_chat))
-        .layer(TraceLayer::new_for_http())
-        .layer(cors)
-        .with_state(state);
+    let app = app(state, &config);
 
     // Get host and port from config
     let addr: SocketAddr = format!("{}:{}", config.server.host, config.server.port)
@@ -83,11 +152,267 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Starting server on {}", addr);
 
     // Start server
+    let grace = Duration::from_secs(config.server.shutdown_grace_secs);
     axum::serve(
         tokio::net::TcpListener::bind(&addr).await?,
-        app.into_make_service(),
+        app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal(shutdown, grace))
     .await?;
 
+    telemetry::shutdown();
     Ok(())
 }
+
+/// Builds the application router with its middleware.
+///
+/// # Arguments
+///
+/// * `state` - Application state shared by the handlers
+/// * `config` - Configuration selecting CORS, body limit, and compression
+///
+/// # Returns
+///
+/// * `Router` - The router, ready to serve
+fn app(state: Arc<AppState>, config: &Config) -> Router {
+    // Set up CORS; the layer also answers preflight OPTIONS requests
+    let cors = cors_layer(&config.cors);
+
+    // Build router. Axum's own 2 MB default is replaced by the configured
+    // limit, and bare 413 rejections are rewritten into our error format.
+    let max_body_bytes = config.server.max_body_bytes;
+    let api = Router::new()
+        .route("/", post(handlers::handle_chat))
+        .route("/v1/models", get(handlers::list_models))
+        .route("/v1/batch", post(handlers::handle_batch))
+        .route("/v1/cost", post(handlers::calculate_cost))
+        .route("/v1/tokenize", post(handlers::tokenize))
+        .route("/v1/cancel/{request_id}", post(handlers::cancel_stream))
+        .route("/debug/fingerprint", post(handlers::debug_fingerprint))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::require_service_key,
+        ));
+    let app = Router::new()
+        .merge(api)
+        .route("/admin/reload", post(handlers::reload_pricing))
+        .route("/admin/usage", get(handlers::usage_summary))
+        .route("/admin/load", get(handlers::load_status))
+        .route("/v1/debug/prompt", post(handlers::debug_prompt))
+        .layer(middleware::from_fn_with_state(state.clone(), handlers::map_provider_errors))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
+        .layer(middleware::map_response(move |response: Response| async move {
+            payload_too_large_error(response, max_body_bytes)
+        }))
+        .layer(middleware::from_fn_with_state(state.clone(), handlers::track_client))
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::request_span))
+        .layer(cors)
+        .with_state(state);
+
+    // The default predicate skips `text/event-stream`, so SSE framing reaches
+    // clients uncompressed and events are flushed as they are produced
+    if config.server.compression_enabled {
+        app.layer(CompressionLayer::new())
+    } else {
+        app
+    }
+}
+
+/// Builds the CORS layer from configuration.
+///
+/// Lists containing `"*"` allow any value. Entries that are not valid
+/// origins, methods, or header names are logged and skipped.
+///
+/// # Arguments
+///
+/// * `config` - The CORS configuration
+///
+/// # Returns
+///
+/// * `CorsLayer` - The configured layer
+fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    fn is_wildcard(values: &[String]) -> bool {
+        values.iter().any(|value| value == "*")
+    }
+
+    fn parse_all<T: std::str::FromStr>(kind: &str, values: &[String]) -> Vec<T> {
+        values
+            .iter()
+            .filter_map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| tracing::warn!("Ignoring invalid CORS {}: {}", kind, value))
+                    .ok()
+            })
+            .collect()
+    }
+
+    let origins = if is_wildcard(&config.allowed_origins) {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(parse_all::<HeaderValue>("origin", &config.allowed_origins))
+    };
+
+    let methods = if is_wildcard(&config.allowed_methods) {
+        AllowMethods::from(Any)
+    } else {
+        AllowMethods::list(parse_all::<Method>("method", &config.allowed_methods))
+    };
+
+    let headers = if is_wildcard(&config.allowed_headers) {
+        AllowHeaders::from(Any)
+    } else {
+        AllowHeaders::list(parse_all::<HeaderName>("header", &config.allowed_headers))
+    };
+
+    let expose = if is_wildcard(&config.expose_headers) {
+        ExposeHeaders::from(Any)
+    } else {
+        ExposeHeaders::list(parse_all::<HeaderName>("header", &config.expose_headers))
+    };
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(expose)
+}
+
+/// Rewrites a body limit rejection into a structured `PayloadTooLarge` error.
+///
+/// Both the body limit layer and the JSON extractor answer oversized bodies
+/// with a plain-text 413; other responses pass through unchanged.
+///
+/// # Arguments
+///
+/// * `response` - The response produced by the inner service
+/// * `limit` - The configured maximum body size in bytes
+///
+/// # Returns
+///
+/// * `Response` - The original response, or the JSON error for a 413
+fn payload_too_large_error(response: Response, limit: usize) -> Response {
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::PayloadTooLarge { limit }.into_response()
+    } else {
+        response
+    }
+}
+
+/// Waits for a shutdown signal (SIGINT or SIGTERM).
+///
+/// Once a signal arrives the server stops accepting new connections.
+/// In-flight streams are given `grace` to finish before the shutdown
+/// token is cancelled, at which point they emit a 503 error event and stop.
+///
+/// # Arguments
+///
+/// * `shutdown` - Token observed by streaming tasks
+/// * `grace` - How long in-flight streams may continue after the signal
+async fn shutdown_signal(shutdown: CancellationToken, grace: Duration) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutdown signal received, draining in-flight requests for {:?}", grace);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        tracing::warn!("Shutdown grace period expired, aborting remaining streams");
+        shutdown.cancel();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use serde_json::json;
+
+    /// Serves the full application under `config` and returns its base URL.
+    async fn serve_app(config: Config) -> String {
+        test_support::serve(app(test_support::state(config.clone()), &config)).await
+    }
+
+    #[tokio::test]
+    async fn oversized_body_gets_a_structured_413() {
+        let mut config = test_support::mock_config();
+        config.server.max_body_bytes = 1024;
+        let url = serve_app(config).await;
+
+        let body = json!({"messages": [{"role": "user", "content": "x".repeat(4096)}]});
+        let response = reqwest::Client::new().post(&url).json(&body).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["type"], "payload_too_large");
+        assert!(error["error"]["message"].as_str().unwrap().contains("1024"));
+    }
+
+    #[tokio::test]
+    async fn too_many_messages_are_rejected_before_dispatch() {
+        let mut config = test_support::mock_config();
+        config.server.max_messages = 2;
+        let url = serve_app(config).await;
+
+        let message = json!({"role": "user", "content": "hi"});
+        let body = json!({"messages": [message.clone(), {"role": "assistant", "content": "hello"}, message]});
+        let response = reqwest::Client::new().post(&url).json(&body).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert!(error["error"]["message"].as_str().unwrap().contains("Too many messages"));
+    }
+
+    #[tokio::test]
+    async fn configured_provider_error_mappings_apply_to_responses() {
+        let deepseek = test_support::serve(axum::Router::new().fallback(|| async {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                axum::Json(json!({"error": {"message": "slow down", "type": "rate_limit_exceeded"}})),
+            )
+        }))
+        .await;
+        let mut config = test_support::live_config(&deepseek, "http://127.0.0.1:9");
+        config.errors.provider_errors.insert(
+            "rate_limit_exceeded".to_string(),
+            config::ProviderErrorMapping {
+                status: 503,
+                type_: "busy".to_string(),
+            },
+        );
+        let url = serve_app(config).await;
+
+        let response = reqwest::Client::new()
+            .post(&url)
+            .headers(test_support::token_headers())
+            .json(&json!({"messages": [{"role": "user", "content": "hi"}]}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["type"], "busy");
+        assert_eq!(error["error"]["code"], "rate_limit_exceeded");
+    }
+}
@@ -3,8 +3,71 @@
 //! This module defines the structures used to represent incoming API requests,
 //! including chat messages, configuration options, and request parameters.
 
+use crate::{
+    config::{AnswerProvider, ReasoningToAnswer, ResponseCharLimits},
+    error::{ApiError, Result},
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+/// Image MIME types accepted in multimodal message content.
+pub const SUPPORTED_IMAGE_MIME_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/webp",
+    "image/heic",
+    "image/heif",
+];
+
+/// Maximum decoded size of a single inline image (20 MiB).
+pub const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Returns true if a request sets a given non-streamable option.
+type StreamingCheck = fn(&ApiRequest) -> bool;
+
+/// Request options that can't be honored when streaming, each with a check
+/// for whether a request sets it. Add an entry here for any new
+/// non-streaming-only option.
+const STREAMING_INCOMPATIBLE: &[(&str, StreamingCheck)] = &[
+    ("pipeline_order \"answer_then_reason\"", |request| {
+        request.pipeline_order == PipelineOrder::AnswerThenReason
+    }),
+    ("gemini_config.n greater than 1", |request| {
+        request.gemini_config.n.is_some_and(|n| n > 1)
+    }),
+    ("gemini_config.extra_params candidate count greater than 1", |request| {
+        request
+            .gemini_config
+            .extra_params
+            .as_ref()
+            .and_then(|params| params.get("candidateCount").or_else(|| params.get("candidate_count")))
+            .and_then(serde_json::Value::as_u64)
+            .is_some_and(|n| n > 1)
+    }),
+];
+
+/// Rejects text that can't be forwarded upstream.
+///
+/// JSON parsing already rejects invalid UTF-8 and lone surrogates; null
+/// characters parse fine but are refused by providers and truncate text
+/// in C-based tooling.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` naming `location` if the text contains a
+/// null character
+fn check_encoding(text: &str, location: &str) -> Result<()> {
+    if text.contains('\0') {
+        return Err(ApiError::BadRequest {
+            message: format!("{} contains a null character", location),
+        });
+    }
+    Ok(())
+}
 
 /// Primary request structure for chat API endpoints.
 ///
@@ -12,11 +75,98 @@ use std::collections::HashMap;
 /// system prompts, and configuration options for both DeepSeek and Google APIs.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ApiRequest {
+    /// Whether to stream the response. The `stream` query parameter takes
+    /// precedence over this flag; when neither is given, an unambiguous
+    /// `Accept` header decides.
     #[serde(default)]
-    pub stream: bool,
+    pub stream: Option<bool>,
     
     #[serde(default)]
     pub verbose: bool,
+
+    /// Report the latency of each upstream call in the usage block,
+    /// without the raw upstream bodies `verbose` adds.
+    #[serde(default)]
+    pub include_timing: bool,
+
+    /// Include the thinking block in the returned `assistant_message`.
+    #[serde(default)]
+    pub history_includes_thinking: bool,
+
+    /// Append a random nonce to the system prompt so provider prompt caches miss.
+    ///
+    /// Without it the reasoner's prompt is never rewritten per request: the
+    /// system prompt, then the configured few-shot examples, then the
+    /// conversation in order. Consecutive turns of a conversation therefore
+    /// share a prefix that DeepSeek's context cache serves at the cache-hit
+    /// price.
+    #[serde(default)]
+    pub bust_cache: bool,
+
+    /// Send only `content`, `usage`, and `error` events when streaming,
+    /// dropping `start`, `done`, and the other control events. Without
+    /// `done`, clients detect the end of the response by the stream
+    /// closing, and without `start` they don't learn the request ID used
+    /// to cancel the stream. Defaults to `server.minimal_events`.
+    #[serde(default)]
+    pub minimal_events: Option<bool>,
+
+    /// When streaming, send the whole answer as one `final_message` event
+    /// right before `done`, in addition to the deltas. The answer is cut
+    /// to the same size cap as the deltas. Not sent with `minimal_events`.
+    #[serde(default)]
+    pub stream_final_message: bool,
+
+    /// Experimental. When streaming, ask the answer provider for draft
+    /// answers from the partial reasoning while DeepSeek is still thinking,
+    /// every `pipeline.pipelined_draft_interval_chars` of new reasoning.
+    /// The final answer call sees the latest draft and only has to revise
+    /// it. Every draft is a full, billed answer call, so this trades extra
+    /// cost for an answer that is shaped as the reasoning arrives. Drafts
+    /// are streamed only under the `interleaved` policy. A draft still
+    /// running when the reasoning ends is awaited briefly, and charged at
+    /// its estimated input if abandoned.
+    /// Ignored for non-streaming requests, and when
+    /// `pipeline.short_circuit_if_reasoning_answers` may skip the responder.
+    #[serde(default)]
+    pub pipelined_answer: bool,
+
+    /// Run only the reasoner and return its reasoning, skipping the answer
+    /// provider. No answer provider token is needed.
+    #[serde(default)]
+    pub reasoning_only: bool,
+
+    /// How reasoning and answer frames are ordered when streaming.
+    #[serde(default)]
+    pub interleave_policy: InterleavePolicy,
+
+    /// Order in which the reasoner and responder run. Non-streaming only.
+    #[serde(default)]
+    pub pipeline_order: PipelineOrder,
+
+    /// With `answer_then_reason`, run a second answer pass that revises
+    /// the draft using the reasoner's critique.
+    #[serde(default)]
+    pub revise_after_critique: bool,
+
+    /// Approximate answer length to ask the responder for, in words.
+    #[serde(default)]
+    pub target_answer_words: Option<u32>,
+
+    /// Estimate token usage and cost without calling either provider.
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// How much reasoning to forward to the answer model. Defaults to the
+    /// server's `pipeline.reasoning_to_answer`.
+    #[serde(default)]
+    pub reasoning_to_answer: Option<ReasoningToAnswer>,
+
+    /// Caps on the reasoning and answer characters returned, e.g.
+    /// `{"answer": 4000}`. Combined with the server's
+    /// `pipeline.max_response_chars`, the lower cap applies.
+    #[serde(default)]
+    pub max_response_chars: ResponseCharLimits,
     
     pub system: Option<String>,
     pub messages: Vec<Message>,
@@ -24,10 +174,113 @@ pub struct ApiRequest {
     #[serde(default)]
     pub deepseek_config: ApiConfig,
     
+    #[serde(default)]
+    pub gemini_config: ApiConfig,
+
     #[serde(default)]
     pub anthropic_config: ApiConfig,
 }
 
+/// Query parameters of the chat endpoint.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ChatQuery {
+    /// Forces streaming on or off, overriding the body's `stream` flag and
+    /// the `Accept` header.
+    pub stream: Option<bool>,
+}
+
+/// Request body for the batch endpoint.
+///
+/// Each item is an independent, non-streaming chat request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchRequest {
+    pub requests: Vec<ApiRequest>,
+}
+
+/// Request body for the cost endpoint: saved token counts to re-price
+/// with the server's current pricing.
+///
+/// Counts are signed so negative values can be rejected with a clear
+/// message rather than a deserialization failure.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CostRequest {
+    pub deepseek: TokenCounts,
+    pub answer: TokenCounts,
+    /// Provider that served the answer. Defaults to the server's configured provider.
+    pub answer_provider: Option<AnswerProvider>,
+}
+
+/// Request body for the tokenize endpoint.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenizeRequest {
+    pub text: String,
+    /// Provider whose tokenization to approximate.
+    pub provider: TokenizeProvider,
+    /// Model to tokenize for. Defaults to the provider's default model.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Also return the character range of each token.
+    #[serde(default)]
+    pub boundaries: bool,
+}
+
+/// Provider whose tokenization a tokenize request approximates.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenizeProvider {
+    DeepSeek,
+    Gemini,
+}
+
+impl TokenizeProvider {
+    /// Returns the model used when the request names none.
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            TokenizeProvider::DeepSeek => crate::clients::deepseek::DEFAULT_MODEL,
+            TokenizeProvider::Gemini => crate::clients::gemini::DEFAULT_MODEL,
+        }
+    }
+}
+
+/// Token counts reported by one provider for one call.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TokenCounts {
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// Reasoning tokens; only meaningful for DeepSeek.
+    pub reasoning_tokens: i64,
+    pub cached_input_tokens: i64,
+    /// Input tokens written to the prompt cache; only meaningful for Anthropic.
+    pub cache_creation_input_tokens: i64,
+    /// Model that served the call, for providers priced per model.
+    pub model: Option<String>,
+}
+
+impl TokenCounts {
+    /// Converts a count to `u32`, rejecting negative or oversized values.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - Field path used in the error message
+    /// * `value` - The count to convert
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if the count is negative or too large
+    pub fn checked(field: &str, value: i64) -> Result<u32> {
+        u32::try_from(value).map_err(|_| ApiError::BadRequest {
+            message: format!(
+                "{} must be between 0 and {}, got {}",
+                field,
+                u32::MAX,
+                value
+            ),
+        })
+    }
+}
+
 /// A single message in a chat conversation.
 ///
 /// Represents one message in the conversation history, including
@@ -35,7 +288,65 @@ pub struct ApiRequest {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    pub content: MessageContent,
+}
+
+/// Content of a chat message.
+///
+/// Either a plain string (the common case) or a list of typed parts,
+/// which allows mixing text with images for multimodal models.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+/// A single part of a multimodal message.
+///
+/// Images can be supplied either inline as base64 data or by URL.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    Image {
+        mime_type: String,
+        data: String,
+    },
+    ImageUrl {
+        mime_type: String,
+        url: String,
+    },
+}
+
+/// Ordering of reasoning and answer frames in a streaming response.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InterleavePolicy {
+    /// Reasoning is streamed in full, between the thinking markers, before
+    /// any answer frame.
+    #[default]
+    ReasoningFirst,
+    /// Frames are sent as they are produced and tagged with their `kind`
+    /// instead of being wrapped in thinking markers. With
+    /// `pipelined_answer`, each draft finished while DeepSeek is still
+    /// reasoning is sent as a `draft` block, so answer frames arrive before
+    /// the reasoning is complete. The final answer is still conditioned on
+    /// the complete reasoning and follows it.
+    Interleaved,
+}
+
+/// Order in which the pipeline runs the reasoner and the responder.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineOrder {
+    /// DeepSeek reasons first and the responder answers using its reasoning.
+    #[default]
+    ReasonThenAnswer,
+    /// The responder drafts an answer and DeepSeek critiques the draft.
+    AnswerThenReason,
 }
 
 /// Possible roles for a message in a chat conversation.
@@ -61,6 +372,240 @@ pub struct ApiConfig {
     
     #[serde(default)]
     pub body: serde_json::Value,
+
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+
+    /// Strip surrounding markdown code fences before JSON validation (default: true).
+    #[serde(default)]
+    pub strip_json_fences: Option<bool>,
+
+    /// Sampling seed for reproducible outputs. Sent to Gemini as the
+    /// generation config seed. Passed through to DeepSeek, which currently
+    /// ignores it. Not sent to Anthropic, whose API has no seed parameter.
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Gemini safety thresholds by harm category, e.g.
+    /// `{"HARM_CATEGORY_HARASSMENT": "BLOCK_ONLY_HIGH"}`. Overrides the
+    /// server's `[gemini.safety_settings]` per category. Ignored by other providers.
+    #[serde(default)]
+    pub safety_settings: HashMap<String, String>,
+
+    /// How much the reasoner should think: `"low"`, `"medium"`, or
+    /// `"high"`. Sent to DeepSeek, which applies it where the model
+    /// supports it and returns an error otherwise. Ignored by other providers.
+    #[serde(default)]
+    pub reasoning_effort: Option<ReasoningEffort>,
+
+    /// Number of answer candidates to request from Gemini. Extra candidates
+    /// are returned in the response's `candidates`. Non-streaming only:
+    /// streaming requests asking for more than one are rejected. Ignored
+    /// by other providers.
+    #[serde(default)]
+    pub n: Option<u32>,
+
+    /// Provider parameters this crate has no first-class support for,
+    /// as a JSON object. DeepSeek receives every key as-is in the request
+    /// body, overriding `body`. Gemini's request is typed, so only its
+    /// generation settings (`temperature`, `topP`, `topK`,
+    /// `maxOutputTokens`, `candidateCount`, `seed`, `responseMimeType`,
+    /// `responseSchema`, in camelCase or snake_case) are applied; other
+    /// keys are dropped with a warning. Ignored by Anthropic.
+    #[serde(default)]
+    pub extra_params: Option<serde_json::Value>,
+}
+
+/// Reasoning depth requested from DeepSeek.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+/// Output format requested from the answer model.
+///
+/// `json_object` forces the model to emit valid JSON, and `json_schema`
+/// additionally constrains the output to the provided schema.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema {
+        schema: serde_json::Value,
+    },
+}
+
+impl ApiConfig {
+    /// Returns true if the config requests JSON output.
+    pub fn json_mode(&self) -> bool {
+        matches!(
+            self.response_format,
+            Some(ResponseFormat::JsonObject | ResponseFormat::JsonSchema { .. })
+        )
+    }
+
+    /// Returns true if markdown fences should be stripped from JSON output.
+    pub fn strip_json_fences(&self) -> bool {
+        self.strip_json_fences.unwrap_or(true)
+    }
+
+    /// Returns the requested model, from `body.model`.
+    pub fn model(&self) -> Option<&str> {
+        self.body.get("model").and_then(|m| m.as_str())
+    }
+
+    /// Returns the requested output token limit, from `body.max_tokens`.
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.body
+            .get("max_tokens")
+            .and_then(|v| v.as_u64())
+            .map(|v| v.min(u32::MAX as u64) as u32)
+    }
+
+    /// Returns the requested sampling temperature, from `body.temperature`.
+    ///
+    /// None means the provider default applies, which is distinct from an
+    /// explicit `0.0`.
+    pub fn temperature(&self) -> Option<f32> {
+        self.body.get("temperature").and_then(|v| v.as_f64()).map(|v| v as f32)
+    }
+
+    /// Returns the requested nucleus sampling cutoff, from `body.top_p`.
+    pub fn top_p(&self) -> Option<f32> {
+        self.body.get("top_p").and_then(|v| v.as_f64()).map(|v| v as f32)
+    }
+
+    /// Returns the requested top-k sampling cutoff, from `body.top_k`.
+    pub fn top_k(&self) -> Option<u32> {
+        self.body
+            .get("top_k")
+            .and_then(|v| v.as_u64())
+            .map(|v| v.min(u32::MAX as u64) as u32)
+    }
+
+    /// Returns the `extra_params` object, if any.
+    pub fn extra_params(&self) -> Option<&serde_json::Map<String, serde_json::Value>> {
+        self.extra_params.as_ref().and_then(|params| params.as_object())
+    }
+
+    /// Sets the output token limit in `body.max_tokens`.
+    pub fn set_max_tokens(&mut self, max_tokens: u32) {
+        if !self.body.is_object() {
+            self.body = serde_json::json!({});
+        }
+        self.body["max_tokens"] = serde_json::json!(max_tokens);
+    }
+}
+
+impl MessageContent {
+    /// Returns the textual content, skipping any non-text parts.
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Returns the content as text, describing any images with placeholders.
+    ///
+    /// Used for text-only models such as DeepSeek, which cannot see images
+    /// but should still know that the user attached one.
+    pub fn text_with_placeholders(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => text.clone(),
+                    ContentPart::Image { mime_type, .. } => format!("[image: {}]", mime_type),
+                    ContentPart::ImageUrl { url, .. } => format!("[image: {}]", url),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Appends text after the existing content, as a new part for
+    /// multimodal content.
+    pub fn append_text(&mut self, text: &str) {
+        match self {
+            MessageContent::Text(existing) => {
+                existing.push_str("\n\n");
+                existing.push_str(text);
+            }
+            MessageContent::Parts(parts) => parts.push(ContentPart::Text {
+                text: text.to_string(),
+            }),
+        }
+    }
+
+    /// Validates image parts for supported MIME types and size.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if an image has an unsupported MIME type
+    /// or its decoded size exceeds `MAX_IMAGE_BYTES`.
+    pub fn validate(&self) -> Result<()> {
+        let MessageContent::Parts(parts) = self else {
+            return Ok(());
+        };
+
+        for part in parts {
+            let (mime_type, data) = match part {
+                ContentPart::Text { .. } => continue,
+                ContentPart::Image { mime_type, data } => (mime_type, Some(data)),
+                ContentPart::ImageUrl { mime_type, .. } => (mime_type, None),
+            };
+
+            if !SUPPORTED_IMAGE_MIME_TYPES.contains(&mime_type.as_str()) {
+                return Err(ApiError::BadRequest {
+                    message: format!("Unsupported image mime type: {}", mime_type),
+                });
+            }
+
+            // Base64 encodes 3 bytes in 4 characters
+            if let Some(data) = data {
+                let decoded_len = data.trim_end_matches('=').len() * 3 / 4;
+                if decoded_len > MAX_IMAGE_BYTES {
+                    return Err(ApiError::BadRequest {
+                        message: format!(
+                            "Image exceeds maximum size of {} bytes",
+                            MAX_IMAGE_BYTES
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl Message {
+    /// Returns a copy of this message with images replaced by text placeholders.
+    pub fn to_text_only(&self) -> Self {
+        Self {
+            role: self.role.clone(),
+            content: MessageContent::Text(self.content.text_with_placeholders()),
+        }
+    }
 }
 
 impl ApiRequest {
@@ -79,10 +624,171 @@ impl ApiRequest {
         !(self.system.is_some() && system_in_messages)
     }
 
+    /// Validates the role ordering of the conversation.
+    ///
+    /// Enforces at most one system message, only in leading position,
+    /// no two consecutive user or assistant messages, and a user message last.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` describing the first violation found.
+    pub fn validate_messages(&self) -> Result<()> {
+        let bad_request = |message: String| Err(ApiError::BadRequest { message });
+
+        let conversation = match self.messages.split_first() {
+            Some((first, rest)) if first.role == Role::System => rest,
+            _ => &self.messages[..],
+        };
+
+        let mut previous: Option<&Role> = None;
+        for (offset, msg) in conversation.iter().enumerate() {
+            let index = offset + self.messages.len() - conversation.len();
+            if msg.role == Role::System {
+                return bad_request(format!(
+                    "messages[{}]: system message must be the first message",
+                    index
+                ));
+            }
+            if previous == Some(&msg.role) {
+                return bad_request(format!(
+                    "messages[{}]: consecutive {} messages",
+                    index,
+                    format!("{:?}", msg.role).to_lowercase()
+                ));
+            }
+            previous = Some(&msg.role);
+        }
+
+        match conversation.last() {
+            Some(msg) if msg.role == Role::User => Ok(()),
+            Some(_) => bad_request("The last message must be from the user".to_string()),
+            None => bad_request("At least one user message is required".to_string()),
+        }
+    }
+
+    /// Validates that the request does not carry more messages than allowed.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The maximum number of messages, including the system message
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if there are more than `max` messages.
+    pub fn validate_message_count(&self, max: usize) -> Result<()> {
+        if self.messages.len() > max {
+            return Err(ApiError::BadRequest {
+                message: format!(
+                    "Too many messages: {} exceeds the limit of {}",
+                    self.messages.len(),
+                    max
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Validates that no message, including the system prompt, has more
+    /// text than allowed.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The maximum characters per message, or None for no limit
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` naming the first message over `max`
+    /// characters.
+    pub fn validate_message_chars(&self, max: Option<usize>) -> Result<()> {
+        let Some(max) = max else {
+            return Ok(());
+        };
+        let texts = self
+            .system
+            .iter()
+            .map(|system| ("system".to_string(), system.chars().count()))
+            .chain(
+                self.messages
+                    .iter()
+                    .enumerate()
+                    .map(|(index, msg)| (format!("messages[{}]", index), msg.content.text().chars().count())),
+            );
+        for (field, chars) in texts {
+            if chars > max {
+                return Err(ApiError::BadRequest {
+                    message: format!("{} is too long: {} characters exceeds the limit of {}", field, chars, max),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates multimodal content and text encoding in all messages and
+    /// the system prompt.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if any message contains an invalid
+    /// image, or any text contains a null character.
+    pub fn validate_content(&self) -> Result<()> {
+        if let Some(system) = &self.system {
+            check_encoding(system, "system")?;
+        }
+        for (index, msg) in self.messages.iter().enumerate() {
+            check_encoding(&msg.content.text(), &format!("messages[{}]", index))?;
+            msg.content.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Validates that every provider's `extra_params` is a JSON object.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` naming the first config whose
+    /// `extra_params` is not an object
+    pub fn validate_extra_params(&self) -> Result<()> {
+        for (name, config) in [
+            ("deepseek_config", &self.deepseek_config),
+            ("gemini_config", &self.gemini_config),
+            ("anthropic_config", &self.anthropic_config),
+        ] {
+            if config.extra_params.as_ref().is_some_and(|params| !params.is_object()) {
+                return Err(ApiError::BadRequest {
+                    message: format!("{}.extra_params must be a JSON object", name),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates that the request sets no option that only works without
+    /// streaming, listed in `STREAMING_INCOMPATIBLE`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` naming the first such option
+    pub fn validate_streamable(&self) -> Result<()> {
+        match STREAMING_INCOMPATIBLE.iter().find(|(_, is_set)| is_set(self)) {
+            Some((option, _)) => Err(ApiError::BadRequest {
+                message: format!(
+                    "{} is not supported for streaming requests; set stream to false",
+                    option
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
     /// Returns messages with the system prompt in the correct position.
     ///
     /// Ensures the system prompt (if present) is the first message,
-    /// followed by the conversation messages in order.
+    /// followed by the conversation messages in order, starting with any
+    /// few-shot examples added by `with_few_shot_examples`. The system prompt
+    /// is taken from either location, as in `get_system_prompt`, so the
+    /// reasoner sees it as a system message even when the client sent it
+    /// inside `messages`. Nothing request-specific is added, so the result
+    /// for one turn is a prefix of the result for the next; see `bust_cache`.
     ///
     /// # Returns
     ///
@@ -91,19 +797,206 @@ impl ApiRequest {
         let mut messages = Vec::new();
 
         // Add system message first
-        if let Some(system) = &self.system {
+        if let Some(system) = self.get_system_prompt() {
             messages.push(Message {
                 role: Role::System,
-                content: system.clone(),
+                content: system.into(),
             });
         }
 
         // Add remaining messages
-        messages.extend(self.messages.iter().filter(|msg| !matches!(msg.role, Role::System)).cloned());
+        messages.extend(
+            self.messages
+                .iter()
+                .filter(|msg| !matches!(msg.role, Role::System))
+                .map(|msg| Message {
+                    role: msg.role.clone(),
+                    content: msg.content.clone(),
+                }),
+        );
 
         messages
     }
 
+    /// Returns the request config for the given answer provider.
+    pub fn answer_config(&self, provider: AnswerProvider) -> &ApiConfig {
+        match provider {
+            AnswerProvider::Gemini => &self.gemini_config,
+            AnswerProvider::Anthropic => &self.anthropic_config,
+        }
+    }
+
+    /// Returns the answer-length instruction for the responder, if a target is set.
+    pub fn answer_length_instruction(&self) -> Option<String> {
+        self.target_answer_words
+            .map(|words| format!("Limit your answer to about {} words.", words))
+    }
+
+    /// Returns the normalized byte sequence identifying this request.
+    ///
+    /// Normalization rules:
+    /// - Only fields that affect model output are included (`stream` and
+    ///   `verbose` are ignored)
+    /// - The system prompt is resolved from either location
+    /// - Message text is trimmed of surrounding whitespace
+    /// - Object keys are serialized in sorted order
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - Canonical JSON bytes for the request
+    pub fn normalized_bytes(&self) -> Vec<u8> {
+        let messages: Vec<serde_json::Value> = self
+            .messages
+            .iter()
+            .filter(|msg| !matches!(msg.role, Role::System))
+            .map(|msg| {
+                let content = match &msg.content {
+                    MessageContent::Text(text) => serde_json::json!(text.trim()),
+                    parts => serde_json::to_value(parts).unwrap_or_default(),
+                };
+                serde_json::json!({ "role": msg.role, "content": content })
+            })
+            .collect();
+
+        let normalized = serde_json::json!({
+            "system": self.get_system_prompt().map(|s| s.trim().to_string()),
+            "messages": messages,
+            "deepseek_config": self.deepseek_config,
+            "gemini_config": self.gemini_config,
+            "anthropic_config": self.anthropic_config,
+        });
+
+        // serde_json maps are ordered by key, so this output is canonical
+        serde_json::to_vec(&normalized).unwrap_or_default()
+    }
+
+    /// Computes the SHA-256 fingerprint of the normalized request.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - Lowercase hex digest of `normalized_bytes`
+    pub fn fingerprint(&self) -> String {
+        format!("{:x}", Sha256::digest(self.normalized_bytes()))
+    }
+
+    /// Returns a copy of the request with the server-side system prompt
+    /// prefix and suffix wrapped around the client's system prompt.
+    ///
+    /// The client prompt may come from either location; afterwards it lives
+    /// only in the `system` field, so `get_messages_with_system` and
+    /// `get_system_prompt` both return the wrapped prompt. The prefix comes
+    /// first and the suffix last, each separated from the client prompt by a
+    /// blank line. With no client prompt, the prefix and suffix are used alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Text placed before the client's system prompt
+    /// * `suffix` - Text placed after the client's system prompt
+    ///
+    /// # Returns
+    ///
+    /// * `ApiRequest` - The request with the wrapped system prompt
+    pub fn with_system_prompt_wrapping(mut self, prefix: Option<&str>, suffix: Option<&str>) -> Self {
+        if prefix.is_none() && suffix.is_none() {
+            return self;
+        }
+
+        let client_prompt = self.get_system_prompt();
+        self.messages.retain(|msg| !matches!(msg.role, Role::System));
+
+        let parts: Vec<&str> = [prefix, client_prompt.as_deref(), suffix]
+            .into_iter()
+            .flatten()
+            .filter(|part| !part.is_empty())
+            .collect();
+        self.system = (!parts.is_empty()).then(|| parts.join("\n\n"));
+
+        self
+    }
+
+    /// Returns the request with `default` as its system prompt if the client
+    /// sent none.
+    ///
+    /// An empty client prompt counts as sent unless `treat_empty_as_missing`
+    /// is set, in which case it is replaced. The default is placed in the
+    /// `system` field, so a later `with_system_prompt_wrapping` wraps it like
+    /// a client prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `default` - The fallback system prompt, if configured
+    /// * `treat_empty_as_missing` - Replace an empty client prompt as well
+    ///
+    /// # Returns
+    ///
+    /// * `ApiRequest` - The request with a system prompt, if one was available
+    pub fn with_default_system_prompt(mut self, default: Option<&str>, treat_empty_as_missing: bool) -> Self {
+        let Some(default) = default else {
+            return self;
+        };
+
+        let missing = match self.get_system_prompt() {
+            None => true,
+            Some(prompt) => treat_empty_as_missing && prompt.trim().is_empty(),
+        };
+        if missing {
+            self.messages.retain(|msg| !matches!(msg.role, Role::System));
+            self.system = Some(default.to_string());
+        }
+
+        self
+    }
+
+    /// Inserts example turns ahead of the client's messages.
+    ///
+    /// The examples go to the front of `messages`, so
+    /// `get_messages_with_system` places them after the system prompt and
+    /// before the conversation, and both providers receive them.
+    ///
+    /// # Arguments
+    ///
+    /// * `examples` - The configured few-shot example turns
+    ///
+    /// # Returns
+    ///
+    /// * `ApiRequest` - The request with the examples prepended
+    pub fn with_few_shot_examples(mut self, examples: &[Message]) -> Self {
+        if !examples.is_empty() {
+            self.messages.splice(0..0, examples.iter().cloned());
+        }
+        self
+    }
+
+    /// Returns a copy of the request with a random nonce appended to the system prompt.
+    ///
+    /// The nonce is an HTML-style comment so it doesn't affect the model's
+    /// behavior, but it changes the prompt prefix and forces providers to
+    /// miss their prompt caches. It is never echoed in the response content.
+    ///
+    /// # Returns
+    ///
+    /// * `ApiRequest` - The request with a nonce-bearing system prompt
+    pub fn with_cache_nonce(mut self) -> Self {
+        let nonce = format!(
+            "<!-- nonce: {:016x} -->",
+            RandomState::new().build_hasher().finish()
+        );
+
+        match self.messages.iter_mut().find(|msg| matches!(msg.role, Role::System)) {
+            Some(msg) => {
+                msg.content = format!("{}\n{}", msg.content.text(), nonce).into();
+            }
+            None => {
+                self.system = Some(match self.system.take() {
+                    Some(system) => format!("{}\n{}", system, nonce),
+                    None => nonce,
+                });
+            }
+        }
+
+        self
+    }
+
     /// Retrieves the system prompt if one is present.
     ///
     /// Checks both the root level system field and the messages array
@@ -111,13 +1004,186 @@ impl ApiRequest {
     ///
     /// # Returns
     ///
-    /// * `Option<&str>` - The system prompt if found, None otherwise
-    pub fn get_system_prompt(&self) -> Option<&str> {
-        self.system.as_deref().or_else(|| {
+    /// * `Option<String>` - The system prompt if found, None otherwise
+    pub fn get_system_prompt(&self) -> Option<String> {
+        self.system.clone().or_else(|| {
             self.messages
                 .iter()
                 .find(|msg| matches!(msg.role, Role::System))
-                .map(|msg| msg.content.as_str())
+                .map(|msg| msg.content.text())
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn message_text_is_forwarded_unchanged() {
+        let text = "a\r\nb\rc\u{1b}[0m\td";
+        let request: ApiRequest = serde_json::from_value(serde_json::json!({
+            "system": text,
+            "messages": [{"role": "user", "content": text}]
+        }))
+        .unwrap();
+
+        assert!(request.validate_content().is_ok());
+        let messages = request.get_messages_with_system();
+        assert_eq!(messages[0].content.text(), text);
+        assert_eq!(messages[1].content.text(), text);
+    }
+
+    #[test]
+    fn each_turn_extends_the_previous_turns_reasoner_prompt() {
+        let examples: Vec<Message> = serde_json::from_value(json!([
+            {"role": "user", "content": "Example question"},
+            {"role": "assistant", "content": "Example answer"}
+        ]))
+        .unwrap();
+        let turn = |messages: serde_json::Value| {
+            let request: ApiRequest =
+                serde_json::from_value(json!({"system": "Be brief.", "messages": messages})).unwrap();
+            serde_json::to_value(request.with_few_shot_examples(&examples).get_messages_with_system()).unwrap()
+        };
+
+        let first = turn(json!([{"role": "user", "content": "Hi"}]));
+        let second = turn(json!([
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello"},
+            {"role": "user", "content": "More"}
+        ]));
+
+        let (first, second) = (first.as_array().unwrap(), second.as_array().unwrap());
+        assert_eq!(first.len(), 4);
+        assert_eq!(first[..], second[..first.len()]);
+        assert_eq!(first[1]["content"], "Example question");
+    }
+
+    #[test]
+    fn bust_cache_changes_the_prompt_prefix() {
+        let request: ApiRequest =
+            serde_json::from_value(json!({"system": "Be brief.", "messages": [{"role": "user", "content": "Hi"}]}))
+                .unwrap();
+
+        let busted = request.clone().with_cache_nonce().get_messages_with_system();
+
+        assert_ne!(busted[0].content.text(), request.get_messages_with_system()[0].content.text());
+    }
+
+    #[test]
+    fn textually_different_but_equivalent_requests_share_a_fingerprint() {
+        let compact: ApiRequest = serde_json::from_str(
+            r#"{"system":"Be brief.","messages":[{"role":"user","content":"Hi"}],"deepseek_config":{"body":{"model":"deepseek-reasoner","temperature":0.5}},"stream":true}"#,
+        )
+        .unwrap();
+        let reordered: ApiRequest = serde_json::from_str(
+            r#"{
+                "deepseek_config": {
+                    "body": { "temperature": 0.5, "model": "deepseek-reasoner" }
+                },
+                "messages": [
+                    { "role": "system", "content": "  Be brief.\n" },
+                    { "content": "\tHi  ", "role": "user" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(compact.fingerprint(), reordered.fingerprint());
+    }
+
+    #[test]
+    fn each_busted_request_gets_its_own_nonce() {
+        let request: ApiRequest =
+            serde_json::from_value(json!({"system": "Be brief.", "messages": [{"role": "user", "content": "Hi"}]}))
+                .unwrap();
+
+        let first = request.clone().with_cache_nonce().get_system_prompt().unwrap();
+        let second = request.with_cache_nonce().get_system_prompt().unwrap();
+
+        assert!(first.starts_with("Be brief.\n<!-- nonce: "), "{}", first);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn message_count_limit_is_enforced() {
+        let request: ApiRequest = serde_json::from_value(json!({
+            "messages": [
+                {"role": "user", "content": "a"},
+                {"role": "assistant", "content": "b"},
+                {"role": "user", "content": "c"}
+            ]
+        }))
+        .unwrap();
+
+        assert!(request.validate_message_count(3).is_ok());
+        assert!(matches!(request.validate_message_count(2), Err(ApiError::BadRequest { .. })));
+    }
+
+    #[test]
+    fn message_chars_limit_covers_messages_and_the_system_prompt() {
+        let request: ApiRequest = serde_json::from_value(json!({
+            "system": "short",
+            "messages": [{"role": "user", "content": "héllo wörld"}]
+        }))
+        .unwrap();
+
+        assert!(request.validate_message_chars(None).is_ok());
+        assert!(request.validate_message_chars(Some(11)).is_ok());
+        match request.validate_message_chars(Some(10)) {
+            Err(ApiError::BadRequest { message }) => assert!(message.starts_with("messages[0]"), "{}", message),
+            other => panic!("expected BadRequest, got {:?}", other),
+        }
+        match request.validate_message_chars(Some(4)) {
+            Err(ApiError::BadRequest { message }) => assert!(message.starts_with("system"), "{}", message),
+            other => panic!("expected BadRequest, got {:?}", other),
+        }
+    }
+
+    /// Returns the `validate_streamable` error message for a request with
+    /// `fields` added to a single user message, or None if it is accepted.
+    fn streaming_conflict(fields: serde_json::Value) -> Option<String> {
+        let mut body = json!({"messages": [{"role": "user", "content": "hi"}], "stream": true});
+        body.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        let request: ApiRequest = serde_json::from_value(body).unwrap();
+        match request.validate_streamable() {
+            Ok(()) => None,
+            Err(ApiError::BadRequest { message }) => Some(message),
+            Err(other) => panic!("expected BadRequest, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn answer_then_reason_cannot_stream() {
+        let message = streaming_conflict(json!({"pipeline_order": "answer_then_reason"})).unwrap();
+        assert!(message.starts_with("pipeline_order \"answer_then_reason\""), "{}", message);
+    }
+
+    #[test]
+    fn multiple_gemini_candidates_cannot_stream() {
+        let message = streaming_conflict(json!({"gemini_config": {"n": 2}})).unwrap();
+        assert!(message.starts_with("gemini_config.n"), "{}", message);
+        assert!(message.contains("set stream to false"), "{}", message);
+    }
+
+    #[test]
+    fn candidate_count_in_extra_params_cannot_stream() {
+        for key in ["candidateCount", "candidate_count"] {
+            let message = streaming_conflict(json!({"gemini_config": {"extra_params": {key: 3}}})).unwrap();
+            assert!(message.starts_with("gemini_config.extra_params candidate count"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn compatible_options_can_stream() {
+        assert_eq!(streaming_conflict(json!({})), None);
+        assert_eq!(streaming_conflict(json!({"pipeline_order": "reason_then_answer"})), None);
+        assert_eq!(streaming_conflict(json!({"gemini_config": {"n": 1}})), None);
+        assert_eq!(
+            streaming_conflict(json!({"gemini_config": {"extra_params": {"candidateCount": 1}}})),
+            None
+        );
+    }
+}
@@ -3,9 +3,14 @@
 //! This module defines the structures used to represent API responses,
 //! including chat completions, usage statistics, and streaming events.
 
+use crate::{
+    config::{AnswerProvider, CostFormat, DeepSeekModelKind, SymbolPosition},
+    error::ErrorPayload,
+    models::{Message, Role, TokenizeProvider},
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Primary response structure for chat API endpoints.
 ///
@@ -15,16 +20,77 @@ use std::collections::HashMap;
 pub struct ApiResponse {
     pub created: DateTime<Utc>,
     pub content: Vec<ContentBlock>,
+
+    /// Content of every answer candidate, the first being `content`, when
+    /// more than one was requested with `n`. Each shares the same reasoning.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<Vec<ContentBlock>>,
+
+    /// Sources the answer provider cited for the answer.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+
+    /// Raw reasoning text, returned separately when JSON mode keeps it out of `content`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+
+    /// Ready-to-append assistant turn for the client's message history.
+    pub assistant_message: Message,
+
+    /// Word count of the answer, reported when a target length was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_word_count: Option<usize>,
+
+    /// Non-fatal issues with the response, such as overshooting the target length.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+
+    /// True when the answer came from the reasoner without a responder call.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub responder_skipped: bool,
+
+    /// Provider that served the answer: the configured one, or the
+    /// fallback if that failed. Absent when no responder was called.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_provider: Option<AnswerProvider>,
+
+    /// True when reasoning or answer content was cut off at
+    /// `max_response_chars`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
     
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deepseek_response: Option<ExternalApiResponse>,
     
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gemini_response: Option<ExternalApiResponse>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_response: Option<ExternalApiResponse>,
     
     pub combined_usage: CombinedUsage,
 }
 
+/// A source cited by the answer provider.
+///
+/// The indices, when present, delimit the cited span of the answer text.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Citation {
+    pub uri: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_index: Option<u32>,
+}
+
 /// A block of content in a response.
 ///
 /// Represents a single piece of content in the response,
@@ -32,8 +98,61 @@ pub struct ApiResponse {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ContentBlock {
     #[serde(rename = "type")]
-    pub content_type: String,
+    pub content_type: ContentType,
     pub text: String,
+
+    /// Pipeline phase that produced the block, set on the deltas and
+    /// drafts of streams with the `interleaved` policy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ContentKind>,
+}
+
+/// Type of a content block.
+///
+/// Non-streaming responses contain only `text` blocks. Streams use:
+///
+/// - `text` for the complete markers that open and close the reasoning,
+///   `<thinking>\n` and `\n</thinking>` by default (see the
+///   `thinking_render_mode` setting). They are omitted in JSON mode.
+/// - `thinking_delta` for each piece of DeepSeek's reasoning, unless
+///   reasoning is hidden.
+/// - `text_delta` for each piece of the answer.
+/// - `draft` for a complete pipelined draft answer, sent only by streams
+///   with the `interleaved` policy. The final answer supersedes it.
+///
+/// Concatenating the `text` of every block in order, skipping drafts,
+/// reproduces the non-streaming content. To render reasoning and answer separately,
+/// append `thinking_delta` blocks to the reasoning and `text_delta` blocks
+/// to the answer; the markers can be ignored.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    Text,
+    TextDelta,
+    ThinkingDelta,
+    Draft,
+}
+
+/// Pipeline phase of streamed content, reported in `truncated` events and
+/// on the blocks of interleaved streams.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    Reasoning,
+    Answer,
+}
+
+/// Normalized reason the answer model stopped generating.
+///
+/// Provider-specific values (e.g. Gemini's `MAX_TOKENS`) are mapped
+/// onto these variants so clients don't need to special-case providers.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Stop,
+    MaxTokens,
+    Safety,
+    Other,
 }
 
 /// Raw response from an external API.
@@ -47,15 +166,291 @@ pub struct ExternalApiResponse {
     pub body: serde_json::Value,
 }
 
+/// Deterministic fingerprint of a request.
+///
+/// Returned by the debug fingerprint endpoint to help diagnose
+/// why two seemingly identical requests produce different cache keys.
+#[derive(Debug, Serialize, Clone)]
+pub struct FingerprintResponse {
+    pub normalized: String,
+    pub hash: String,
+}
+
+/// Token count of a text, as the server estimates it.
+#[derive(Debug, Serialize, Clone)]
+pub struct TokenizeResponse {
+    pub provider: TokenizeProvider,
+    pub model: String,
+    pub tokens: u32,
+    /// Start and end character offsets of each token, when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boundaries: Option<Vec<[usize; 2]>>,
+}
+
+/// Prompts a chat request would send upstream.
+///
+/// Returned by the debug prompt endpoint, after every server-side
+/// transformation, to diagnose prompt assembly without calling providers.
+#[derive(Debug, Serialize, Clone)]
+pub struct DebugPromptResponse {
+    pub deepseek: UpstreamPrompt,
+    /// Absent when the answer provider would not be called.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<UpstreamPrompt>,
+}
+
+/// Messages and system prompt of one upstream call.
+#[derive(Debug, Serialize, Clone)]
+pub struct UpstreamPrompt {
+    pub provider: String,
+    pub model: String,
+    /// System prompt sent separately from the messages, for providers
+    /// that take it that way.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+/// Token usage and cost of a single provider call.
+///
+/// Provider-agnostic so that the reasoning and answer phases can be
+/// tracked the same way regardless of which provider served them.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ProviderUsage {
+    /// Name of the provider that served the call (e.g. "deepseek", "gemini").
+    pub provider: String,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub reasoning_tokens: u32,
+    pub cached_input_tokens: u32,
+    pub total_tokens: u32,
+    /// Cost of the call in dollars.
+    pub cost: f64,
+    /// Components of `cost`, for DeepSeek calls.
+    pub cost_breakdown: Option<DeepSeekCost>,
+    /// Whether the result was served from the reasoning cache.
+    pub cached: bool,
+    /// Estimated reasoning tokens included in the answer call's input,
+    /// per `reasoning_to_answer`. Only set for answer calls.
+    pub forwarded_reasoning_tokens: Option<u32>,
+    /// Times the call was repeated after an empty answer. The token counts
+    /// and cost cover every attempt.
+    pub retries: u32,
+}
+
+/// Cost of a DeepSeek call in dollars, split by what was billed.
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct DeepSeekCost {
+    /// Input tokens served from DeepSeek's prompt cache.
+    pub cache_hit_cost: f64,
+    /// Input tokens not served from the cache.
+    pub cache_miss_cost: f64,
+    /// Output tokens other than reasoning.
+    pub output_cost: f64,
+    /// Reasoning tokens, billed at the output rate.
+    pub reasoning_cost: f64,
+}
+
+/// Usage and cost estimate returned for dry-run requests.
+///
+/// No provider is called; token counts are approximations.
+#[derive(Debug, Serialize, Clone)]
+pub struct DryRunResponse {
+    /// Always true, marking the body as an estimate.
+    pub dry_run: bool,
+    pub estimated_usage: CombinedUsage,
+}
+
+/// Response body for the batch endpoint.
+#[derive(Debug, Serialize, Clone)]
+pub struct BatchResponse {
+    /// One result per submitted request, in submission order.
+    pub results: Vec<BatchItemResult>,
+    /// Combined cost of all successful requests, formatted.
+    pub total_cost: String,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Outcome of a single request within a batch.
+#[derive(Debug, Serialize, Clone)]
+pub struct BatchItemResult {
+    /// Position of the request in the submitted batch.
+    pub index: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<ApiResponse>,
+
+    /// HTTP status the request would have failed with on its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorPayload>,
+}
+
+/// Response body for the cancel endpoint.
+#[derive(Debug, Serialize, Clone)]
+pub struct CancelResponse {
+    pub cancelled: bool,
+    pub request_id: String,
+}
+
+/// Response body for the admin load endpoint.
+#[derive(Debug, Serialize, Clone)]
+pub struct LoadResponse {
+    /// Chat requests currently being processed, including open streams.
+    pub in_flight: usize,
+    /// Requests waiting for a slot.
+    pub queued: usize,
+    pub max_concurrent_requests: Option<usize>,
+    pub max_queue_depth: usize,
+}
+
+/// Response body for the admin usage endpoint: aggregate usage since startup.
+#[derive(Debug, Serialize, Clone)]
+pub struct UsageSummary {
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: u64,
+    /// Completed requests, streaming and non-streaming.
+    pub total_requests: u64,
+    /// Combined cost of all completed requests, formatted.
+    pub total_cost: String,
+    /// Totals keyed by provider name.
+    pub providers: BTreeMap<String, UsageTotals>,
+    /// Totals keyed by model.
+    pub models: BTreeMap<String, UsageTotals>,
+}
+
+/// Aggregate usage of one provider or model.
+///
+/// `requests` counts the calls that reached it; a request that used the
+/// same provider for both phases counts once per phase.
+#[derive(Debug, Serialize, Clone)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub reasoning_tokens: u64,
+    pub total_tokens: u64,
+    /// Cost in the configured cost format.
+    pub total_cost: String,
+}
+
+/// Models available to the pipeline, as listed by the models endpoint.
+#[derive(Debug, Serialize, Clone)]
+pub struct ModelsResponse {
+    pub models: Vec<ModelInfo>,
+    pub defaults: ModelDefaults,
+}
+
+/// A configured model and its pricing (per million tokens).
+///
+/// `id` is the model name sent upstream. Anthropic models are priced by
+/// family, so their `id` is the family's key in the pricing configuration.
+#[derive(Debug, Serialize, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    pub provider: String,
+    pub role: ModelRole,
+    /// Whether a DeepSeek model streams separate reasoning content.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<DeepSeekModelKind>,
+    pub input_price: f64,
+    pub output_price: f64,
+    pub cache_read_price: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_write_price: Option<f64>,
+}
+
+/// Pipeline phase a model serves.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelRole {
+    Reasoning,
+    Answer,
+}
+
+/// Models used when a request does not select one.
+#[derive(Debug, Serialize, Clone)]
+pub struct ModelDefaults {
+    pub reasoning_model: String,
+    pub answer_provider: String,
+    pub answer_model: String,
+}
+
 /// Combined usage statistics from both AI models.
 ///
-/// Aggregates token usage and cost information from both
-/// DeepSeek and Google API calls.
+/// Aggregates token usage and cost information from the reasoning
+/// and answer phases. Serializes to the flattened per-provider layout
+/// (`deepseek_usage`, `gemini_usage`, ...) that existing clients expect.
 #[derive(Debug, Serialize, Clone)]
+#[serde(into = "CombinedUsageWire")]
 pub struct CombinedUsage {
-    pub total_cost: String,
-    pub deepseek_usage: DeepSeekUsage,
-    pub gemini_usage: GeminiUsage,
+    pub reasoning: ProviderUsage,
+    pub answer: ProviderUsage,
+    /// How costs are rendered when serialized.
+    pub cost_format: CostFormat,
+    /// Latency measurements, recorded for streamed responses and for
+    /// non-streaming requests that set `include_timing`.
+    pub timing: StreamTiming,
+}
+
+/// Server-side latency measurements of a response.
+///
+/// All fields are omitted when not measured, so clients that don't know
+/// about them can ignore them.
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct StreamTiming {
+    /// Milliseconds from the request to the first DeepSeek reasoning delta.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_to_first_reasoning_ms: Option<u64>,
+
+    /// Milliseconds from the request to the first answer content delta.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_to_first_answer_ms: Option<u64>,
+
+    /// Answer output tokens per second, from the first answer delta to completion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_tokens_per_sec: Option<f64>,
+
+    /// Milliseconds spent in the DeepSeek call, including short-reasoning retries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_latency_ms: Option<u64>,
+
+    /// Milliseconds spent in the answer provider call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gemini_latency_ms: Option<u64>,
+}
+
+/// Serialized form of `CombinedUsage`.
+#[derive(Serialize)]
+struct CombinedUsageWire {
+    total_cost: String,
+
+    /// Input tokens of both phases. Each provider counts with its own
+    /// tokenizer, so this is a rough volume figure for dashboards; the
+    /// per-provider blocks have the exact counts.
+    total_input_tokens: u32,
+
+    /// Output tokens of both phases, including DeepSeek's reasoning tokens.
+    total_output_tokens: u32,
+
+    deepseek_usage: DeepSeekUsage,
+
+    /// Present when Gemini served the answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gemini_usage: Option<GeminiUsage>,
+
+    /// Present when Anthropic served the answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anthropic_usage: Option<AnthropicUsage>,
+
+    /// Fraction of DeepSeek prompt tokens served from cache (0.0 to 1.0).
+    deepseek_cache_hit_rate: f32,
+
+    #[serde(flatten)]
+    timing: StreamTiming,
 }
 
 /// Usage statistics for DeepSeek API calls.
@@ -70,6 +465,23 @@ pub struct DeepSeekUsage {
     pub cached_input_tokens: u32,
     pub total_tokens: u32,
     pub total_cost: String,
+
+    /// Formatted components of `total_cost`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_breakdown: Option<DeepSeekCostBreakdown>,
+
+    /// True when the reasoning was reused from the cache instead of calling DeepSeek.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+/// Formatted components of a DeepSeek call's cost.
+#[derive(Debug, Serialize, Clone)]
+pub struct DeepSeekCostBreakdown {
+    pub cache_hit_cost: String,
+    pub cache_miss_cost: String,
+    pub output_cost: String,
+    pub reasoning_cost: String,
 }
 
 /// Usage statistics for Gemini API calls.
@@ -82,6 +494,35 @@ pub struct GeminiUsage {
     pub output_tokens: u32,
     pub total_tokens: u32,
     pub total_cost: String,
+
+    /// Estimated reasoning tokens forwarded in the input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_reasoning_tokens: Option<u32>,
+
+    /// Retries after an empty answer, included in the counts above.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retries: u32,
+}
+
+/// Usage statistics for Anthropic API calls.
+///
+/// Tracks token consumption and costs specific to
+/// Claude model usage.
+#[derive(Debug, Serialize, Clone)]
+pub struct AnthropicUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cached_input_tokens: u32,
+    pub total_tokens: u32,
+    pub total_cost: String,
+
+    /// Estimated reasoning tokens forwarded in the input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_reasoning_tokens: Option<u32>,
+
+    /// Retries after an empty answer, included in the counts above.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retries: u32,
 }
 
 // Streaming event types
@@ -90,12 +531,17 @@ pub struct GeminiUsage {
 /// Represents different types of events that can occur
 /// during a streaming response, including content updates
 /// and usage statistics.
+///
+/// With `minimal_events`, only `content`, `usage`, and `error` events are
+/// sent; the stream closing marks the end of the response.
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum StreamEvent {
     #[serde(rename = "start")]
     Start {
         created: DateTime<Utc>,
+        /// ID to pass to `POST /v1/cancel/{request_id}` to stop the stream.
+        request_id: String,
     },
     
     #[serde(rename = "content")]
@@ -106,15 +552,88 @@ pub enum StreamEvent {
     #[serde(rename = "usage")]
     Usage {
         usage: CombinedUsage,
+
+        /// True for running estimates sent while DeepSeek is still reasoning,
+        /// and for the final usage when DeepSeek never reported its own
+        /// and the reasoning figures are estimated from the streamed text
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        estimated: bool,
+    },
+
+    /// DeepSeek's usage, sent once reasoning ends and before the answer
+    /// phase, so the reasoning cost is known even if the answer fails.
+    /// The combined `usage` event still follows at the end.
+    #[serde(rename = "reasoning_usage")]
+    ReasoningUsage {
+        deepseek_usage: DeepSeekUsage,
+
+        /// True when DeepSeek didn't report usage before the reasoning
+        /// ended and the figures are estimated from the streamed text
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        estimated: bool,
     },
     
+    /// Sources cited by the answer provider, sent as they arrive.
+    #[serde(rename = "citations")]
+    Citations {
+        citations: Vec<Citation>,
+    },
+
+    /// Content of one kind reached its `max_response_chars` limit; no more
+    /// of it is sent. The stream still ends with `usage` and `done`.
+    #[serde(rename = "truncated")]
+    Truncated {
+        kind: ContentKind,
+        max_chars: usize,
+    },
+
+    /// The full answer as a single block, sent before `done` when the
+    /// request sets `stream_final_message`.
+    #[serde(rename = "final_message")]
+    FinalMessage {
+        content: Vec<ContentBlock>,
+    },
+
     #[serde(rename = "done")]
-    Done,
+    Done {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        finish_reason: Option<FinishReason>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        answer_word_count: Option<usize>,
+
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<String>,
+
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        responder_skipped: bool,
+
+        /// Provider that served the answer, as in non-streaming responses.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        answer_provider: Option<AnswerProvider>,
+
+        /// Draft answers completed during reasoning with `pipelined_answer`.
+        #[serde(skip_serializing_if = "is_zero")]
+        pipelined_drafts: u32,
+    },
     
+    /// The stream was stopped through the cancel endpoint.
+    #[serde(rename = "cancelled")]
+    Cancelled {},
+
+    /// A failure that ends the stream. `error` has the same shape as the
+    /// body of a non-streaming error response.
     #[serde(rename = "error")]
     Error {
-        message: String,
-        code: u16,
+        error: ErrorPayload,
+        /// HTTP-style status code describing the error.
+        status: u16,
+        /// True when part of the answer was streamed before the error.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        partial: bool,
+        /// Characters of answer content delivered before the error.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        delivered_chars: Option<usize>,
     },
 }
 
@@ -130,24 +649,89 @@ impl ContentBlock {
     /// A new `ContentBlock` with the type set to "text"
     pub fn text(text: impl Into<String>) -> Self {
         Self {
-            content_type: "text".to_string(),
+            content_type: ContentType::Text,
             text: text.into(),
+            kind: None,
         }
     }
 
-    /// Converts an Google content block to a generic content block.
+    /// Creates a new streaming delta block.
     ///
     /// # Arguments
     ///
-    /// * `block` - The Google-specific content block to convert
+    /// * `content_type` - The block type, `TextDelta` or `ThinkingDelta`
+    /// * `text` - The delta text
     ///
     /// # Returns
     ///
-    /// A new `ContentBlock` with the same content type and text
-    pub fn from_gemini(block: crate::clients::gemini::ContentBlock) -> Self {
+    /// A new `ContentBlock` carrying the delta
+    pub fn delta(content_type: ContentType, text: impl Into<String>) -> Self {
         Self {
-            content_type: block.content_type,
-            text: block.text,
+            content_type,
+            text: text.into(),
+            kind: None,
+        }
+    }
+
+    /// Returns the block tagged with the pipeline phase that produced it.
+    pub fn with_kind(mut self, kind: Option<ContentKind>) -> Self {
+        self.kind = kind;
+        self
+    }
+}
+
+impl FinishReason {
+    /// Maps a Gemini finish reason string to the normalized enum.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The raw finish reason reported by Gemini
+    ///
+    /// # Returns
+    ///
+    /// The corresponding `FinishReason`, or `Other` for unknown values
+    pub fn from_gemini(reason: &str) -> Self {
+        match reason.to_ascii_uppercase().as_str() {
+            "STOP" => FinishReason::Stop,
+            "MAX_TOKENS" => FinishReason::MaxTokens,
+            "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => FinishReason::Safety,
+            _ => FinishReason::Other,
+        }
+    }
+
+    /// Maps a DeepSeek (OpenAI-style) finish reason string to the normalized enum.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The raw finish reason reported by DeepSeek
+    ///
+    /// # Returns
+    ///
+    /// The corresponding `FinishReason`, or `Other` for unknown values
+    pub fn from_deepseek(reason: &str) -> Self {
+        match reason {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::MaxTokens,
+            "content_filter" => FinishReason::Safety,
+            _ => FinishReason::Other,
+        }
+    }
+
+    /// Maps an Anthropic stop reason string to the normalized enum.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The raw stop reason reported by Anthropic
+    ///
+    /// # Returns
+    ///
+    /// The corresponding `FinishReason`, or `Other` for unknown values
+    pub fn from_anthropic(reason: &str) -> Self {
+        match reason {
+            "end_turn" | "stop_sequence" => FinishReason::Stop,
+            "max_tokens" => FinishReason::MaxTokens,
+            "refusal" => FinishReason::Safety,
+            _ => FinishReason::Other,
         }
     }
 }
@@ -164,48 +748,285 @@ impl ApiResponse {
     /// A new `ApiResponse` with default values and the provided content
     #[allow(dead_code)]
     pub fn new(content: impl Into<String>) -> Self {
+        let content = content.into();
         Self {
             created: Utc::now(),
-            content: vec![ContentBlock::text(content)],
+            content: vec![ContentBlock::text(content.clone())],
+            candidates: Vec::new(),
+            citations: Vec::new(),
+            reasoning: None,
+            finish_reason: None,
+            answer_word_count: None,
+            warnings: Vec::new(),
+            responder_skipped: false,
+            answer_provider: None,
+            truncated: false,
+            assistant_message: Message {
+                role: Role::Assistant,
+                content: content.into(),
+            },
             deepseek_response: None,
+            gemini_response: None,
             anthropic_response: None,
             combined_usage: CombinedUsage {
-                total_cost: "$0.00".to_string(),
-                deepseek_usage: DeepSeekUsage {
-                    input_tokens: 0,
-                    output_tokens: 0,
-                    reasoning_tokens: 0,
-                    cached_input_tokens: 0,
-                    total_tokens: 0,
-                    total_cost: "$0.00".to_string(),
-                },
-                gemini_usage: GeminiUsage {
-                    input_tokens: 0,
-                    output_tokens: 0,
-                    total_tokens: 0,
-                    total_cost: "$0.00".to_string(),
-                },
+                reasoning: ProviderUsage::new("deepseek"),
+                answer: ProviderUsage::new("gemini"),
+                cost_format: CostFormat::default(),
+                timing: StreamTiming::default(),
             },
         }
     }
 }
 
-impl GeminiUsage {
-    /// Converts Gemini usage statistics to the generic usage format.
+impl ProviderUsage {
+    /// Creates an empty usage record for the given provider.
     ///
     /// # Arguments
     ///
-    /// * `response` - The Gemini response containing usage information
+    /// * `provider` - Name of the provider that served the call
+    ///
+    /// # Returns
+    ///
+    /// A new `ProviderUsage` with zero tokens and cost
+    pub fn new(provider: impl Into<String>) -> Self {
+        Self {
+            provider: provider.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl DeepSeekCost {
+    /// Returns the sum of all components in dollars.
+    pub fn total(&self) -> f64 {
+        self.cache_hit_cost + self.cache_miss_cost + self.output_cost + self.reasoning_cost
+    }
+
+    /// Formats each component according to the configured currency format.
+    pub fn format(&self, format: &CostFormat) -> DeepSeekCostBreakdown {
+        DeepSeekCostBreakdown {
+            cache_hit_cost: format_cost(self.cache_hit_cost, format),
+            cache_miss_cost: format_cost(self.cache_miss_cost, format),
+            output_cost: format_cost(self.output_cost, format),
+            reasoning_cost: format_cost(self.reasoning_cost, format),
+        }
+    }
+}
+
+impl CombinedUsage {
+    /// Returns the total cost of both phases in dollars.
+    pub fn total_cost(&self) -> f64 {
+        self.reasoning.cost + self.answer.cost
+    }
+
+    /// Returns the input tokens of both phases, summed across providers.
+    pub fn total_input_tokens(&self) -> u32 {
+        self.reasoning.input_tokens.saturating_add(self.answer.input_tokens)
+    }
+
+    /// Returns the output tokens of both phases, summed across providers.
+    pub fn total_output_tokens(&self) -> u32 {
+        self.reasoning.output_tokens.saturating_add(self.answer.output_tokens)
+    }
+}
+
+impl From<CombinedUsage> for CombinedUsageWire {
+    fn from(usage: CombinedUsage) -> Self {
+        let format = &usage.cost_format;
+        let total_cost = format_cost(usage.total_cost(), format);
+        let deepseek_usage = DeepSeekUsage::from_provider(&usage.reasoning, format);
+        let answer = &usage.answer;
+
+        Self {
+            total_cost,
+            total_input_tokens: usage.total_input_tokens(),
+            total_output_tokens: usage.total_output_tokens(),
+            deepseek_cache_hit_rate: deepseek_usage.cache_hit_rate(),
+            deepseek_usage,
+            gemini_usage: (answer.provider == "gemini")
+                .then(|| GeminiUsage::from_provider(answer, format)),
+            anthropic_usage: (answer.provider == "anthropic")
+                .then(|| AnthropicUsage::from_provider(answer, format)),
+            timing: usage.timing,
+        }
+    }
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Formats a cost value according to the configured currency format.
+///
+/// # Arguments
+///
+/// * `cost` - The cost value in dollars
+/// * `format` - Currency symbol, placement, precision, and exchange rate
+///
+/// # Returns
+///
+/// A string representing the converted cost, e.g. `$0.012` with the defaults
+pub fn format_cost(cost: f64, format: &CostFormat) -> String {
+    let amount = format!("{:.*}", format.decimal_places, cost * format.exchange_rate);
+    match format.position {
+        SymbolPosition::Prefix => format!("{}{}", format.symbol, amount),
+        SymbolPosition::Suffix => format!("{}{}", amount, format.symbol),
+    }
+}
+
+impl DeepSeekUsage {
+    /// Converts a provider-agnostic usage record to the DeepSeek layout.
+    pub fn from_provider(usage: &ProviderUsage, format: &CostFormat) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            reasoning_tokens: usage.reasoning_tokens,
+            cached_input_tokens: usage.cached_input_tokens,
+            total_tokens: usage.total_tokens,
+            total_cost: format_cost(usage.cost, format),
+            cost_breakdown: usage.cost_breakdown.map(|cost| cost.format(format)),
+            cached: usage.cached,
+        }
+    }
+
+    /// Returns the fraction of input tokens served from DeepSeek's prompt cache.
     ///
     /// # Returns
     ///
-    /// A new `GeminiUsage` with values from the Gemini response
-    pub fn from_gemini(response: &crate::clients::gemini::GeminiResponse) -> Self {
+    /// `cached_input_tokens / input_tokens`, or 0.0 for an empty prompt
+    pub fn cache_hit_rate(&self) -> f32 {
+        if self.input_tokens == 0 {
+            0.0
+        } else {
+            self.cached_input_tokens as f32 / self.input_tokens as f32
+        }
+    }
+}
+
+impl AnthropicUsage {
+    /// Converts a provider-agnostic usage record to the Anthropic layout.
+    pub fn from_provider(usage: &ProviderUsage, format: &CostFormat) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cached_input_tokens: usage.cached_input_tokens,
+            total_tokens: usage.total_tokens,
+            total_cost: format_cost(usage.cost, format),
+            forwarded_reasoning_tokens: usage.forwarded_reasoning_tokens,
+            retries: usage.retries,
+        }
+    }
+}
+
+impl GeminiUsage {
+    /// Converts a provider-agnostic usage record to the Gemini layout.
+    pub fn from_provider(usage: &ProviderUsage, format: &CostFormat) -> Self {
         Self {
-            input_tokens: response.usage.as_ref().map(|u| u.prompt_tokens).unwrap_or(0),
-            output_tokens: response.usage.as_ref().map(|u| u.completion_tokens).unwrap_or(0),
-            total_tokens: response.usage.as_ref().map(|u| u.total_tokens).unwrap_or(0),
-            total_cost: "$0.00".to_string(), // Cost will be calculated later
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+            total_cost: format_cost(usage.cost, format),
+            forwarded_reasoning_tokens: usage.forwarded_reasoning_tokens,
+            retries: usage.retries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Returns combined usage with the given answer provider and fixed token counts.
+    fn usage(answer_provider: &str) -> CombinedUsage {
+        CombinedUsage {
+            reasoning: ProviderUsage {
+                input_tokens: 100,
+                output_tokens: 50,
+                cached_input_tokens: 25,
+                total_tokens: 150,
+                cost: 0.002,
+                ..ProviderUsage::new("deepseek")
+            },
+            answer: ProviderUsage {
+                input_tokens: 200,
+                output_tokens: 40,
+                total_tokens: 240,
+                cost: 0.001,
+                ..ProviderUsage::new(answer_provider)
+            },
+            cost_format: CostFormat::default(),
+            timing: StreamTiming::default(),
         }
     }
+
+    #[test]
+    fn combined_usage_serializes_to_the_flattened_gemini_layout() {
+        let value = serde_json::to_value(usage("gemini")).unwrap();
+
+        assert_eq!(value["total_cost"], json!("$0.003"));
+        assert_eq!(value["total_input_tokens"], json!(300));
+        assert_eq!(value["total_output_tokens"], json!(90));
+        assert_eq!(value["deepseek_usage"]["input_tokens"], json!(100));
+        assert_eq!(value["deepseek_usage"]["total_cost"], json!("$0.002"));
+        assert_eq!(value["deepseek_cache_hit_rate"], json!(0.25));
+        assert_eq!(value["gemini_usage"]["output_tokens"], json!(40));
+        assert_eq!(value["gemini_usage"]["total_cost"], json!("$0.001"));
+        assert!(value.get("anthropic_usage").is_none());
+        assert!(value.get("reasoning").is_none());
+    }
+
+    #[test]
+    fn cache_hit_rate_is_the_cached_share_of_the_prompt() {
+        let rate = |input_tokens, cached_input_tokens| {
+            let usage = ProviderUsage { input_tokens, cached_input_tokens, ..ProviderUsage::new("deepseek") };
+            DeepSeekUsage::from_provider(&usage, &CostFormat::default()).cache_hit_rate()
+        };
+
+        assert_eq!(rate(200, 50), 0.25);
+        assert_eq!(rate(200, 200), 1.0);
+        assert_eq!(rate(200, 0), 0.0);
+        assert_eq!(rate(0, 0), 0.0);
+    }
+
+    #[test]
+    fn an_empty_prompt_reports_a_zero_cache_hit_rate() {
+        let mut usage = usage("gemini");
+        usage.reasoning.input_tokens = 0;
+        usage.reasoning.cached_input_tokens = 0;
+
+        let value = serde_json::to_value(usage).unwrap();
+
+        assert_eq!(value["deepseek_cache_hit_rate"], json!(0.0));
+    }
+
+    #[test]
+    fn combined_usage_reports_an_anthropic_answer_under_anthropic_usage() {
+        let value = serde_json::to_value(usage("anthropic")).unwrap();
+
+        assert_eq!(value["anthropic_usage"]["input_tokens"], json!(200));
+        assert!(value.get("gemini_usage").is_none());
+    }
+
+    #[test]
+    fn deepseek_cost_breakdown_is_formatted_per_component() {
+        let cost = DeepSeekCost {
+            cache_hit_cost: 0.028,
+            cache_miss_cost: 0.44,
+            output_cost: 0.876,
+            reasoning_cost: 1.314,
+        };
+        let mut combined = usage("gemini");
+        combined.reasoning.cost = cost.total();
+        combined.reasoning.cost_breakdown = Some(cost);
+
+        let value = serde_json::to_value(combined).unwrap();
+
+        let deepseek = &value["deepseek_usage"];
+        assert_eq!(deepseek["total_cost"], json!("$2.658"));
+        assert_eq!(deepseek["cost_breakdown"]["cache_hit_cost"], json!("$0.028"));
+        assert_eq!(deepseek["cost_breakdown"]["cache_miss_cost"], json!("$0.440"));
+        assert_eq!(deepseek["cost_breakdown"]["output_cost"], json!("$0.876"));
+        assert_eq!(deepseek["cost_breakdown"]["reasoning_cost"], json!("$1.314"));
+    }
 }
@@ -0,0 +1,87 @@
+//! Pre-dispatch screening of chat inputs.
+//!
+//! Requests are checked against configured keywords and regular
+//! expressions before any upstream call, so blocked inputs cost nothing.
+//! All matching is case-insensitive.
+
+use crate::{
+    config::ModerationConfig,
+    error::{ApiError, Result},
+    models::ApiRequest,
+};
+use regex::{Regex, RegexBuilder};
+
+/// Compiled moderation rules.
+#[derive(Debug)]
+pub struct ModerationFilter {
+    keywords: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl ModerationFilter {
+    /// Compiles the rules from configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The moderation configuration
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self>` - The compiled filter
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::Internal` if a pattern is not a valid regex.
+    pub fn new(config: &ModerationConfig) -> Result<Self> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| ApiError::Internal {
+                        message: format!("Invalid moderation pattern {:?}: {}", pattern, e),
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            keywords: config
+                .keywords
+                .iter()
+                .filter(|keyword| !keyword.is_empty())
+                .map(|keyword| keyword.to_lowercase())
+                .collect(),
+            patterns,
+        })
+    }
+
+    /// Checks every message in a request against the rules.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The chat request to screen
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::ContentRejected` naming the first rule that matched.
+    pub fn check(&self, request: &ApiRequest) -> Result<()> {
+        for (index, message) in request.messages.iter().enumerate() {
+            let text = message.content.text();
+            let lowercase = text.to_lowercase();
+
+            if let Some(keyword) = self.keywords.iter().find(|k| lowercase.contains(k.as_str())) {
+                return Err(ApiError::ContentRejected {
+                    reason: format!("messages[{}] contains blocked keyword \"{}\"", index, keyword),
+                });
+            }
+            if let Some(pattern) = self.patterns.iter().find(|p| p.is_match(&text)) {
+                return Err(ApiError::ContentRejected {
+                    reason: format!("messages[{}] matches blocked pattern \"{}\"", index, pattern.as_str()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Buffers of streamed events, so a client that loses its connection can
+//! resume a stream instead of re-running it.
+//!
+//! Every event of a resumable stream is recorded with a sequence number and
+//! sent with the SSE id `{request_id}:{seq}`. A client reconnecting with
+//! `Last-Event-ID` is replayed the events after that id, then follows the
+//! stream live. The upstream calls keep running while no client is attached,
+//! so a reconnect never starts a second, separately billed run.
+//!
+//! Only a request carrying the credential that started a stream may resume
+//! it; to anyone else the stream doesn't exist.
+//!
+//! Memory is bounded by `max_streams` buffers of at most
+//! `max_events_per_stream` events each. A buffer that overflows drops its
+//! oldest events, which can then no longer be resumed from. Finished streams
+//! are evicted `ttl` after their last event; when the registry is full, the
+//! least recently active finished stream is evicted first, and a new stream
+//! is not buffered if every slot belongs to a live stream.
+
+use crate::error::SseResult;
+use axum::response::sse::Event;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, Notify};
+
+/// A single recorded event.
+#[derive(Debug, Clone)]
+struct BufferedEvent {
+    seq: u64,
+    name: String,
+    data: String,
+}
+
+/// Mutable state of one stream's buffer.
+#[derive(Debug)]
+struct BufferState {
+    events: VecDeque<BufferedEvent>,
+    next_seq: u64,
+    finished: bool,
+    last_activity: Instant,
+}
+
+/// The recorded events of one stream.
+#[derive(Debug)]
+pub struct StreamBuffer {
+    request_id: String,
+    /// Hash of the credential that started the stream.
+    owner: Option<String>,
+    max_events: usize,
+    state: Mutex<BufferState>,
+    notify: Notify,
+}
+
+impl StreamBuffer {
+    /// Records an event and wakes any resumed readers.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The SSE id to send with the event
+    pub fn push(&self, name: &str, data: &str) -> String {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.last_activity = Instant::now();
+        if state.events.len() >= self.max_events {
+            state.events.pop_front();
+        }
+        state.events.push_back(BufferedEvent {
+            seq,
+            name: name.to_string(),
+            data: data.to_string(),
+        });
+        drop(state);
+
+        self.notify.notify_waiters();
+        event_id(&self.request_id, seq)
+    }
+
+    /// Marks the stream as complete, so readers stop once they catch up.
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.finished = true;
+        state.last_activity = Instant::now();
+        drop(state);
+
+        self.notify.notify_waiters();
+    }
+
+    /// Returns the buffered events after `after`, and whether the stream has finished.
+    ///
+    /// Returns None if events after `after` were already dropped from the buffer.
+    fn events_after(&self, after: u64) -> Option<(Vec<BufferedEvent>, bool)> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.events.front().is_some_and(|first| first.seq > after + 1) {
+            return None;
+        }
+        let events = state
+            .events
+            .iter()
+            .filter(|event| event.seq > after)
+            .cloned()
+            .collect();
+        Some((events, state.finished))
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.finished && state.last_activity.elapsed() > ttl
+    }
+}
+
+/// Why a stream could not be resumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeError {
+    /// The `Last-Event-ID` is not a resumable event id.
+    InvalidId,
+    /// The stream is unknown, its buffer has expired, or it was started
+    /// with another credential.
+    UnknownStream,
+    /// Events after the given id were dropped from the buffer.
+    EventsDropped,
+}
+
+/// Registry of stream buffers, keyed by request ID.
+#[derive(Debug)]
+pub struct ReplayRegistry {
+    streams: Mutex<HashMap<String, Arc<StreamBuffer>>>,
+    ttl: Duration,
+    max_streams: usize,
+    max_events_per_stream: usize,
+}
+
+impl ReplayRegistry {
+    /// Creates an empty registry.
+    ///
+    /// # Arguments
+    ///
+    /// * `ttl` - How long a finished stream stays resumable
+    /// * `max_streams` - Maximum number of buffered streams
+    /// * `max_events_per_stream` - Maximum events kept per stream
+    pub fn new(ttl: Duration, max_streams: usize, max_events_per_stream: usize) -> Self {
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            ttl,
+            max_streams,
+            max_events_per_stream: max_events_per_stream.max(1),
+        }
+    }
+
+    /// Creates a buffer for a new stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - ID of the stream
+    /// * `owner` - Hash of the credential that started the stream
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<StreamBuffer>>` - The buffer, or None if every slot is
+    ///   held by a live stream and this stream won't be resumable
+    pub fn register(&self, request_id: &str, owner: Option<String>) -> Option<Arc<StreamBuffer>> {
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        streams.retain(|_, buffer| !buffer.is_expired(self.ttl));
+
+        if streams.len() >= self.max_streams {
+            // Make room by evicting the least recently active finished stream
+            let oldest = streams
+                .iter()
+                .filter_map(|(id, buffer)| {
+                    let state = buffer.state.lock().unwrap_or_else(|e| e.into_inner());
+                    state.finished.then_some((state.last_activity, id.clone()))
+                })
+                .min()
+                .map(|(_, id)| id);
+            match oldest {
+                Some(id) => {
+                    streams.remove(&id);
+                }
+                None => {
+                    tracing::warn!("Replay buffer full, stream {} will not be resumable", request_id);
+                    return None;
+                }
+            }
+        }
+
+        let buffer = Arc::new(StreamBuffer {
+            request_id: request_id.to_string(),
+            owner,
+            max_events: self.max_events_per_stream,
+            state: Mutex::new(BufferState {
+                events: VecDeque::new(),
+                next_seq: 0,
+                finished: false,
+                last_activity: Instant::now(),
+            }),
+            notify: Notify::new(),
+        });
+        streams.insert(request_id.to_string(), buffer.clone());
+        Some(buffer)
+    }
+
+    /// Replays a stream from just after `last_event_id` and then follows it live.
+    ///
+    /// # Arguments
+    ///
+    /// * `last_event_id` - The `Last-Event-ID` sent by the reconnecting client
+    /// * `owner` - Hash of the reconnecting client's credential
+    /// * `channel_buffer` - Capacity of the channel feeding the new response
+    ///
+    /// # Returns
+    ///
+    /// * `Result<mpsc::Receiver<SseResult>, ResumeError>` - The events for the new response
+    pub fn resume(
+        &self,
+        last_event_id: &str,
+        owner: Option<&str>,
+        channel_buffer: usize,
+    ) -> std::result::Result<mpsc::Receiver<SseResult>, ResumeError> {
+        let (request_id, seq) = parse_event_id(last_event_id).ok_or(ResumeError::InvalidId)?;
+        let buffer = {
+            let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+            streams.retain(|_, buffer| !buffer.is_expired(self.ttl));
+            streams
+                .get(request_id)
+                .filter(|buffer| buffer.owner.as_deref() == owner)
+                .cloned()
+                .ok_or(ResumeError::UnknownStream)?
+        };
+        if buffer.events_after(seq).is_none() {
+            return Err(ResumeError::EventsDropped);
+        }
+
+        let (tx, rx) = mpsc::channel(channel_buffer.max(1));
+        tokio::spawn(async move {
+            let mut after = seq;
+            loop {
+                // Register for wakeups before reading, so no push is missed
+                let notified = buffer.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+
+                let Some((events, finished)) = buffer.events_after(after) else {
+                    // The reader fell behind far enough that events were dropped
+                    return;
+                };
+                for event in events {
+                    after = event.seq;
+                    let sse = Event::default()
+                        .id(event_id(&buffer.request_id, event.seq))
+                        .event(event.name)
+                        .data(event.data);
+                    if tx.send(Ok(sse)).await.is_err() {
+                        return;
+                    }
+                }
+                if finished {
+                    return;
+                }
+                notified.await;
+            }
+        });
+        Ok(rx)
+    }
+}
+
+/// Formats the SSE id of an event.
+fn event_id(request_id: &str, seq: u64) -> String {
+    format!("{}:{}", request_id, seq)
+}
+
+/// Splits an SSE id into its request ID and sequence number.
+fn parse_event_id(id: &str) -> Option<(&str, u64)> {
+    let (request_id, seq) = id.rsplit_once(':')?;
+    Some((request_id, seq.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a registry with a finished stream of two events owned by `owner`.
+    fn registry_with_stream(owner: Option<&str>) -> ReplayRegistry {
+        let registry = ReplayRegistry::new(Duration::from_secs(60), 4, 16);
+        let buffer = registry.register("req_1", owner.map(str::to_string)).unwrap();
+        buffer.push("start", "{}");
+        buffer.push("content", "{}");
+        buffer.finish();
+        registry
+    }
+
+    #[tokio::test]
+    async fn the_owning_credential_can_resume() {
+        let registry = registry_with_stream(Some("owner"));
+
+        let mut rx = registry.resume("req_1:0", Some("owner"), 4).unwrap();
+
+        assert!(rx.recv().await.is_some());
+    }
+
+    #[test]
+    fn another_credential_cannot_resume() {
+        let registry = registry_with_stream(Some("owner"));
+
+        assert!(matches!(registry.resume("req_1:0", Some("intruder"), 4), Err(ResumeError::UnknownStream)));
+        assert!(matches!(registry.resume("req_1:0", None, 4), Err(ResumeError::UnknownStream)));
+    }
+}
@@ -0,0 +1,161 @@
+//! Startup self-test.
+//!
+//! Run with `--self-test` or `server.self_test_on_start = true` to refuse to
+//! start a misconfigured instance. The configuration is always checked;
+//! providers are additionally called with a one-token request when a server
+//! token is available in the environment, unless they are mocked.
+
+use crate::{
+    clients::{anthropic, deepseek, gemini, pinning::ProviderHttp, AnswerClient, DeepSeekClient},
+    config::{AnswerProvider, Config, ModelPricing, ProviderMode},
+    models::{ApiConfig, Message, Role},
+};
+
+/// Command-line flag that runs the self-test at startup.
+pub const SELF_TEST_FLAG: &str = "--self-test";
+
+/// Environment variable holding the DeepSeek token used for the provider check.
+const DEEPSEEK_TOKEN_ENV: &str = "DEEPSEEK_API_TOKEN";
+
+/// Returns the environment variable holding the answer provider's token.
+fn answer_token_env(provider: AnswerProvider) -> &'static str {
+    match provider {
+        AnswerProvider::Gemini => "GEMINI_API_TOKEN",
+        AnswerProvider::Anthropic => "ANTHROPIC_API_TOKEN",
+    }
+}
+
+/// Runs the self-test.
+///
+/// # Arguments
+///
+/// * `config` - The loaded configuration
+/// * `http` - The HTTP clients provider requests will use
+///
+/// # Errors
+///
+/// Returns an error listing every failed check
+pub async fn run(config: &Config, http: ProviderHttp) -> anyhow::Result<()> {
+    let mut failures = check_config(config);
+    failures.extend(check_providers(config, http).await);
+
+    if failures.is_empty() {
+        tracing::info!("Self-test passed");
+        return Ok(());
+    }
+    for failure in &failures {
+        tracing::error!("Self-test failed: {}", failure);
+    }
+    anyhow::bail!("Self-test failed:\n  - {}", failures.join("\n  - "))
+}
+
+/// Validates the pricing table and default models.
+///
+/// # Returns
+///
+/// * `Vec<String>` - A description of each problem found
+fn check_config(config: &Config) -> Vec<String> {
+    let mut failures = Vec::new();
+    let pricing = &config.pricing;
+
+    let (cache_hit, cache_miss, output) = pricing.deepseek.rates(deepseek::DEFAULT_MODEL);
+    for (name, price) in [
+        ("input_cache_hit_price", cache_hit),
+        ("input_cache_miss_price", cache_miss),
+        ("output_price", output),
+    ] {
+        if !is_valid_price(price) {
+            failures.push(format!("DeepSeek {} for {} is {}", name, deepseek::DEFAULT_MODEL, price));
+        }
+    }
+    if pricing.deepseek.max_output_tokens(deepseek::DEFAULT_MODEL) == Some(0) {
+        failures.push(format!("DeepSeek max_output_tokens for {} is 0", deepseek::DEFAULT_MODEL));
+    }
+
+    check_model_pricing(&mut failures, gemini::DEFAULT_MODEL, &pricing.gemini.gemini_pro);
+    check_model_pricing(
+        &mut failures,
+        anthropic::DEFAULT_MODEL,
+        pricing.anthropic.for_model(anthropic::DEFAULT_MODEL),
+    );
+
+    if !config.deepseek.models.allows(deepseek::DEFAULT_MODEL) {
+        failures.push(format!("Default DeepSeek model {} is not allowed", deepseek::DEFAULT_MODEL));
+    }
+    if !config.gemini.models.allows(gemini::DEFAULT_MODEL) {
+        failures.push(format!("Default Gemini model {} is not allowed", gemini::DEFAULT_MODEL));
+    }
+
+    failures
+}
+
+/// Records a failure for each missing or invalid price of an answer model.
+fn check_model_pricing(failures: &mut Vec<String>, model: &str, pricing: &ModelPricing) {
+    for (name, price) in [
+        ("input_price", pricing.input_price),
+        ("output_price", pricing.output_price),
+        ("cache_write_price", pricing.cache_write_price),
+        ("cache_read_price", pricing.cache_read_price),
+    ] {
+        if !is_valid_price(price) {
+            failures.push(format!("{} for {} is {}", name, model, price));
+        }
+    }
+    if pricing.max_output_tokens == Some(0) {
+        failures.push(format!("max_output_tokens for {} is 0", model));
+    }
+}
+
+/// Returns true if a configured price is usable.
+fn is_valid_price(price: f64) -> bool {
+    price.is_finite() && price >= 0.0
+}
+
+/// Sends a one-token request to each provider that has a server token.
+///
+/// # Returns
+///
+/// * `Vec<String>` - A description of each failed call
+async fn check_providers(config: &Config, http: ProviderHttp) -> Vec<String> {
+    let mut failures = Vec::new();
+    if config.pipeline.provider_mode == ProviderMode::Mock {
+        tracing::info!("Providers are mocked, skipping provider checks");
+        return failures;
+    }
+    let messages = vec![Message {
+        role: Role::User,
+        content: "ping".to_string().into(),
+    }];
+    let request_config = ApiConfig {
+        body: serde_json::json!({ "max_tokens": 1 }),
+        ..Default::default()
+    };
+
+    match std::env::var(DEEPSEEK_TOKEN_ENV) {
+        Ok(token) => {
+            let client = DeepSeekClient::with_http_client(token, http.deepseek.clone())
+                .with_base_url(config.deepseek.base_url.as_deref());
+            if let Err(e) = client.chat(messages.clone(), &request_config).await {
+                failures.push(format!("DeepSeek call failed: {}", e));
+            }
+        }
+        Err(_) => tracing::info!("{} not set, skipping DeepSeek check", DEEPSEEK_TOKEN_ENV),
+    }
+
+    let provider = config.pipeline.answer_provider;
+    match std::env::var(answer_token_env(provider)) {
+        Ok(token) => {
+            let client = AnswerClient::new(provider, token, config, http.answer(provider));
+            if let Err(e) = client.chat(messages, None, &request_config).await {
+                failures.push(format!("{} call failed: {}", provider.name(), e));
+            }
+        }
+        Err(_) => tracing::info!(
+            "{} not set, skipping {} check",
+            answer_token_env(provider),
+            provider.name()
+        ),
+    }
+
+    failures
+}
@@ -0,0 +1,137 @@
+//! Logging and distributed tracing.
+//!
+//! Spans are always logged locally. Built with the `otel` feature and with
+//! `telemetry.otlp_endpoint` set, they are also exported to an OTLP
+//! collector, and requests carrying a W3C `traceparent` header join the
+//! caller's trace.
+//!
+//! Each request gets a `request` span, with a child span per upstream call
+//! recording the model, token counts, and cost.
+
+use crate::{config::TelemetryConfig, models::ProviderUsage};
+use tracing::{field::Empty, Span};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Installs the global subscriber, with an OTLP exporter if configured.
+///
+/// # Errors
+///
+/// Returns an error if the OTLP exporter cannot be created
+pub fn init(config: &TelemetryConfig) -> anyhow::Result<()> {
+    let registry = tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "deepclaude=debug,tower_http=debug".into()),
+        )
+        .with(tracing_subscriber::fmt::layer());
+
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &config.otlp_endpoint {
+        registry
+            .with(tracing_opentelemetry::layer().with_tracer(otel::tracer(endpoint)?))
+            .init();
+        return Ok(());
+    }
+
+    registry.init();
+    #[cfg(not(feature = "otel"))]
+    if config.otlp_endpoint.is_some() {
+        tracing::warn!("telemetry.otlp_endpoint is set, but this build lacks the otel feature; spans are not exported");
+    }
+    Ok(())
+}
+
+/// Flushes spans not yet exported. Call before the process exits.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Creates the span of an incoming request, joining the caller's trace
+/// when the request carries a `traceparent` header.
+pub fn request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        url.path = %request.uri().path(),
+    );
+    #[cfg(feature = "otel")]
+    otel::set_parent(&span, request.headers());
+    span
+}
+
+/// Creates the span of a call to an upstream provider.
+///
+/// Token counts and cost are filled in with `record_usage` once known.
+///
+/// # Arguments
+///
+/// * `provider` - Name of the provider, e.g. "deepseek"
+/// * `model` - Model the call is made to
+pub fn upstream_span(provider: &str, model: &str) -> Span {
+    tracing::info_span!(
+        "upstream_call",
+        otel.name = %format!("chat {}", model),
+        otel.kind = "client",
+        gen_ai.system = %provider,
+        gen_ai.request.model = %model,
+        gen_ai.usage.input_tokens = Empty,
+        gen_ai.usage.output_tokens = Empty,
+        cost = Empty,
+    )
+}
+
+/// Records an upstream call's token counts and cost on its span.
+pub fn record_usage(span: &Span, usage: &ProviderUsage) {
+    span.record("gen_ai.usage.input_tokens", usage.input_tokens);
+    span.record("gen_ai.usage.output_tokens", usage.output_tokens);
+    span.record("cost", usage.cost);
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::{propagation::Extractor, trace::TracerProvider as _, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace::Tracer, Resource};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    /// Builds a tracer exporting to the OTLP collector at `endpoint` and
+    /// installs it, with W3C trace context propagation, globally.
+    pub fn tracer(endpoint: &str) -> anyhow::Result<Tracer> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", env!("CARGO_PKG_NAME"))]))
+            .build();
+        let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+        opentelemetry::global::set_tracer_provider(provider);
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        Ok(tracer)
+    }
+
+    /// Makes the trace context in `headers`, if any, the parent of `span`.
+    pub fn set_parent(span: &tracing::Span, headers: &axum::http::HeaderMap) {
+        let context = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(headers))
+        });
+        span.set_parent(context);
+    }
+
+    /// Reads propagation headers from a request.
+    struct HeaderExtractor<'a>(&'a axum::http::HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|key| key.as_str()).collect()
+        }
+    }
+}
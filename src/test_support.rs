@@ -0,0 +1,230 @@
+//! Helpers shared by the unit tests.
+//!
+//! Handlers are called directly with state built from a test config.
+//! Mock mode serves canned provider output; tests that need a provider
+//! to misbehave point its base URL at a local server instead.
+
+use crate::{
+    abuse::AbuseMonitor,
+    cache::ReasoningCache,
+    clients::pinning::ProviderHttp,
+    config::{Config, ProviderMode},
+    handlers::AppState,
+    limiter::RequestLimiter,
+    models::{Message, Role},
+    moderation::ModerationFilter,
+    replay::ReplayRegistry,
+    usage::UsageStats,
+};
+use axum::{
+    body::to_bytes,
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Returns a config that serves canned output from the mock providers
+/// without delays.
+pub fn mock_config() -> Config {
+    let mut config = Config::default();
+    config.pipeline.provider_mode = ProviderMode::Mock;
+    config.mock.chunk_delay_ms = 0;
+    config
+}
+
+/// Builds application state from `config` the way `main` does.
+pub fn state(config: Config) -> Arc<AppState> {
+    Arc::new(AppState {
+        shutdown: CancellationToken::new(),
+        request_limiter: RequestLimiter::new(
+            config.server.max_concurrent_requests,
+            config.server.max_queue_depth,
+            Duration::from_millis(config.server.queue_timeout_ms),
+        ),
+        reasoning_cache: NonZeroUsize::new(config.pipeline.reasoning_cache_size).map(|size| {
+            ReasoningCache::new(
+                size,
+                config.pipeline.reasoning_cache_ttl_secs.map(Duration::from_secs),
+            )
+        }),
+        http: ProviderHttp::from_config(&config).unwrap(),
+        active_streams: Default::default(),
+        moderation: ModerationFilter::new(&config.moderation).unwrap(),
+        replay: config.resume.enabled.then(|| {
+            ReplayRegistry::new(
+                Duration::from_secs(config.resume.ttl_secs),
+                config.resume.max_streams,
+                config.resume.max_events_per_stream,
+            )
+        }),
+        usage_sink: None,
+        usage_stats: UsageStats::default(),
+        abuse_monitor: config
+            .abuse
+            .enabled
+            .then(|| AbuseMonitor::new(Duration::from_secs(config.abuse.window_secs))),
+        config: RwLock::new(Arc::new(config)),
+    })
+}
+
+/// Upstream calls received by fake providers, as (provider, request body)
+/// pairs in arrival order.
+pub type CallLog = Arc<Mutex<Vec<(&'static str, Value)>>>;
+
+/// Returns a config with live providers whose DeepSeek and Gemini base URLs
+/// point at the given fakes.
+pub fn live_config(deepseek_url: &str, gemini_url: &str) -> Config {
+    let mut config = Config::default();
+    config.deepseek.base_url = Some(deepseek_url.to_string());
+    config.gemini.base_url = Some(gemini_url.to_string());
+    config
+}
+
+/// Returns headers carrying the DeepSeek and Gemini API tokens.
+pub fn token_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("X-DeepSeek-API-Token", HeaderValue::from_static("deepseek-token"));
+    headers.insert("X-Gemini-API-Token", HeaderValue::from_static("gemini-token"));
+    headers
+}
+
+/// Serves a fake provider that records every request body in `log` under
+/// `name` and answers each with `response`.
+pub async fn fake_provider(name: &'static str, response: Value, log: CallLog) -> String {
+    serve(Router::new().fallback(move |Json(body): Json<Value>| {
+        let (response, log) = (response.clone(), log.clone());
+        async move {
+            log.lock().unwrap().push((name, body));
+            Json(response)
+        }
+    }))
+    .await
+}
+
+/// Serves a fake provider like `fake_provider` that answers with each of
+/// `responses` in turn, repeating the last one.
+pub async fn sequenced_provider(name: &'static str, responses: Vec<Value>, log: CallLog) -> String {
+    serve(Router::new().fallback(move |Json(body): Json<Value>| {
+        let (responses, log) = (responses.clone(), log.clone());
+        async move {
+            let mut calls = log.lock().unwrap();
+            let made = calls.iter().filter(|(called, _)| *called == name).count();
+            calls.push((name, body));
+            Json(responses[made.min(responses.len() - 1)].clone())
+        }
+    }))
+    .await
+}
+
+/// Returns a DeepSeek chat completion with the given content and reasoning.
+pub fn deepseek_body(content: &str, reasoning: Option<&str>) -> Value {
+    json!({
+        "id": "resp",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "deepseek-reasoner",
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content, "reasoning_content": reasoning},
+            "logprobs": null,
+            "finish_reason": "stop"
+        }],
+        "usage": {
+            "prompt_tokens": 10,
+            "completion_tokens": 20,
+            "total_tokens": 30,
+            "prompt_tokens_details": {"cached_tokens": 0},
+            "completion_tokens_details": {"reasoning_tokens": 15}
+        },
+        "system_fingerprint": "fp"
+    })
+}
+
+/// Serves a fake provider that streams `events` as SSE `data:` lines to
+/// every request.
+pub async fn streaming_provider(events: Vec<Value>) -> String {
+    let body: String = events.iter().map(|event| format!("data: {}\n\n", event)).collect();
+    serve(Router::new().fallback(move || {
+        let body = body.clone();
+        async move { ([(axum::http::header::CONTENT_TYPE, "text/event-stream")], body) }
+    }))
+    .await
+}
+
+/// Returns a DeepSeek stream chunk with the given delta fields.
+pub fn deepseek_chunk(reasoning: Option<&str>, content: Option<&str>, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": "resp",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "deepseek-reasoner",
+        "choices": [{
+            "index": 0,
+            "delta": {"role": "assistant", "content": content, "reasoning_content": reasoning},
+            "logprobs": null,
+            "finish_reason": finish_reason
+        }],
+        "usage": null,
+        "system_fingerprint": "fp"
+    })
+}
+
+/// Returns a Gemini `generateContent` response with the given answer.
+pub fn gemini_body(text: &str) -> Value {
+    json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [{"text": text}]},
+            "finishReason": "STOP"
+        }],
+        "usageMetadata": {"promptTokenCount": 5, "candidatesTokenCount": 3, "totalTokenCount": 8}
+    })
+}
+
+/// Serves `router` on a local port and returns its base URL.
+pub async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{}", addr)
+}
+
+/// Returns a conversation of a single user message.
+pub fn user_message(text: &str) -> Vec<Message> {
+    vec![Message {
+        role: Role::User,
+        content: text.to_string().into(),
+    }]
+}
+
+/// Reads a response body as JSON.
+pub async fn json_body(response: Response) -> serde_json::Value {
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Reads an SSE response body as (event name, JSON data) pairs.
+pub async fn sse_events(response: Response) -> Vec<(String, serde_json::Value)> {
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    body.split("\n\n")
+        .filter_map(|frame| {
+            let mut name = String::from("message");
+            let mut data = None;
+            for line in frame.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    name = value.trim().to_string();
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data = serde_json::from_str(value.trim()).ok();
+                }
+            }
+            Some((name, data?))
+        })
+        .collect()
+}
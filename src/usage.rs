@@ -0,0 +1,283 @@
+//! Durable usage records for billing and analytics.
+//!
+//! Every completed request produces one `UsageRecord`, written through the
+//! configured `UsageSink` as a JSON line. Sinks hand records to a background
+//! writer task, so recording never blocks a request; a record that can't be
+//! queued or written is logged and dropped rather than failing the request.
+//!
+//! Records are also folded into in-memory `UsageStats`, served by the admin
+//! usage endpoint.
+
+use crate::{
+    config::{CostFormat, UsageLogConfig, UsageSinkKind},
+    models::{format_cost, ProviderUsage, UsageSummary, UsageTotals},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+
+/// Records queued per sink before new records are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Usage of one completed request.
+#[derive(Debug, Serialize, Clone)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: String,
+    pub stream: bool,
+    pub reasoning_model: String,
+    pub answer_model: String,
+    /// Token counts and cost of the reasoning phase.
+    pub reasoning: ProviderUsage,
+    /// Token counts and cost of the answer phase.
+    pub answer: ProviderUsage,
+    /// Combined cost of both phases in dollars.
+    pub total_cost: f64,
+    /// Milliseconds from the request to its completion.
+    pub latency_ms: u64,
+}
+
+/// Destination for usage records.
+pub trait UsageSink: Send + Sync {
+    /// Queues a record for writing. Must not block or fail the caller.
+    fn record(&self, record: UsageRecord);
+}
+
+/// Appends records to a JSONL file.
+pub struct JsonlFileSink {
+    queue: mpsc::Sender<UsageRecord>,
+}
+
+impl JsonlFileSink {
+    /// Opens `path` for appending, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            queue: spawn_writer(tokio::fs::File::from_std(file)),
+        })
+    }
+}
+
+impl UsageSink for JsonlFileSink {
+    fn record(&self, record: UsageRecord) {
+        enqueue(&self.queue, record);
+    }
+}
+
+/// Writes records to stdout, one JSON line each.
+pub struct StdoutSink {
+    queue: mpsc::Sender<UsageRecord>,
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self {
+            queue: spawn_writer(tokio::io::stdout()),
+        }
+    }
+}
+
+impl UsageSink for StdoutSink {
+    fn record(&self, record: UsageRecord) {
+        enqueue(&self.queue, record);
+    }
+}
+
+/// Creates the sink selected in configuration.
+///
+/// # Returns
+///
+/// * `std::io::Result<Option<Box<dyn UsageSink>>>` - The sink, or None if
+///   usage records are disabled
+///
+/// # Errors
+///
+/// Returns an error if the usage file cannot be opened
+pub fn from_config(config: &UsageLogConfig) -> std::io::Result<Option<Box<dyn UsageSink>>> {
+    Ok(match config.sink {
+        UsageSinkKind::None => None,
+        UsageSinkKind::Stdout => Some(Box::new(StdoutSink::default())),
+        UsageSinkKind::File => Some(Box::new(JsonlFileSink::open(&config.path)?)),
+    })
+}
+
+/// Queues a record without waiting, dropping it if the writer is behind.
+fn enqueue(queue: &mpsc::Sender<UsageRecord>, record: UsageRecord) {
+    if let Err(e) = queue.try_send(record) {
+        tracing::warn!("Dropping usage record: {}", e);
+    }
+}
+
+/// Spawns a task that writes queued records to `writer` as JSON lines.
+fn spawn_writer<W>(mut writer: W) -> mpsc::Sender<UsageRecord>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel::<UsageRecord>(QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(record) = rx.recv().await {
+            let mut line = match serde_json::to_vec(&record) {
+                Ok(line) => line,
+                Err(e) => {
+                    tracing::warn!("Failed to serialize usage record {}: {}", record.request_id, e);
+                    continue;
+                }
+            };
+            line.push(b'\n');
+            if let Err(e) = writer.write_all(&line).await {
+                tracing::warn!("Failed to write usage record {}: {}", record.request_id, e);
+                continue;
+            }
+            if let Err(e) = writer.flush().await {
+                tracing::warn!("Failed to flush usage record {}: {}", record.request_id, e);
+            }
+        }
+    });
+    tx
+}
+
+/// Running totals of one provider or model.
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    input_tokens: AtomicU64,
+    output_tokens: AtomicU64,
+    reasoning_tokens: AtomicU64,
+    total_tokens: AtomicU64,
+    /// Cost in dollars, stored as `f64` bits.
+    cost: AtomicU64,
+}
+
+impl Counters {
+    fn add(&self, usage: &ProviderUsage) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.input_tokens.fetch_add(usage.input_tokens.into(), Ordering::Relaxed);
+        self.output_tokens.fetch_add(usage.output_tokens.into(), Ordering::Relaxed);
+        self.reasoning_tokens.fetch_add(usage.reasoning_tokens.into(), Ordering::Relaxed);
+        self.total_tokens.fetch_add(usage.total_tokens.into(), Ordering::Relaxed);
+        add_cost(&self.cost, usage.cost);
+    }
+
+    fn cost(&self) -> f64 {
+        f64::from_bits(self.cost.load(Ordering::Relaxed))
+    }
+
+    fn totals(&self, format: &CostFormat) -> UsageTotals {
+        UsageTotals {
+            requests: self.requests.load(Ordering::Relaxed),
+            input_tokens: self.input_tokens.load(Ordering::Relaxed),
+            output_tokens: self.output_tokens.load(Ordering::Relaxed),
+            reasoning_tokens: self.reasoning_tokens.load(Ordering::Relaxed),
+            total_tokens: self.total_tokens.load(Ordering::Relaxed),
+            total_cost: format_cost(self.cost(), format),
+        }
+    }
+}
+
+/// Atomically adds `amount` to a cost stored as `f64` bits.
+fn add_cost(cost: &AtomicU64, amount: f64) {
+    let _ = cost.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((f64::from_bits(bits) + amount).to_bits())
+    });
+}
+
+/// Aggregate usage since startup.
+///
+/// Counters are atomic, so concurrent requests update them without a lock;
+/// the maps are only write-locked the first time a provider or model is seen.
+#[derive(Debug)]
+pub struct UsageStats {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    requests: AtomicU64,
+    cost: AtomicU64,
+    providers: RwLock<HashMap<String, Arc<Counters>>>,
+    models: RwLock<HashMap<String, Arc<Counters>>>,
+}
+
+impl Default for UsageStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: Utc::now(),
+            requests: AtomicU64::new(0),
+            cost: AtomicU64::new(0.0f64.to_bits()),
+            providers: Default::default(),
+            models: Default::default(),
+        }
+    }
+}
+
+impl UsageStats {
+    /// Adds a completed request to the totals.
+    pub fn add(&self, record: &UsageRecord) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        add_cost(&self.cost, record.total_cost);
+
+        for (usage, model) in [
+            (&record.reasoning, &record.reasoning_model),
+            (&record.answer, &record.answer_model),
+        ] {
+            // A skipped responder made no call
+            if usage.total_tokens == 0 && usage.cost == 0.0 {
+                continue;
+            }
+            counters(&self.providers, &usage.provider).add(usage);
+            counters(&self.models, model).add(usage);
+        }
+    }
+
+    /// Returns a snapshot of the totals.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - How costs are rendered
+    pub fn summary(&self, format: &CostFormat) -> UsageSummary {
+        let totals = |map: &RwLock<HashMap<String, Arc<Counters>>>| {
+            map.read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .map(|(key, counters)| (key.clone(), counters.totals(format)))
+                .collect()
+        };
+
+        UsageSummary {
+            started_at: self.started_at,
+            uptime_secs: self.started.elapsed().as_secs(),
+            total_requests: self.requests.load(Ordering::Relaxed),
+            total_cost: format_cost(f64::from_bits(self.cost.load(Ordering::Relaxed)), format),
+            providers: totals(&self.providers),
+            models: totals(&self.models),
+        }
+    }
+}
+
+/// Returns the counters for `key`, inserting them on first use.
+fn counters(map: &RwLock<HashMap<String, Arc<Counters>>>, key: &str) -> Arc<Counters> {
+    if let Some(counters) = map.read().unwrap_or_else(|e| e.into_inner()).get(key) {
+        return counters.clone();
+    }
+    map.write()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(key.to_string())
+        .or_default()
+        .clone()
+}